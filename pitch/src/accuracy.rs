@@ -0,0 +1,138 @@
+use crate::analysis::{PitchFrame, Voicing};
+
+/// Pitch a transposed signal is expected to have
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum PitchReference {
+    /// the frequency, in Hz, of a note held all along
+    Note(f32),
+    /// the pitch of a reference signal, e.g the input of the transposer,
+    /// shifted by the given number of semi tons (0 for an oracle signal)
+    Shift(f32),
+}
+
+/// Deviation of the pitch of a signal from the expected one, over its voiced
+/// frames
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct PitchAccuracy {
+    pub mean: f32,     // mean absolute deviation, in cents
+    pub max: f32,      // largest absolute deviation, in cents
+    pub frames: usize, // number of frames compared
+}
+
+impl PitchAccuracy {
+
+    /// Returns true if the mean deviation is at most `threshold` cents
+    pub fn passes(&self, threshold: f32) -> bool {
+        self.mean <= threshold
+    }
+}
+
+/// Returns the deviation, in cents, of `frequency` from `expected`
+pub fn cents(frequency: f32, expected: f32) -> f32 {
+    1200.0 * (frequency / expected).log2()
+}
+
+/// Returns the accuracy of the pitch of `track` against `reference`. Only
+/// the frames voiced for `voicing` are compared and, for a shift, those whose
+/// frame of the same index in `reference_track` is voiced for
+/// `reference_voicing`. None if no frame is compared.
+pub fn pitch_accuracy(
+    track: &[PitchFrame],
+    voicing: &Voicing,
+    reference: PitchReference,
+    reference_track: &[PitchFrame],
+    reference_voicing: &Voicing
+) -> Option<PitchAccuracy> {
+    let deviations: Vec<f32> = track
+        .iter()
+        .enumerate()
+        .filter(|(_, f)| voicing.is_voiced(f))
+        .filter_map(|(i, f)| {
+            let expected = match reference {
+                PitchReference::Note(frequency) => frequency,
+                PitchReference::Shift(semitones) => {
+                    let r = reference_track.get(i)?;
+                    if !reference_voicing.is_voiced(r) {
+                        return None;
+                    }
+                    r.frequency * 2.0f32.powf(semitones / 12.0)
+                },
+            };
+            Some(cents(f.frequency, expected).abs())
+        })
+        .collect();
+    if deviations.is_empty() {
+        return None;
+    }
+    Some(PitchAccuracy {
+        mean: deviations.iter().sum::<f32>() / deviations.len() as f32,
+        max: deviations.iter().fold(0.0f32, |max, d| max.max(*d)),
+        frames: deviations.len(),
+    })
+}
+
+#[cfg(test)]
+mod accuracy_tests {
+    use super::*;
+    use crate::analysis::pitch_track;
+    use crate::generate::Signal;
+
+    #[test]
+    fn cents_between_frequencies() {
+        assert_eq!(0.0, cents(440.0, 440.0));
+        assert!((cents(880.0, 440.0) - 1200.0).abs() < 1e-3);
+        assert!((cents(440.0 * 2.0f32.powf(-1.0 / 12.0), 440.0) + 100.0).abs() < 1e-3);
+    }
+
+    #[test]
+    fn accuracy_against_note() {
+        let voicing = Voicing::new(1.0);
+        let sine = Signal::Sine { frequency: 445.0 }
+            .synthesize(8000.0, 2.0, 1.0)
+            .unwrap();
+        let track = pitch_track(&sine, 2048, 512, 8000.0);
+        let accuracy =
+            pitch_accuracy(&track, &voicing, PitchReference::Note(440.0), &[], &voicing)
+                .unwrap();
+        // 445 Hz is 19.6 cents above A4
+        assert!((accuracy.mean - 19.6).abs() < 1.0, "{:?}", accuracy);
+        // a steady tone
+        assert!((accuracy.max - accuracy.mean).abs() < 1.0);
+        assert_eq!(track.len(), accuracy.frames);
+        assert!(accuracy.passes(25.0) && !accuracy.passes(10.0));
+    }
+
+    #[test]
+    fn accuracy_against_shifted_reference() {
+        let voicing = Voicing::new(1.0);
+        let a4 = Signal::Sine { frequency: 440.0 }.synthesize(8000.0, 2.0, 1.0).unwrap();
+        let e5 = Signal::Sine { frequency: 660.0 }.synthesize(8000.0, 2.0, 1.0).unwrap();
+        let input = pitch_track(&a4, 2048, 512, 8000.0);
+        let output = pitch_track(&e5, 2048, 512, 8000.0);
+        // a fifth above is 7.02 semi tons
+        let fifth = PitchReference::Shift(7.0);
+        let accuracy =
+            pitch_accuracy(&output, &voicing, fifth, &input, &voicing).unwrap();
+        assert!((accuracy.mean - 2.0).abs() < 1.0, "{:?}", accuracy);
+        // against itself
+        let oracle = PitchReference::Shift(0.0);
+        let accuracy =
+            pitch_accuracy(&input, &voicing, oracle, &input, &voicing).unwrap();
+        assert_eq!(0.0, accuracy.max);
+    }
+
+    #[test]
+    fn silent_frames_are_not_compared() {
+        let voicing = Voicing::new(1.0);
+        let mut signal =
+            Signal::Sine { frequency: 440.0 }.synthesize(8000.0, 1.024, 1.0).unwrap();
+        signal.extend(vec![0.0; 8192]);
+        let track = pitch_track(&signal, 2048, 512, 8000.0);
+        let note = PitchReference::Note(440.0);
+        let accuracy = pitch_accuracy(&track, &voicing, note, &[], &voicing).unwrap();
+        assert!(accuracy.frames < track.len() / 2 + 2);
+        assert!(accuracy.max < 10.0, "{:?}", accuracy);
+        let silence = pitch_track(&vec![0.0; 8192], 2048, 512, 8000.0);
+        assert_eq!(None, pitch_accuracy(&silence, &voicing, note, &[], &voicing));
+    }
+}
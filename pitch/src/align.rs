@@ -0,0 +1,235 @@
+use clap::ValueEnum;
+use rustfft::{num_complex::Complex, FftPlanner};
+use std::f64::consts::PI;
+
+/// Zero crossings of the windowed sinc on each side of a resampled sample
+const RESAMPLING_TAPS: usize = 16;
+
+/// How signals of different lengths are compared
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq)]
+pub enum LengthPolicy {
+    /// compares the samples common to both signals, dropping the end of the
+    /// longest one
+    Trim,
+    /// pads the shortest signal with silence
+    Pad,
+    /// refuses to compare signals of different lengths
+    Strict,
+}
+
+impl LengthPolicy {
+
+    /// Brings `signal` and `oracle` to the same length
+    pub fn apply(
+        &self,
+        signal: &mut Vec<f32>,
+        oracle: &mut Vec<f32>
+    ) -> Result<(), &'static str> {
+        match self {
+            LengthPolicy::Trim => {
+                let len = signal.len().min(oracle.len());
+                signal.truncate(len);
+                oracle.truncate(len);
+            },
+            LengthPolicy::Pad => {
+                let len = signal.len().max(oracle.len());
+                signal.resize(len, 0.0);
+                oracle.resize(len, 0.0);
+            },
+            LengthPolicy::Strict if signal.len() != oracle.len() => {
+                return Err("Can not compare signals of different length");
+            },
+            LengthPolicy::Strict => (),
+        }
+        Ok(())
+    }
+}
+
+/// Returns the delay, in samples, of `signal` relative to `oracle`, between
+/// `-max_lag` and `max_lag`: the lag maximizing their cross-correlation,
+/// computed in the frequency domain. It is positive if `signal` starts
+/// later than `oracle`.
+pub fn estimate_lag(signal: &[f32], oracle: &[f32], max_lag: usize) -> isize {
+    let len = (signal.len() + oracle.len()).max(1).next_power_of_two();
+    let mut planner = FftPlanner::new();
+    let spectrum = |x: &[f32], planner: &mut FftPlanner<f32>| {
+        let mut buffer: Vec<Complex<f32>> =
+            x.iter().map(|&s| Complex::new(s, 0.0)).collect();
+        buffer.resize(len, Complex::new(0.0, 0.0));
+        planner.plan_fft_forward(len).process(&mut buffer);
+        buffer
+    };
+    let s = spectrum(signal, &mut planner);
+    let o = spectrum(oracle, &mut planner);
+    // correlation[k] = sum of signal[n + k] * oracle[n], negative lags wrapped
+    let mut correlation: Vec<Complex<f32>> =
+        s.iter().zip(&o).map(|(s, o)| s * o.conj()).collect();
+    planner.plan_fft_inverse(len).process(&mut correlation);
+
+    let max_lag = max_lag.min(len / 2) as isize;
+    let value = |lag: isize| correlation[lag.rem_euclid(len as isize) as usize].re;
+    (-max_lag..=max_lag)
+        .fold((0, f32::NEG_INFINITY), |(best, max), lag| {
+            let v = value(lag);
+            if v > max { (lag, v) } else { (best, max) }
+        })
+        .0
+}
+
+/// Returns `signal` and `oracle` with the first samples of the one starting
+/// later dropped, `signal` being `lag` samples late (see `estimate_lag()`)
+pub fn align(signal: &[f32], oracle: &[f32], lag: isize) -> (Vec<f32>, Vec<f32>) {
+    let skip = |x: &[f32], n: isize| x[(n.max(0) as usize).min(x.len())..].to_vec();
+    (skip(signal, lag), skip(oracle, -lag))
+}
+
+/// Returns the value at `x` samples of the Hann windowed sinc of half width
+/// `width` samples
+fn windowed_sinc(x: f64, width: f64) -> f64 {
+    if x == 0.0 {
+        return 1.0;
+    }
+    if x.abs() >= width {
+        return 0.0;
+    }
+    let window = 0.5 * (1.0 + (PI * x / width).cos());
+    window * (PI * x).sin() / (PI * x)
+}
+
+/// Returns `signal`, sampled at `from` Hz, resampled at `to` Hz by windowed
+/// sinc interpolation. When downsampling, frequencies above the new Nyquist
+/// frequency are filtered out.
+pub fn resample(signal: &[f32], from: f32, to: f32) -> Vec<f32> {
+    if from == to || signal.is_empty() {
+        return signal.to_vec();
+    }
+    let ratio = to as f64 / from as f64;
+    let len = (signal.len() as f64 * ratio).round() as usize;
+    // cut-off frequency relative to the input Nyquist frequency
+    let cutoff = ratio.min(1.0);
+    let width = RESAMPLING_TAPS as f64 / cutoff;
+    (0..len)
+        .map(|n| {
+            let t = n as f64 / ratio;
+            let first = (t - width).ceil().max(0.0) as usize;
+            let last = ((t + width).floor() as usize).min(signal.len() - 1);
+            let value: f64 = (first..=last)
+                .map(|k| {
+                    let x = t - k as f64;
+                    signal[k] as f64 * cutoff * windowed_sinc(cutoff * x, cutoff * width)
+                })
+                .sum();
+            value as f32
+        })
+        .collect()
+}
+
+/// Returns `signal`, of `channels` interleaved channels sampled at `from` Hz,
+/// resampled at `to` Hz channel by channel (see `resample()`)
+pub fn resample_channels(
+    signal: &[f32],
+    channels: usize,
+    from: f32,
+    to: f32
+) -> Vec<f32> {
+    if channels <= 1 {
+        return resample(signal, from, to);
+    }
+    let resampled: Vec<Vec<f32>> = (0..channels)
+        .map(|c| {
+            let channel: Vec<f32> =
+                signal.iter().skip(c).step_by(channels).copied().collect();
+            resample(&channel, from, to)
+        })
+        .collect();
+    let len = resampled.iter().map(Vec::len).min().unwrap_or(0);
+    (0..len).flat_map(|n| resampled.iter().map(move |c| c[n])).collect()
+}
+
+#[cfg(test)]
+mod align_tests {
+    use super::*;
+    use crate::generate::Signal;
+
+    #[test]
+    fn length_policies() {
+        let (mut s, mut o) = (vec![1.0, 2.0, 3.0], vec![4.0]);
+        LengthPolicy::Trim.apply(&mut s, &mut o).unwrap();
+        assert_eq!((vec![1.0], vec![4.0]), (s, o));
+        let (mut s, mut o) = (vec![1.0], vec![4.0, 5.0]);
+        LengthPolicy::Pad.apply(&mut s, &mut o).unwrap();
+        assert_eq!((vec![1.0, 0.0], vec![4.0, 5.0]), (s, o));
+        let (mut s, mut o) = (vec![1.0], vec![4.0, 5.0]);
+        assert!(LengthPolicy::Strict.apply(&mut s, &mut o).is_err());
+        let (mut s, mut o) = (vec![1.0], vec![4.0]);
+        assert!(LengthPolicy::Strict.apply(&mut s, &mut o).is_ok());
+    }
+
+    #[test]
+    fn lag_of_delayed_signal() {
+        let oracle = Signal::WhiteNoise { seed: 7 }.synthesize(8000.0, 0.5, 0.5).unwrap();
+        let mut late = vec![0.0; 123];
+        late.extend_from_slice(&oracle[..3900]);
+        assert_eq!(123, estimate_lag(&late, &oracle, 500));
+        assert_eq!(-123, estimate_lag(&oracle, &late, 500));
+        // beyond the largest lag searched
+        assert_ne!(123, estimate_lag(&late, &oracle, 100));
+        let (s, o) = align(&late, &oracle, 123);
+        assert_eq!(s[..100], o[..100]);
+        let (o, s) = align(&oracle, &late, -123);
+        assert_eq!(s[..100], o[..100]);
+    }
+
+    #[test]
+    fn resample_sine() {
+        let sine = Signal::Sine { frequency: 440.0 };
+        let signal = sine.synthesize(8000.0, 1.0, 1.0).unwrap();
+        let upsampled = resample(&signal, 8000.0, 44100.0);
+        assert_eq!(44100, upsampled.len());
+        let expected = sine.synthesize(44100.0, 1.0, 1.0).unwrap();
+        // away from the edges
+        for (u, e) in upsampled[1000..43000].iter().zip(&expected[1000..43000]) {
+            assert!((u - e).abs() < 0.01, "{} {}", u, e);
+        }
+        let downsampled = resample(&expected, 44100.0, 8000.0);
+        assert_eq!(8000, downsampled.len());
+        for (d, s) in downsampled[100..7900].iter().zip(&signal[100..7900]) {
+            assert!((d - s).abs() < 0.01, "{} {}", d, s);
+        }
+        assert_eq!(signal, resample(&signal, 8000.0, 8000.0));
+    }
+
+    #[test]
+    fn downsampling_removes_frequencies_above_nyquist() {
+        // 6 kHz can not be represented at 8 kHz and would alias to 2 kHz
+        let signal = Signal::Sine { frequency: 6000.0 }
+            .synthesize(44100.0, 1.0, 1.0)
+            .unwrap();
+        let downsampled = resample(&signal, 44100.0, 8000.0);
+        let rms = (downsampled[100..7900].iter().map(|s| s * s).sum::<f32>() / 7800.0)
+            .sqrt();
+        assert!(rms < 0.01, "{}", rms);
+    }
+
+    #[test]
+    fn resample_each_channel() {
+        let left = Signal::Sine { frequency: 440.0 }
+            .synthesize(8000.0, 1.0, 1.0)
+            .unwrap();
+        let right = Signal::Sine { frequency: 660.0 }
+            .synthesize(8000.0, 1.0, 1.0)
+            .unwrap();
+        let stereo: Vec<f32> =
+            left.iter().zip(&right).flat_map(|(l, r)| [*l, *r]).collect();
+        let upsampled = resample_channels(&stereo, 2, 8000.0, 44100.0);
+        assert_eq!(2 * 44100, upsampled.len());
+        let expected: Vec<f32> = resample(&left, 8000.0, 44100.0)
+            .into_iter()
+            .zip(resample(&right, 8000.0, 44100.0))
+            .flat_map(|(l, r)| [l, r])
+            .collect();
+        assert_eq!(expected, upsampled);
+        let mono = resample_channels(&left, 1, 8000.0, 44100.0);
+        assert_eq!(resample(&left, 8000.0, 44100.0), mono);
+    }
+}
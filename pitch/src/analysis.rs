@@ -0,0 +1,537 @@
+/// Pitch track smoothing and octave error correction module
+pub mod smoothing;
+
+/// Vibrato separation module
+pub mod vibrato;
+
+/// Pre-processing of the signal before pitch detection module
+pub mod preprocess;
+
+use std::cmp::min;
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use rustfft::num_complex::Complex;
+use serde::Serialize;
+use crate::midi::smf::Note;
+use crate::{frame_slices, get_main_frequency, norm, parallelized_analysis};
+
+/// What the pitch detector saw in an analysis frame
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct PitchFrame {
+    pub time: f64,               // time of the middle of the frame, in seconds
+    pub frequency: f32,          // main frequency in Hz, 0 if none was found
+    pub rms: f32,                // root mean square of the frame amplitudes
+    pub periodicity: f32,        // from 0 (noise) to 1 (periodic)
+    pub zero_crossing_rate: f32, // zero crossings per second
+}
+
+/// Level, in dBFS, under which frames are silent by default
+pub const DEFAULT_SILENCE: f32 = -50.0;
+
+/// Voicing confidence from which frames are voiced by default
+pub const DEFAULT_VOICING_THRESHOLD: f32 = 0.5;
+
+/// Level range, in dB above silence, over which the voicing confidence grows
+/// from 0 to 1
+const LEVEL_RANGE: f32 = 10.0;
+
+/// Zero crossing rate, in crossings per second, above which the voicing
+/// confidence decreases. Voices rarely cross zero more often, unlike noise
+/// and sibilants.
+const MAX_VOICED_ZERO_CROSSING_RATE: f32 = 3000.0;
+
+/// Voiced/unvoiced decision: only voiced frames, i.e loud, periodic frames
+/// that do not cross zero too often, are pitch corrected. Silence, breaths
+/// and consonants are not.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Voicing {
+    pub full_scale: f32, // maximal amplitude of the signal
+    pub silence: f32,    // level in dBFS under which frames are silent
+    pub threshold: f32,  // voicing confidence from which frames are voiced
+}
+
+impl Voicing {
+
+    /// Returns the voicing decision of a signal of maximal amplitude
+    /// `full_scale`, with default silence level and threshold
+    pub fn new(full_scale: f32) -> Voicing {
+        Voicing {
+            full_scale,
+            silence: DEFAULT_SILENCE,
+            threshold: DEFAULT_VOICING_THRESHOLD,
+        }
+    }
+
+    /// Returns the confidence, from 0 to 1, that `frame` is voiced. It is
+    /// the product of the periodicity of the frame, of its level (0 if
+    /// silent, 1 from `LEVEL_RANGE` dB above silence) and of its zero
+    /// crossing rate (1 up to `MAX_VOICED_ZERO_CROSSING_RATE`, 0 from twice
+    /// as much).
+    pub fn confidence(&self, frame: &PitchFrame) -> f32 {
+        if frame.frequency <= 0.0 {
+            return 0.0;
+        }
+        let level = to_dbfs(frame.rms, self.full_scale) - self.silence;
+        let energy = (level / LEVEL_RANGE).clamp(0.0, 1.0);
+        let crossings = 2.0 - frame.zero_crossing_rate / MAX_VOICED_ZERO_CROSSING_RATE;
+        frame.periodicity * energy * crossings.clamp(0.0, 1.0)
+    }
+
+    /// Returns true if `frame` is voiced, i.e its confidence reaches the
+    /// threshold
+    pub fn is_voiced(&self, frame: &PitchFrame) -> bool {
+        frame.frequency > 0.0 && self.confidence(frame) >= self.threshold
+    }
+
+    /// Returns the given scale factors of the frames of `track`, where
+    /// frames that are not voiced are not transposed
+    pub fn apply(&self, track: &[PitchFrame], scale_factors: &[f32]) -> Vec<f32> {
+        track
+            .iter()
+            .zip(scale_factors)
+            .map(|(f, &s)| if self.is_voiced(f) { s } else { 1.0 })
+            .collect()
+    }
+}
+
+/// Names of the notes of an octave, from C
+const NOTE_NAMES: [&str; 12] =
+    ["C", "C#", "D", "D#", "E", "F", "F#", "G", "G#", "A", "A#", "B"];
+
+/// Returns the number of zero crossings per second of `frame`
+fn zero_crossing_rate(frame: &[f32], samp_rate: f32) -> f32 {
+    let crossings = frame
+        .windows(2)
+        .filter(|w| (w[0] < 0.0) != (w[1] < 0.0))
+        .count();
+    crossings as f32 * samp_rate / frame.len() as f32
+}
+
+/// Returns the fraction of the energy of `spectrum` that lies near the
+/// harmonics of `frequency`, i.e how periodic of fundamental `frequency` the
+/// analyzed frame is
+fn periodicity(spectrum: &[Complex<f32>], frequency: f32, samp_rate: f32) -> f32 {
+    let half = spectrum.len() / 2;
+    let total: f32 = spectrum[1..=half].iter().map(|c| c.norm_sqr()).sum();
+    if frequency <= 0.0 || total <= 0.0 {
+        return 0.0;
+    }
+    let bin_width = samp_rate / spectrum.len() as f32;
+    // the bins of the harmonics only grow, each bin is counted once from
+    // `next`
+    let (mut harmonic_energy, mut next) = (0.0, 1);
+    let mut harmonic = frequency;
+    while harmonic / bin_width < half as f32 {
+        let bin = (harmonic / bin_width).round() as usize;
+        let last = min(bin + 1, half);
+        for k in bin.saturating_sub(1).max(next)..=last {
+            harmonic_energy += spectrum[k].norm_sqr();
+        }
+        next = next.max(last + 1);
+        harmonic += frequency;
+    }
+    (harmonic_energy / total).min(1.0)
+}
+
+/// Returns the pitch frames of the given frames, where `analyzed_frames` are
+/// their spectrums as computed by `parallelized_analysis()`
+pub(crate) fn pitch_frames(
+    frames: &[&[f32]],
+    analyzed_frames: &[Vec<Complex<f32>>],
+    hopa: usize,
+    samp_rate: f32
+) -> Vec<PitchFrame> {
+    frames
+        .iter()
+        .zip(analyzed_frames)
+        .enumerate()
+        .map(|(i, (frame, analyzed))| pitch_frame(i, frame, analyzed, hopa, samp_rate))
+        .collect()
+}
+
+/// Returns the pitch frame of `frame`, the `index`-th frame of a signal,
+/// where `analyzed` is its spectrum as computed by `analyze_frame()`
+pub(crate) fn pitch_frame(
+    index: usize,
+    frame: &[f32],
+    analyzed: &[Complex<f32>],
+    hopa: usize,
+    samp_rate: f32
+) -> PitchFrame {
+    let frequency = get_main_frequency(analyzed, samp_rate);
+    PitchFrame {
+        time: (index * hopa + frame.len() / 2) as f64 / samp_rate as f64,
+        frequency,
+        rms: norm(frame) / (frame.len() as f32).sqrt(),
+        periodicity: periodicity(analyzed, frequency, samp_rate),
+        zero_crossing_rate: zero_crossing_rate(frame, samp_rate),
+    }
+}
+
+/// Returns the pitch frames of `signal` pre-processed by `preprocessing`,
+/// where `frames` and `analyzed_frames` are the frames of `signal` and their
+/// spectrums as computed by `parallelized_analysis()`. They are reused if the
+/// pre-processing does nothing.
+pub(crate) fn preprocessed_pitch_frames(
+    signal: &[f32],
+    frames: &[&[f32]],
+    analyzed_frames: &[Vec<Complex<f32>>],
+    hopa: usize,
+    samp_rate: f32,
+    preprocessing: &preprocess::Preprocessing
+) -> Vec<PitchFrame> {
+    if preprocessing.is_none() {
+        return pitch_frames(frames, analyzed_frames, hopa, samp_rate);
+    }
+    let fsize = frames.first().map_or(0, |frame| frame.len());
+    pitch_track(&preprocessing.apply(signal, samp_rate), fsize, hopa, samp_rate)
+}
+
+/// Returns the pitch frames of `signal`, cut in frames of size `fsize`
+/// separated by `hopa` samples, as seen by the phase vocoder
+pub fn pitch_track(
+    signal: &[f32],
+    fsize: usize,
+    hopa: usize,
+    samp_rate: f32
+) -> Vec<PitchFrame> {
+    let frames = frame_slices(signal, fsize, hopa);
+    let analyzed_frames = parallelized_analysis(&crate::pool::global(), &frames, hopa);
+    pitch_frames(&frames, &analyzed_frames, hopa, samp_rate)
+}
+
+/// Returns the fractional MIDI note number of `frequency`, A4 (69) being
+/// 440 Hz
+pub fn midi_note_number(frequency: f32) -> f32 {
+    69.0 + 12.0 * (frequency / 440.0).log2()
+}
+
+/// Returns the name of the MIDI note number `key` in scientific pitch
+/// notation, e.g `A4` for 69
+pub fn note_name(key: u8) -> String {
+    format!("{}{}", NOTE_NAMES[key as usize % 12], key as i32 / 12 - 1)
+}
+
+/// Returns the MIDI note number of the note `name` in scientific pitch
+/// notation, e.g 69 for `A4`, `Bb3` being the same as `A#3`, None if it is
+/// not a note or out of the MIDI range
+pub fn note_number(name: &str) -> Option<u8> {
+    let split = name.find(|c: char| c == '-' || c.is_ascii_digit())?;
+    let (pitch_class, octave) = name.split_at(split);
+    let octave: i32 = octave.parse().ok()?;
+    let (letter, accidental) = pitch_class.split_at(pitch_class.len().min(1));
+    let natural = NOTE_NAMES.iter().position(|n| n.eq_ignore_ascii_case(letter))?;
+    let alteration = match accidental {
+        "" => 0,
+        "#" => 1,
+        "b" => -1,
+        _ => return None,
+    };
+    let key = 12 * (octave + 1) + natural as i32 + alteration;
+    u8::try_from(key).ok().filter(|&k| k <= 127)
+}
+
+/// Converts the given root mean square to a level in dBFS, `full_scale` being
+/// the maximal amplitude of the signal
+pub fn to_dbfs(rms: f32, full_scale: f32) -> f32 {
+    20.0 * (rms / full_scale).max(1e-10).log10()
+}
+
+/// Returns the MIDI velocity of a note of level `dbfs`: -60 dBFS and less map
+/// to 1, 0 dBFS to 127
+fn velocity(dbfs: f32) -> u8 {
+    (127.0 * (dbfs + 60.0) / 60.0).round().clamp(1.0, 127.0) as u8
+}
+
+/// What the phase vocoder did to an analysis frame
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct TraceFrame {
+    pub time: f64,
+    pub frequency: f32,
+    pub confidence: f32,
+    pub voiced: bool,
+    pub note: Option<String>, // nearest note of the frequency
+    pub cents: Option<f32>,   // deviation of the frequency from the note
+    pub ratio: f32,           // scale factor applied to the frame
+}
+
+/// Returns the trace of the correction of the frames of `track` by the given
+/// scale factors, frames being voiced according to `voicing`
+pub fn pitch_trace(
+    track: &[PitchFrame],
+    scale_factors: &[f32],
+    voicing: &Voicing
+) -> Vec<TraceFrame> {
+    track
+        .iter()
+        .zip(scale_factors)
+        .map(|(f, &ratio)| {
+            let number = midi_note_number(f.frequency);
+            let key = number.round();
+            let in_range = f.frequency > 0.0 && (0.0..=127.0).contains(&key);
+            TraceFrame {
+                time: f.time,
+                frequency: f.frequency,
+                confidence: voicing.confidence(f),
+                voiced: voicing.is_voiced(f),
+                note: in_range.then(|| note_name(key as u8)),
+                cents: in_range.then_some(100.0 * (number - key)),
+                ratio,
+            }
+        })
+        .collect()
+}
+
+/// Writes the given trace to the file at `path`, in JSON if its extension is
+/// `.json` and in CSV otherwise
+pub fn save_trace(trace: &[TraceFrame], path: &str) -> Result<(), &'static str> {
+    let mut writer = match File::create(path) {
+        Ok(file) => BufWriter::new(file),
+        Err(_) => return Err("could not create pitch track file"),
+    };
+    let written = if path.to_lowercase().ends_with(".json") {
+        serde_json::to_writer_pretty(&mut writer, trace).map_err(|_| ())
+    } else {
+        write_trace_csv(trace, &mut writer).map_err(|_| ())
+    };
+    match written.and_then(|_| writer.flush().map_err(|_| ())) {
+        Ok(()) => Ok(()),
+        Err(()) => Err("could not write pitch track file"),
+    }
+}
+
+/// Writes the given trace as CSV, with a header line. Missing values are left
+/// empty.
+pub fn write_trace_csv<W: Write>(
+    trace: &[TraceFrame],
+    writer: &mut W
+) -> std::io::Result<()> {
+    writeln!(writer, "time,frequency,confidence,voiced,note,cents,ratio")?;
+    for f in trace {
+        writeln!(
+            writer,
+            "{:.6},{:.3},{:.3},{},{},{},{:.6}",
+            f.time,
+            f.frequency,
+            f.confidence,
+            f.voiced,
+            f.note.as_deref().unwrap_or(""),
+            f.cents.map(|c| format!("{:.2}", c)).unwrap_or_default(),
+            f.ratio
+        )?;
+    }
+    Ok(())
+}
+
+/// Segments the pitch track of a melody in notes. Frames that are not voiced
+/// according to `voicing` are rests, the others hold the MIDI note nearest to
+/// their frequency. Consecutive frames holding the same note form a note,
+/// which is dropped if it lasts less than `min_duration` seconds. The
+/// velocity of a note comes from the mean root mean square of its frames.
+/// `hop_time` is the time between frames.
+pub fn transcribe(
+    track: &[PitchFrame],
+    hop_time: f64,
+    voicing: &Voicing,
+    min_duration: f64
+) -> Vec<Note> {
+    let full_scale = voicing.full_scale;
+    let keys: Vec<Option<u8>> = track
+        .iter()
+        .map(|f| {
+            if !voicing.is_voiced(f) {
+                None
+            } else {
+                Some(midi_note_number(f.frequency).round().clamp(0.0, 127.0) as u8)
+            }
+        })
+        .collect();
+
+    let mut notes = Vec::new();
+    let mut start = 0;
+    while start < keys.len() {
+        let mut end = start + 1;
+        while end < keys.len() && keys[end] == keys[start] {
+            end += 1;
+        }
+        let duration = (end - start) as f64 * hop_time;
+        if let Some(key) = keys[start] {
+            if duration >= min_duration {
+                let rms = track[start..end].iter().map(|f| f.rms).sum::<f32>()
+                    / (end - start) as f32;
+                notes.push(Note {
+                    start: (track[start].time - hop_time / 2.0).max(0.0),
+                    end: track[end - 1].time + hop_time / 2.0,
+                    channel: 0,
+                    key,
+                    velocity: velocity(to_dbfs(rms, full_scale)),
+                });
+            }
+        }
+        start = end;
+    }
+    notes
+}
+
+#[cfg(test)]
+mod analysis_tests {
+    use super::*;
+    use crate::generate::Signal;
+
+    fn pitch_frame(time: f64, frequency: f32, rms: f32) -> PitchFrame {
+        PitchFrame { time, frequency, rms, periodicity: 1.0, zero_crossing_rate: 0.0 }
+    }
+
+    fn voicing(silence: f32) -> Voicing {
+        Voicing { full_scale: 1.0, silence, threshold: 0.5 }
+    }
+
+    #[test]
+    fn note_names() {
+        assert_eq!("A4", note_name(69));
+        assert_eq!("C4", note_name(60));
+        assert_eq!("C#-1", note_name(1));
+        assert_eq!("G9", note_name(127));
+    }
+
+    #[test]
+    fn note_numbers() {
+        assert_eq!(Some(69), note_number("A4"));
+        assert_eq!(Some(61), note_number("C#4"));
+        assert_eq!(Some(70), note_number("Bb4"));
+        assert_eq!(Some(0), note_number("C-1"));
+        assert_eq!(Some(127), note_number("G9"));
+        for key in 0..128 {
+            assert_eq!(Some(key), note_number(&note_name(key)));
+        }
+        for name in ["H4", "A", "A#b4", "G#9", "Cb-1", ""] {
+            assert_eq!(None, note_number(name), "{}", name);
+        }
+    }
+
+    #[test]
+    fn midi_note_number_of_a4_and_c4() {
+        assert_eq!(69.0, midi_note_number(440.0));
+        assert_eq!(81.0, midi_note_number(880.0));
+        assert!((midi_note_number(261.63) - 60.0).abs() < 0.01);
+    }
+
+    #[test]
+    fn velocity_from_level() {
+        assert_eq!(127, velocity(0.0));
+        assert_eq!(64, velocity(-29.8));
+        assert_eq!(1, velocity(-80.0));
+        assert_eq!(-20.0, to_dbfs(0.1, 1.0));
+    }
+
+    #[test]
+    fn pitch_track_of_sine() {
+        let sine = Signal::Sine { frequency: 440.0 }
+            .synthesize(8000.0, 1.024, 1.0)
+            .unwrap();
+        let track = pitch_track(&sine, 1024, 256, 8000.0);
+        assert_eq!(29, track.len());
+        assert_eq!(0.064, track[0].time);
+        for f in track {
+            assert!((f.frequency - 440.0).abs() < 2.0);
+            assert!((f.rms - 0.5f32.sqrt()).abs() < 0.01);
+            assert!(f.periodicity > 0.99);
+            assert!((f.zero_crossing_rate - 880.0).abs() < 10.0);
+        }
+    }
+
+    #[test]
+    fn periodicity_of_noise_is_low() {
+        let noise = Signal::WhiteNoise { seed: 1 }
+            .synthesize(8000.0, 1.024, 0.5)
+            .unwrap();
+        for f in pitch_track(&noise, 1024, 256, 8000.0) {
+            assert!(f.periodicity < 0.5);
+        }
+    }
+
+    #[test]
+    fn sine_is_voiced_but_not_noise_nor_silence() {
+        let voicing = voicing(-50.0);
+        let sine = Signal::Sine { frequency: 220.0 }
+            .synthesize(44100.0, 1.0, 1.0)
+            .unwrap();
+        for f in pitch_track(&sine, 2048, 512, 44100.0) {
+            assert!(voicing.confidence(&f) > 0.9);
+            assert!(voicing.is_voiced(&f));
+        }
+        let noise = Signal::WhiteNoise { seed: 1 }.synthesize(44100.0, 1.0, 0.5).unwrap();
+        for f in pitch_track(&noise, 2048, 512, 44100.0) {
+            assert!(!voicing.is_voiced(&f));
+        }
+        let quiet: Vec<f32> = sine.iter().map(|s| s * 1e-4).collect();
+        for f in pitch_track(&quiet, 2048, 512, 44100.0) {
+            assert_eq!(0.0, voicing.confidence(&f));
+        }
+    }
+
+    #[test]
+    fn high_zero_crossing_rate_is_not_voiced() {
+        let voicing = voicing(-50.0);
+        let mut frame = pitch_frame(0.0, 440.0, 0.5);
+        assert_eq!(1.0, voicing.confidence(&frame));
+        frame.zero_crossing_rate = 4500.0;
+        assert_eq!(0.5, voicing.confidence(&frame));
+        frame.zero_crossing_rate = 6000.0;
+        assert_eq!(0.0, voicing.confidence(&frame));
+    }
+
+    #[test]
+    fn unvoiced_frames_are_not_transposed() {
+        let track = [pitch_frame(0.0, 440.0, 0.5), pitch_frame(0.1, 440.0, 1e-5)];
+        assert_eq!(vec![1.5, 1.0], voicing(-50.0).apply(&track, &[1.5, 1.5]));
+    }
+
+    #[test]
+    fn pitch_trace_reports_nearest_note_and_deviation() {
+        let track = [pitch_frame(0.0, 446.0, 0.5), pitch_frame(0.1, 0.0, 0.0)];
+        let trace = pitch_trace(&track, &[440.0 / 446.0, 1.0], &voicing(-50.0));
+        assert_eq!(Some(String::from("A4")), trace[0].note);
+        assert!((trace[0].cents.unwrap() - 23.45).abs() < 0.01);
+        assert_eq!(440.0 / 446.0, trace[0].ratio);
+        assert!(trace[0].voiced);
+        assert_eq!((false, None, None), (trace[1].voiced, trace[1].note.clone(), trace[1].cents));
+    }
+
+    #[test]
+    fn trace_as_csv() {
+        let trace = pitch_trace(&[pitch_frame(0.5, 440.0, 0.5)], &[1.0], &voicing(-50.0));
+        let mut csv = Vec::new();
+        write_trace_csv(&trace, &mut csv).unwrap();
+        assert_eq!(
+            "time,frequency,confidence,voiced,note,cents,ratio\n\
+             0.500000,440.000,1.000,true,A4,0.00,1.000000\n",
+            String::from_utf8(csv).unwrap()
+        );
+    }
+
+    #[test]
+    fn transcribe_segments_notes_and_rests() {
+        let mut track = Vec::new();
+        let freqs = [440.0, 442.0, 440.0, 0.0, 0.0, 494.0, 494.0, 500.0, 300.0, 523.0];
+        for (i, &freq) in freqs.iter().enumerate() {
+            track.push(pitch_frame(0.05 + i as f64 * 0.1, freq, 0.5));
+        }
+        let notes = transcribe(&track, 0.1, &voicing(-50.0), 0.15);
+        assert_eq!(2, notes.len());
+        assert_eq!((69, 0.0), (notes[0].key, notes[0].start));
+        assert!((notes[0].end - 0.3).abs() < 1e-9);
+        assert_eq!(71, notes[1].key);
+        assert!((notes[1].start - 0.5).abs() < 1e-9);
+        assert!((notes[1].end - 0.8).abs() < 1e-9);
+        assert_eq!(velocity(to_dbfs(0.5, 1.0)), notes[1].velocity);
+    }
+
+    #[test]
+    fn transcribe_ignores_quiet_frames() {
+        let track: Vec<PitchFrame> =
+            (0..10).map(|i| pitch_frame(i as f64 * 0.1, 440.0, 0.001)).collect();
+        assert!(transcribe(&track, 0.1, &voicing(-50.0), 0.1).is_empty());
+        assert_eq!(1, transcribe(&track, 0.1, &voicing(-70.0), 0.1).len());
+    }
+}
@@ -0,0 +1,157 @@
+use crate::filter::Biquad;
+
+/// Noise gate silencing the signal while its level stays under a threshold
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct NoiseGate {
+    pub threshold: f32, // level in dBFS under which the gate closes
+    pub attack: f32,    // time constant of the opening, in seconds
+    pub release: f32,   // time constant of the closing, in seconds
+}
+
+/// Returns the coefficient of a one pole smoothing of time constant `time`
+/// seconds at `samp_rate` Hz, 0.0 (no smoothing) for a null time
+fn smoothing_coefficient(time: f32, samp_rate: f32) -> f32 {
+    if time > 0.0 {
+        (-1.0 / (time * samp_rate)).exp()
+    } else {
+        0.0
+    }
+}
+
+impl NoiseGate {
+
+    /// Returns `signal`, sampled at `samp_rate` Hz and of amplitudes between
+    /// `-full_scale` and `full_scale`, gated. The level is followed by a
+    /// peak detector decaying with the release time, and the gain goes from
+    /// 0 to 1 with the attack time and back with the release time.
+    pub fn apply(&self, signal: &[f32], samp_rate: f32, full_scale: f32) -> Vec<f32> {
+        let mut gated = signal.to_vec();
+        self.apply_in_place(&mut gated, samp_rate, full_scale);
+        gated
+    }
+
+    /// Same as `apply()`, gating `signal` in place
+    pub fn apply_in_place(&self, signal: &mut [f32], samp_rate: f32, full_scale: f32) {
+        let threshold = full_scale * 10.0f32.powf(self.threshold / 20.0);
+        let attack = smoothing_coefficient(self.attack, samp_rate);
+        let release = smoothing_coefficient(self.release, samp_rate);
+        let (mut envelope, mut gain) = (0.0f32, 0.0f32);
+        for s in signal.iter_mut() {
+            envelope = s.abs().max(release * envelope);
+            let (target, coefficient) = if envelope >= threshold {
+                (1.0, attack)
+            } else {
+                (0.0, release)
+            };
+            gain = coefficient * gain + (1.0 - coefficient) * target;
+            *s *= gain;
+        }
+    }
+}
+
+/// Pre-processing of the signal seen by the pitch detector, making it robust
+/// to low frequency rumble and room noise. The audible output is not
+/// processed.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Preprocessing {
+    pub high_pass: Option<f32>,    // cut-off frequency in Hz, None if off
+    pub gate: Option<NoiseGate>,   // None if off
+    pub pre_emphasis: Option<f32>, // coefficient, e.g 0.97, None if off
+    pub full_scale: f32,           // maximal amplitude of the signal
+}
+
+impl Preprocessing {
+
+    /// Returns a pre-processing doing nothing, for a signal of amplitudes
+    /// between `-full_scale` and `full_scale`
+    pub fn none(full_scale: f32) -> Preprocessing {
+        Preprocessing { high_pass: None, gate: None, pre_emphasis: None, full_scale }
+    }
+
+    /// Returns true if the pre-processing does nothing
+    pub fn is_none(&self) -> bool {
+        *self == Preprocessing::none(self.full_scale)
+    }
+
+    /// Returns `signal`, sampled at `samp_rate` Hz, high-pass filtered, then
+    /// gated, then pre-emphasized
+    pub fn apply(&self, signal: &[f32], samp_rate: f32) -> Vec<f32> {
+        let mut processed = Vec::new();
+        self.apply_into(signal, samp_rate, &mut processed);
+        processed
+    }
+
+    /// Same as `apply()`, writing to `processed`, which is cleared first and
+    /// whose capacity is reused
+    pub fn apply_into(&self, signal: &[f32], samp_rate: f32, processed: &mut Vec<f32>) {
+        processed.clear();
+        processed.extend_from_slice(signal);
+        if let Some(cutoff) = self.high_pass {
+            Biquad::high_pass(cutoff, samp_rate).filter_in_place(processed);
+        }
+        if let Some(gate) = self.gate {
+            gate.apply_in_place(processed, samp_rate, self.full_scale);
+        }
+        if let Some(coefficient) = self.pre_emphasis {
+            Biquad::pre_emphasis(coefficient).filter_in_place(processed);
+        }
+    }
+}
+
+#[cfg(test)]
+mod preprocess_tests {
+    use super::*;
+    use crate::analysis::pitch_track;
+    use crate::generate::Signal;
+
+    #[test]
+    fn gate_closes_under_threshold() {
+        let gate = NoiseGate { threshold: -30.0, attack: 0.005, release: 0.05 };
+        let a4 = Signal::Sine { frequency: 440.0 };
+        let mut signal = a4.synthesize(8000.0, 1.0, 0.5).unwrap();
+        signal.extend(a4.synthesize(8000.0, 1.0, 0.01).unwrap());
+        let gated = gate.apply(&signal, 8000.0, 1.0);
+        // open after the attack
+        for (g, s) in gated[400..8000].iter().zip(&signal[400..8000]) {
+            assert!((g - s).abs() < 1e-3);
+        }
+        // closed after the release
+        assert!(gated[..10].iter().all(|g| g.abs() < 0.5));
+        assert!(gated[12000..].iter().all(|g| g.abs() < 1e-4));
+    }
+
+    #[test]
+    fn gate_attack_time() {
+        let gate = NoiseGate { threshold: -20.0, attack: 0.01, release: 0.1 };
+        let gated = gate.apply(&[1.0; 200], 10000.0, 1.0);
+        // 1 - 1/e of the gain after the attack time
+        assert!((gated[99] - 0.632).abs() < 0.01);
+    }
+
+    #[test]
+    fn high_pass_removes_rumble_for_detection() {
+        let samp_rate = 8000.0;
+        let rumble = Signal::Sine { frequency: 30.0 }
+            .synthesize(samp_rate, 2.048, 1.0)
+            .unwrap();
+        let signal: Vec<f32> = Signal::Sine { frequency: 440.0 }
+            .synthesize(samp_rate, 2.048, 0.3)
+            .unwrap()
+            .iter()
+            .zip(&rumble)
+            .map(|(s, r)| s + r)
+            .collect();
+        for f in pitch_track(&signal, 2048, 512, samp_rate) {
+            assert!(f.frequency < 40.0);
+        }
+        let preprocessing =
+            Preprocessing { high_pass: Some(100.0), ..Preprocessing::none(1.0) };
+        let filtered = preprocessing.apply(&signal, samp_rate);
+        for f in pitch_track(&filtered, 2048, 512, samp_rate).iter().skip(1) {
+            assert!((f.frequency - 440.0).abs() < 2.0);
+        }
+        assert!(!preprocessing.is_none());
+        assert!(Preprocessing::none(1.0).is_none());
+        assert_eq!(signal, Preprocessing::none(1.0).apply(&signal, samp_rate));
+    }
+}
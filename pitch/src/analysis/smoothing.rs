@@ -0,0 +1,278 @@
+use crate::analysis::{midi_note_number, PitchFrame, Voicing};
+
+/// Cost, in semi tons of transition, of moving a frame by an octave in the
+/// Viterbi smoothing. A burst of octave errors shorter than 12 / OCTAVE_COST
+/// frames is cheaper to move back than to jump to and back from.
+const OCTAVE_COST: f32 = 4.0;
+
+/// Post-processing of a pitch track, correcting the octave errors and
+/// outliers of single frame estimates. Only voiced frames are processed,
+/// unvoiced frames split the track in independent segments.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Smoothing {
+    pub octave_window: usize, // frames of the octave jump suppression, 0 or 1 if off
+    pub viterbi: bool,        // Viterbi smoothing of octave errors
+    pub median: usize,        // frames of the median filter, 0 or 1 if off
+}
+
+impl Smoothing {
+
+    /// Returns a smoothing doing nothing
+    pub fn none() -> Smoothing {
+        Smoothing { octave_window: 0, viterbi: false, median: 0 }
+    }
+
+    /// Returns `track` where the frequencies of voiced frames are processed
+    /// by octave jump suppression, then Viterbi smoothing, then median
+    /// filtering
+    pub fn apply(&self, track: &[PitchFrame], voicing: &Voicing) -> Vec<PitchFrame> {
+        let mut smoothed = track.to_vec();
+        if self.octave_window <= 1 && !self.viterbi && self.median <= 1 {
+            return smoothed;
+        }
+        for segment in voiced_segments(track, voicing) {
+            let mut cents: Vec<f32> = track[segment.clone()]
+                .iter()
+                .map(|f| 100.0 * midi_note_number(f.frequency))
+                .collect();
+            if self.octave_window > 1 {
+                cents = suppress_octave_jumps(&cents, self.octave_window);
+            }
+            if self.viterbi {
+                cents = viterbi(&cents);
+            }
+            if self.median > 1 {
+                cents = median_filter(&cents, self.median);
+            }
+            for (frame, c) in smoothed[segment].iter_mut().zip(cents) {
+                frame.frequency = 440.0 * 2.0f32.powf((c - 6900.0) / 1200.0);
+            }
+        }
+        smoothed
+    }
+}
+
+/// Returns the ranges of consecutive voiced frames of `track`
+fn voiced_segments(
+    track: &[PitchFrame],
+    voicing: &Voicing
+) -> Vec<std::ops::Range<usize>> {
+    let mut segments = Vec::new();
+    let mut start = None;
+    for (i, frame) in track.iter().enumerate() {
+        match (voicing.is_voiced(frame), start) {
+            (true, None) => start = Some(i),
+            (false, Some(s)) => {
+                segments.push(s..i);
+                start = None;
+            },
+            _ => (),
+        }
+    }
+    if let Some(s) = start {
+        segments.push(s..track.len());
+    }
+    segments
+}
+
+/// Returns the median of `values`, which must not be empty
+fn median(values: &[f32]) -> f32 {
+    let mut sorted = values.to_vec();
+    sorted.sort_by(|a, b| a.total_cmp(b));
+    let middle = sorted.len() / 2;
+    if sorted.len().is_multiple_of(2) {
+        (sorted[middle - 1] + sorted[middle]) / 2.0
+    } else {
+        sorted[middle]
+    }
+}
+
+/// Returns the window of `len` values around `values[i]`, centered on it
+/// except at the edges of `values`
+fn window(values: &[f32], i: usize, len: usize) -> &[f32] {
+    let start = i.saturating_sub(len / 2).min(values.len().saturating_sub(len));
+    &values[start..(start + len).min(values.len())]
+}
+
+/// Returns `cents` filtered by a median filter of `len` values, rounded up
+/// to an odd number. The window shrinks at the edges to stay centered, so
+/// that glides are kept.
+pub fn median_filter(cents: &[f32], len: usize) -> Vec<f32> {
+    (0..cents.len())
+        .map(|i| {
+            let half = (len / 2).min(i).min(cents.len() - 1 - i);
+            median(&cents[i - half..=i + half])
+        })
+        .collect()
+}
+
+/// Returns `cents` where values one or more octaves away from the median of
+/// the `len` values around them are moved by octaves towards it. Values more
+/// than a semi tone away from an octave of the median are real jumps and are
+/// kept.
+pub fn suppress_octave_jumps(cents: &[f32], len: usize) -> Vec<f32> {
+    (0..cents.len())
+        .map(|i| {
+            let deviation = cents[i] - median(window(cents, i, len));
+            let octaves = (deviation / 1200.0).round();
+            if octaves != 0.0 && (deviation - 1200.0 * octaves).abs() < 100.0 {
+                cents[i] - 1200.0 * octaves
+            } else {
+                cents[i]
+            }
+        })
+        .collect()
+}
+
+/// Returns the most likely path of pitches through `cents`, where each value
+/// may be an octave error: the states of a frame are its value and the
+/// values an octave below and above it. Moving a frame by an octave costs
+/// `OCTAVE_COST` and going from a frame to the next one costs their distance
+/// in semi tons, so that short octave jumps are corrected but not lasting
+/// ones, nor glides.
+pub fn viterbi(cents: &[f32]) -> Vec<f32> {
+    const OCTAVES: [f32; 3] = [-1.0, 0.0, 1.0];
+    let state = |i: usize, s: usize| cents[i] + 1200.0 * OCTAVES[s];
+    let emission = |s: usize| if OCTAVES[s] == 0.0 { 0.0 } else { OCTAVE_COST };
+
+    let mut costs: Vec<f32> = (0..OCTAVES.len()).map(emission).collect();
+    let mut previous_states: Vec<[usize; 3]> = Vec::with_capacity(cents.len());
+    for i in 1..cents.len() {
+        let mut next_costs = vec![0.0; OCTAVES.len()];
+        let mut best = [0; 3];
+        for (s, next_cost) in next_costs.iter_mut().enumerate() {
+            let (p, cost) = (0..OCTAVES.len())
+                .map(|p| (p, costs[p] + (state(i, s) - state(i - 1, p)).abs() / 100.0))
+                .min_by(|a, b| a.1.total_cmp(&b.1))
+                .unwrap();
+            *next_cost = cost + emission(s);
+            best[s] = p;
+        }
+        costs = next_costs;
+        previous_states.push(best);
+    }
+
+    // backtrack from the cheapest last state
+    let mut s = (0..OCTAVES.len())
+        .min_by(|&a, &b| costs[a].total_cmp(&costs[b]))
+        .unwrap_or(1);
+    let mut path = vec![0.0; cents.len()];
+    for i in (0..cents.len()).rev() {
+        path[i] = state(i, s);
+        if i > 0 {
+            s = previous_states[i - 1][s];
+        }
+    }
+    path
+}
+
+#[cfg(test)]
+mod smoothing_tests {
+    use super::*;
+    use crate::analysis::pitch_track;
+
+    fn glide(len: usize, from: f32, to: f32) -> Vec<f32> {
+        (0..len)
+            .map(|i| from + (to - from) * i as f32 / (len - 1) as f32)
+            .collect()
+    }
+
+    fn assert_close(expected: &[f32], actual: &[f32]) {
+        for (e, a) in expected.iter().zip(actual) {
+            assert!((e - a).abs() < 0.1, "expected {:?}, got {:?}", expected, actual);
+        }
+    }
+
+    fn with_octave_errors(cents: &[f32]) -> Vec<f32> {
+        let mut with_errors = cents.to_vec();
+        with_errors[5] += 1200.0;
+        with_errors[12] -= 1200.0;
+        with_errors[13] -= 1200.0;
+        with_errors[20] += 1200.0;
+        with_errors
+    }
+
+    #[test]
+    fn median_filter_removes_outliers() {
+        let cents = [6900.0, 6900.0, 7300.0, 6900.0, 6800.0];
+        let filtered = vec![6900.0, 6900.0, 6900.0, 6900.0, 6800.0];
+        assert_eq!(filtered, median_filter(&cents, 3));
+        assert_eq!(cents.to_vec(), median_filter(&cents, 1));
+    }
+
+    #[test]
+    fn median_filter_keeps_glides() {
+        let cents = glide(30, 5700.0, 6900.0);
+        assert_close(&cents, &median_filter(&cents, 5));
+    }
+
+    #[test]
+    fn octave_jumps_are_suppressed_on_glide() {
+        let cents = glide(30, 5700.0, 6300.0);
+        assert_close(&cents, &suppress_octave_jumps(&with_octave_errors(&cents), 9));
+    }
+
+    #[test]
+    fn octave_jump_suppression_keeps_real_jumps() {
+        let mut cents = vec![6000.0; 10];
+        cents.extend(vec![6700.0; 10]);
+        assert_eq!(cents, suppress_octave_jumps(&cents, 9));
+    }
+
+    #[test]
+    fn viterbi_corrects_short_octave_errors_on_glide() {
+        let cents = glide(30, 5700.0, 6300.0);
+        assert_close(&cents, &viterbi(&with_octave_errors(&cents)));
+    }
+
+    #[test]
+    fn viterbi_keeps_lasting_octave_jumps() {
+        let mut cents = vec![6000.0; 10];
+        cents.extend(vec![7200.0; 10]);
+        assert_eq!(cents, viterbi(&cents));
+        assert_eq!(vec![6900.0], viterbi(&[6900.0]));
+        assert!(viterbi(&[]).is_empty());
+    }
+
+    #[test]
+    fn smoothing_corrects_octave_ambiguous_signal() {
+        // the second harmonic is louder than the fundamental for 2 frames
+        // every 10, so single frame estimates jump an octave up
+        let (samp_rate, fsize, hopa) = (8000.0, 512, 512);
+        let signal: Vec<f32> = (0..51200)
+            .map(|n| {
+                let t = n as f32 / samp_rate;
+                let h2 = if (n / hopa) % 10 < 2 { 1.5 } else { 0.5 };
+                let phase = 2.0 * std::f32::consts::PI * 200.0 * t;
+                0.4 * phase.sin() + 0.4 * h2 * (2.0 * phase).sin()
+            })
+            .collect();
+        let track = pitch_track(&signal, fsize, hopa, samp_rate);
+        let jumps = track.iter().filter(|f| (f.frequency - 400.0).abs() < 20.0).count();
+        assert!(jumps >= 15);
+
+        let voicing = Voicing { threshold: 0.0, ..Voicing::new(1.0) };
+        let smoothings = [
+            Smoothing { octave_window: 9, ..Smoothing::none() },
+            Smoothing { viterbi: true, ..Smoothing::none() },
+            Smoothing { octave_window: 9, viterbi: true, median: 5 },
+        ];
+        for smoothing in smoothings {
+            for f in smoothing.apply(&track, &voicing) {
+                assert!((f.frequency - 200.0).abs() < 5.0, "{:?}", smoothing);
+            }
+        }
+    }
+
+    #[test]
+    fn unvoiced_frames_are_left_as_is() {
+        let voicing = Voicing::new(1.0);
+        let frame = |frequency, rms| PitchFrame {
+            time: 0.0, frequency, rms, periodicity: 1.0, zero_crossing_rate: 0.0
+        };
+        let track = [frame(440.0, 0.5), frame(880.0, 0.0), frame(440.0, 0.5)];
+        let smoothing = Smoothing { octave_window: 3, viterbi: true, median: 3 };
+        assert_eq!(880.0, smoothing.apply(&track, &voicing)[1].frequency);
+        assert_eq!(vec![0..1, 2..3], voiced_segments(&track, &voicing));
+    }
+}
@@ -0,0 +1,158 @@
+use crate::analysis::{midi_note_number, PitchFrame, Voicing};
+
+/// Lowest vibrato rate, in Hz. Vibratos are usually between 4 and 8 Hz.
+pub const MIN_VIBRATO_RATE: f32 = 4.0;
+
+/// Returns the mean of the `len` values around each value of `values`,
+/// centered on it except at the edges of `values`
+fn moving_average(values: &[f32], len: usize) -> Vec<f32> {
+    let len = len.clamp(1, values.len().max(1));
+    (0..values.len())
+        .map(|i| {
+            let start = i.saturating_sub(len / 2).min(values.len() - len);
+            values[start..start + len].iter().sum::<f32>() / len as f32
+        })
+        .collect()
+}
+
+/// Returns the slow moving centre of the pitch `cents` of frames analyzed
+/// `frame_rate` times per second: two moving averages over a period of the
+/// slowest vibrato, which cancel the modulations of `MIN_VIBRATO_RATE` Hz
+/// and faster.
+pub fn pitch_centre(cents: &[f32], frame_rate: f32) -> Vec<f32> {
+    let len = (frame_rate / MIN_VIBRATO_RATE).round() as usize;
+    moving_average(&moving_average(cents, len), len)
+}
+
+/// Splits the voiced frames of `track`, analyzed `frame_rate` times per
+/// second, in their pitch centre and their vibrato. Returns `track` where
+/// the frequency of voiced frames is their centre, and the vibrato of each
+/// frame in cents (0 for unvoiced frames).
+pub fn split(
+    track: &[PitchFrame],
+    voicing: &Voicing,
+    frame_rate: f32
+) -> (Vec<PitchFrame>, Vec<f32>) {
+    let mut centre_track = track.to_vec();
+    let mut vibrato = vec![0.0; track.len()];
+    let mut start = 0;
+    while start < track.len() {
+        let voiced = voicing.is_voiced(&track[start]);
+        let mut end = start + 1;
+        while end < track.len() && voicing.is_voiced(&track[end]) == voiced {
+            end += 1;
+        }
+        if voiced {
+            let cents: Vec<f32> = track[start..end]
+                .iter()
+                .map(|f| 100.0 * midi_note_number(f.frequency))
+                .collect();
+            let centre = pitch_centre(&cents, frame_rate);
+            for (i, (c, centre)) in cents.iter().zip(centre).enumerate() {
+                vibrato[start + i] = c - centre;
+                centre_track[start + i].frequency =
+                    440.0 * 2.0f32.powf((centre - 6900.0) / 1200.0);
+            }
+        }
+        start = end;
+    }
+    (centre_track, vibrato)
+}
+
+/// Returns the scale factors correcting frames to the targets of their pitch
+/// centre, given by `centre_scale_factors`, with their vibrato, in cents,
+/// multiplied by `depth`: 0 removes the vibrato, 1 keeps it and more
+/// exaggerates it.
+pub fn reapply(centre_scale_factors: &[f32], vibrato: &[f32], depth: f32) -> Vec<f32> {
+    centre_scale_factors
+        .iter()
+        .zip(vibrato)
+        .map(|(s, v)| s * 2.0f32.powf((depth - 1.0) * v / 1200.0))
+        .collect()
+}
+
+#[cfg(test)]
+mod vibrato_tests {
+    use super::*;
+    use crate::analysis::pitch_track;
+
+    /// Pitch, in cents, of a tone 30 cents above A3 with a vibrato of 50
+    /// cents at 5.5 Hz, at time `t`
+    fn vibrato_tone(t: f32) -> f32 {
+        5730.0 + 50.0 * (2.0 * std::f32::consts::PI * 5.5 * t).sin()
+    }
+
+    fn frame(time: f64, cents: f32) -> PitchFrame {
+        PitchFrame {
+            time,
+            frequency: 440.0 * 2.0f32.powf((cents - 6900.0) / 1200.0),
+            rms: 0.5,
+            periodicity: 1.0,
+            zero_crossing_rate: 0.0,
+        }
+    }
+
+    #[test]
+    fn moving_average_of_short_values() {
+        assert_eq!(vec![2.0, 2.0, 2.0], moving_average(&[1.0, 2.0, 3.0], 5));
+        assert_eq!(vec![1.5, 1.5, 2.5], moving_average(&[1.0, 2.0, 3.0], 2));
+        assert!(moving_average(&[], 5).is_empty());
+    }
+
+    #[test]
+    fn pitch_centre_removes_vibrato() {
+        let cents: Vec<f32> = (0..300).map(|i| vibrato_tone(i as f32 / 100.0)).collect();
+        for c in pitch_centre(&cents, 100.0) {
+            assert!((c - 5730.0).abs() < 5.0);
+        }
+    }
+
+    #[test]
+    fn correction_keeps_removes_or_exaggerates_vibrato() {
+        let track: Vec<PitchFrame> = (0..300)
+            .map(|i| frame(i as f64 / 100.0, vibrato_tone(i as f32 / 100.0)))
+            .collect();
+        let (centre, vibrato) = split(&track, &Voicing::new(1.0), 100.0);
+        // correction of the centre to A3
+        let centre_scale_factors: Vec<f32> =
+            centre.iter().map(|f| 220.0 / f.frequency).collect();
+        for depth in [0.0, 1.0, 2.5] {
+            let scale_factors = reapply(&centre_scale_factors, &vibrato, depth);
+            for (i, (f, s)) in track.iter().zip(scale_factors).enumerate() {
+                let corrected = 100.0 * midi_note_number(f.frequency * s);
+                let expected = 5700.0 + depth * (vibrato_tone(i as f32 / 100.0) - 5730.0);
+                assert!((corrected - expected).abs() < 5.0 * depth.max(1.0));
+            }
+        }
+    }
+
+    #[test]
+    fn unvoiced_frames_have_no_vibrato() {
+        let mut track = vec![frame(0.0, 6900.0), frame(0.01, 7000.0)];
+        track[1].rms = 0.0;
+        let (centre, vibrato) = split(&track, &Voicing::new(1.0), 100.0);
+        assert_eq!(vec![0.0, 0.0], vibrato);
+        assert_eq!(track[1], centre[1]);
+    }
+
+    #[test]
+    fn split_vibrato_tone() {
+        let samp_rate = 8000.0;
+        let mut phase = 0.0;
+        let signal: Vec<f32> = (0..24000)
+            .map(|n| {
+                let cents = vibrato_tone(n as f32 / samp_rate) + 1200.0;
+                phase += 2.0 * std::f32::consts::PI
+                    * 440.0 * 2.0f32.powf((cents - 6900.0) / 1200.0) / samp_rate;
+                phase.sin()
+            })
+            .collect();
+        let track = pitch_track(&signal, 512, 80, samp_rate);
+        let (centre, vibrato) = split(&track, &Voicing::new(1.0), 100.0);
+        for f in centre {
+            assert!((100.0 * midi_note_number(f.frequency) - 6930.0).abs() < 10.0);
+        }
+        let depth = vibrato.iter().fold(0.0f32, |max, v| max.max(v.abs()));
+        assert!((depth - 50.0).abs() < 15.0);
+    }
+}
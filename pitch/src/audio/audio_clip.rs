@@ -1,53 +1,103 @@
 /// This code was adapted from https://github.com/RustAudio/cpal examples
 use cpal::{Device, SupportedStreamConfig, FromSample, Sample};
+use std::fs::File;
+use std::io::BufWriter;
 use std::sync::{Arc, Mutex};
 use cpal::traits::{DeviceTrait, StreamTrait};
 
-/// An audio clip is a vector of audio samples sampled at a certain rate
+/// An audio clip is a vector of audio samples sampled at a certain rate. When
+/// the clip has several channels, samples are interleaved: the sample of
+/// channel c at frame i is stored at index `i * channels + c`.
 #[derive(Clone)]
 pub struct AudioClip {
-    pub samples: Vec<f32>, // vector of temporal amplitudes
+    pub samples: Vec<f32>, // vector of interleaved temporal amplitudes
     pub sample_rate: u32, 
+    pub channels: u16,     // number of interleaved channels
 }
 
 type ClipHandle = Arc<Mutex<Option<AudioClip>>>; // wrapper for shared clip
 
+/// Writer of .wav files in which audio clips are saved
+pub type ClipWriter = hound::WavWriter<BufWriter<File>>;
+
 // Writes input device data into given audio clip. T is a Sample, but evrything
-// is converted to f32 as AudioClip stores f32 temporal amplitude values. nbc
-// indicates how many channels there are used by input device.
-fn write_input_data<T>(input: &[T], nbc: u16, clipw: &ClipHandle)
+// is converted to f32 as AudioClip stores f32 temporal amplitude values. Input
+// data is interleaved the same way as the clip, so every channel is kept.
+fn write_input_data<T>(input: &[T], clipw: &ClipHandle)
 where
     T: cpal::Sample, f32: FromSample<T>
 {
     if let Ok(mut guard) = clipw.try_lock() {
         if let Some(clip) = guard.as_mut() { // encapsulated clip
-            // iterate over chunks of nbc elements of input data
-            for chunk in input.chunks(nbc as usize) {
-                clip.samples.push(chunk[0].to_sample::<f32>()); // mono data
+            for sample in input {
+                clip.samples.push(sample.to_sample::<f32>());
             }
         }
     }
 }
 
-type StateHandle = Arc<Mutex<Option<(usize, Vec<f32>)>>>;
+type StateHandle = Arc<Mutex<Option<(usize, AudioClip)>>>;
 
-// Writes input device data into given audio clip. T is a Sample, but everything
-// is converted to f32 as AudioClip stores f32 temporal amplitude values. nbc
-// indicates how many channels there are used by input device.
-fn write_output_data(output: &mut [f32], nbc: u16, state: &StateHandle) {
+// Writes the given audio clip into output device data. routes contains, for
+// each output channel, the clip channel that feeds it (None for silence). The
+// position stored in the state is a frame index in the clip.
+fn write_output_data(
+    output: &mut [f32],
+    routes: &[Option<usize>],
+    state: &StateHandle
+) {
     if let Ok(mut guard) = state.try_lock() {
-        if let Some((i, data)) = guard.as_mut(){ // position and audio
-            for chunk in output.chunks_mut(nbc.into()) {
-                for sample in chunk.iter_mut() {
-                     *sample = data
-                     .get(*i)
-                     .unwrap_or(&0f32)
-                     .to_sample::<f32>();
+        if let Some((i, clip)) = guard.as_mut() { // position and audio
+            let clip_nbc = clip.channels as usize;
+            for chunk in output.chunks_mut(routes.len()) {
+                for (sample, route) in chunk.iter_mut().zip(routes) {
+                    *sample = match route {
+                        Some(c) => *clip
+                            .samples
+                            .get(*i * clip_nbc + c)
+                            .unwrap_or(&0f32),
+                        None => 0f32,
+                    };
                 }
-                *i += 1;           
+                *i += 1;
+            }
+        }
+    }
+}
+
+/// Computes, for each of the `nbc` output channels, the channel of a clip of
+/// `clip_nbc` channels that is routed to it. When `out_channels` is empty, a
+/// mono clip is played on every output channel and the channels of a
+/// multi-channel clip are played on the output channels of same index.
+/// Otherwise, a mono clip is played on every output channel of `out_channels`
+/// and the ith channel of a multi-channel clip on `out_channels[i]`.
+fn routing(
+    clip_nbc: u16,
+    out_channels: &[usize],
+    nbc: u16
+) -> Result<Vec<Option<usize>>, &'static str> {
+    let mut routes = vec![None; nbc as usize];
+    if out_channels.is_empty() {
+        for (j, route) in routes.iter_mut().enumerate() {
+            if clip_nbc == 1 {
+                *route = Some(0);
+            } else if j < clip_nbc as usize {
+                *route = Some(j);
             }
         }
+        return Ok(routes);
     }
+    for (i, &j) in out_channels.iter().enumerate() {
+        if j >= nbc as usize {
+            return Err("output channel index out of range");
+        }
+        if clip_nbc == 1 {
+            routes[j] = Some(0);
+        } else if i < clip_nbc as usize {
+            routes[j] = Some(i);
+        }
+    }
+    Ok(routes)
 }
 
 impl AudioClip {
@@ -55,7 +105,153 @@ impl AudioClip {
     // Debug print starts with this constant
     pub const STATUS: &str = "[STATUS] ";
 
-    /// Produces an audio clip from the output device of ~len seconds
+    /// Produces an audio clip by interleaving the given channels. Every
+    /// channel is truncated to the length of the shortest one.
+    pub fn from_channels(sample_rate: u32, channels: &[Vec<f32>]) -> AudioClip {
+        let nb_frames = channels.iter().map(|c| c.len()).min().unwrap_or(0);
+        let mut samples = Vec::with_capacity(nb_frames * channels.len());
+        for i in 0..nb_frames {
+            for channel in channels {
+                samples.push(channel[i]);
+            }
+        }
+        AudioClip {
+            samples,
+            sample_rate,
+            channels: channels.len() as u16
+        }
+    }
+
+    /// Returns the number of frames of the clip, i.e the number of samples
+    /// per channel
+    pub fn nb_frames(&self) -> usize {
+        if self.channels == 0 {
+            0
+        } else {
+            self.samples.len() / self.channels as usize
+        }
+    }
+
+    /// Returns the samples of the channel at index `c`. Panics if `c` is not
+    /// a channel of the clip.
+    pub fn channel(&self, c: usize) -> Vec<f32> {
+        if c >= self.channels as usize {
+            panic!("channel(): channel index out of range");
+        }
+        self.samples
+            .iter()
+            .skip(c)
+            .step_by(self.channels as usize)
+            .copied()
+            .collect()
+    }
+
+    /// Returns the planar representation of the clip: a vector containing the
+    /// samples of each channel
+    pub fn to_channels(&self) -> Vec<Vec<f32>> {
+        (0..self.channels as usize).map(|c| self.channel(c)).collect()
+    }
+
+    /// Produces a new audio clip containing only the given channels of this
+    /// clip, in the given order
+    pub fn select_channels(
+        &self,
+        channels: &[usize]
+    ) -> Result<AudioClip, &'static str> {
+        if channels.is_empty() {
+            return Err("no channel selected");
+        }
+        if channels.iter().any(|&c| c >= self.channels as usize) {
+            return Err("input channel index out of range");
+        }
+        let selected: Vec<Vec<f32>> =
+            channels.iter().map(|&c| self.channel(c)).collect();
+        Ok(AudioClip::from_channels(self.sample_rate, &selected))
+    }
+
+    /// Loads the audio clip stored in the .wav file at `path`. Integer samples
+    /// are scaled to [-1.0, 1.0], as the ones recorded from input devices.
+    pub fn load(path: &str) -> Result<AudioClip, &'static str> {
+        let mut reader = match hound::WavReader::open(path) {
+            Ok(reader) => reader,
+            Err(_) => return Err("could not open .wav file")
+        };
+        let spec = reader.spec();
+        let samples: Result<Vec<f32>, hound::Error> = match spec.sample_format {
+            hound::SampleFormat::Float => reader.samples::<f32>().collect(),
+            hound::SampleFormat::Int => {
+                let scale = (1u32 << (spec.bits_per_sample - 1)) as f32;
+                reader.samples::<i32>().map(|s| s.map(|s| s as f32 / scale)).collect()
+            }
+        };
+        match samples {
+            Ok(samples) => Ok(AudioClip {
+                samples,
+                sample_rate: spec.sample_rate,
+                channels: spec.channels
+            }),
+            Err(_) => Err("could not read .wav file samples")
+        }
+    }
+
+    /// Creates a 16 bits PCM .wav file at `path`, ready to receive clips of
+    /// `channels` channels sampled at `sample_rate`
+    pub fn create_wav(
+        path: &str,
+        sample_rate: u32,
+        channels: u16
+    ) -> Result<ClipWriter, &'static str> {
+        let spec = hound::WavSpec {
+            channels,
+            sample_rate,
+            bits_per_sample: 16,
+            sample_format: hound::SampleFormat::Int
+        };
+        match hound::WavWriter::create(path, spec) {
+            Ok(writer) => Ok(writer),
+            Err(_) => Err("could not create .wav file")
+        }
+    }
+
+    /// Appends the clip to the .wav file of `writer`. The header of the file is
+    /// updated, so that it stays readable if the program is stopped.
+    pub fn append_to(&self, writer: &mut ClipWriter) -> Result<(), &'static str> {
+        let spec = writer.spec();
+        if spec.channels != self.channels || spec.sample_rate != self.sample_rate {
+            return Err("clip does not match the .wav file format");
+        }
+        for sample in &self.samples {
+            if writer.write_sample(sample.to_sample::<i16>()).is_err() {
+                return Err("could not write to .wav file");
+            }
+        }
+        match writer.flush() {
+            Ok(()) => Ok(()),
+            Err(_) => Err("could not write to .wav file")
+        }
+    }
+
+    /// Saves the clip in a 16 bits PCM .wav file at `path`
+    pub fn save_wav(&self, path: &str) -> Result<(), &'static str> {
+        let mut writer =
+            AudioClip::create_wav(path, self.sample_rate, self.channels)?;
+        self.append_to(&mut writer)?;
+        match writer.finalize() {
+            Ok(()) => Ok(()),
+            Err(_) => Err("could not write to .wav file")
+        }
+    }
+
+    /// Returns the duration of the clip in seconds, rounded up
+    pub fn duration(&self) -> u64 {
+        if self.sample_rate == 0 {
+            return 0;
+        }
+        (self.nb_frames() as u64).div_ceil(self.sample_rate as u64)
+    }
+
+    /// Produces an audio clip from the input device of ~len seconds, holding
+    /// every channel of the device
     pub fn record(
         in_dev: &Device,
         in_conf: SupportedStreamConfig,
@@ -63,7 +259,8 @@ impl AudioClip {
     ) -> AudioClip {
         let clip = AudioClip { // Where to store the input data
             samples: Vec::new(),
-            sample_rate: in_conf.sample_rate().0 // the sample rate of input
+            sample_rate: in_conf.sample_rate().0, // the sample rate of input
+            channels: in_conf.channels()          // the channels of input
         };
 
         // Allow clip to be multi-owned by using locks for access
@@ -73,26 +270,23 @@ impl AudioClip {
         // Callback function on stream error
         let err_fn = |err| eprintln!("error on stream: {}", err);
 
-        // Number of input channels
-        let nbc = in_conf.channels();
-
         // Create stream result depending on sample format
         let in_stream_res = match in_conf.sample_format() {
             cpal::SampleFormat::F32 => in_dev.build_input_stream(
                 &in_conf.into(),
-                move |data, _: &_| write_input_data::<f32>(data, nbc, &clip2),
+                move |data, _: &_| write_input_data::<f32>(data, &clip2),
                 err_fn,
                 None
             ),
             cpal::SampleFormat::I16 => in_dev.build_input_stream(
                 &in_conf.into(),
-                move |data, _: &_| write_input_data::<i16>(data, nbc, &clip2),
+                move |data, _: &_| write_input_data::<i16>(data, &clip2),
                 err_fn,
                 None
             ),
             cpal::SampleFormat::U16 => in_dev.build_input_stream(
                 &in_conf.into(),
-                move |data, _: &_| write_input_data::<u16>(data, nbc, &clip2),
+                move |data, _: &_| write_input_data::<u16>(data, &clip2),
                 err_fn,
                 None
             ),
@@ -119,31 +313,44 @@ impl AudioClip {
         clip
     }
 
+    /// Checks that a clip of `channels` channels can be routed to the output
+    /// channels `out_channels` of a device of `nbc` channels (see
+    /// `routing()`), e.g before recording the clip
+    pub fn check_routing(
+        channels: u16,
+        out_channels: &[usize],
+        nbc: u16
+    ) -> Result<(), &'static str> {
+        routing(channels, out_channels, nbc).map(|_| ())
+    }
+
+    /// Plays the clip on the output device for len seconds. The clip is
+    /// routed to the output channels `out_channels` (see `routing()`), every
+    /// output channel is used if it is empty.
     pub fn play(
         &self, 
         out_dev: &Device, 
         out_conf: SupportedStreamConfig,
-        len: u64
-    ) {
+        len: u64,
+        out_channels: &[usize]
+    ) -> Result<(), &'static str> {
         // Position in data and data to play
-        let state = (0, self.samples.clone());
+        let state = (0, self.clone());
         let state = Arc::new(Mutex::new(Some(state)));
 
         // Callback function on stream error
         let err_fn = |err| eprintln!("error on stream: {}", err);
 
-        // Number of output channelspp
-        let nbc = out_conf.channels();
+        // Output channel of each clip channel
+        let routes = routing(self.channels, out_channels, out_conf.channels())?;
 
-        // Create stream result depending on sample format
-        let out_stream_res = match out_conf.sample_format() {
-            _ => out_dev.build_output_stream(
-                &out_conf.into(),
-                move |data, _: &_| write_output_data(data, nbc, &state),
-                err_fn,
-                None
-            )
-        };
+        // Create stream result
+        let out_stream_res = out_dev.build_output_stream(
+            &out_conf.into(),
+            move |data, _: &_| write_output_data(data, &routes, &state),
+            err_fn,
+            None
+        );
 
         // Get output stream
         let stream = match out_stream_res {
@@ -156,5 +363,141 @@ impl AudioClip {
 
         // Play back for len seconds
         std::thread::sleep(std::time::Duration::from_secs(len));
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod audio_clip_tests {
+    use super::*;
+
+    fn stereo_clip() -> AudioClip {
+        AudioClip {
+            samples: vec![1.0, -1.0, 2.0, -2.0, 3.0, -3.0],
+            sample_rate: 8000,
+            channels: 2
+        }
+    }
+
+    #[test]
+    fn from_channels_interleaves_samples() {
+        let clip = AudioClip::from_channels(
+            8000,
+            &[vec![1.0, 2.0, 3.0], vec![-1.0, -2.0, -3.0, -4.0]]
+        );
+        assert_eq!(2, clip.channels);
+        assert_eq!(3, clip.nb_frames());
+        assert_eq!(clip.samples, stereo_clip().samples);
+    }
+
+    #[test]
+    fn channel_deinterleaves_samples() {
+        let clip = stereo_clip();
+        assert_eq!(vec![1.0, 2.0, 3.0], clip.channel(0));
+        assert_eq!(vec![-1.0, -2.0, -3.0], clip.channel(1));
+        assert_eq!(clip.to_channels(), vec![clip.channel(0), clip.channel(1)]);
+    }
+
+    #[test]
+    fn select_channels_keeps_given_order() {
+        let clip = stereo_clip().select_channels(&[1, 0]).unwrap();
+        assert_eq!(vec![-1.0, 1.0, -2.0, 2.0, -3.0, 3.0], clip.samples);
+        let clip = stereo_clip().select_channels(&[1]).unwrap();
+        assert_eq!(1, clip.channels);
+        assert_eq!(vec![-1.0, -2.0, -3.0], clip.samples);
+    }
+
+    #[test]
+    fn select_channels_out_of_range_fails() {
+        assert!(stereo_clip().select_channels(&[2]).is_err());
+        assert!(stereo_clip().select_channels(&[]).is_err());
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn write_input_data_keeps_every_channel() {
+        let clip = AudioClip { samples: vec![], sample_rate: 8000, channels: 2 };
+        let clip = Arc::new(Mutex::new(Some(clip)));
+        write_input_data::<f32>(&[1.0, -1.0, 2.0, -2.0], &clip);
+        write_input_data::<f32>(&[3.0, -3.0], &clip);
+        let clip = clip.lock().unwrap().take().unwrap();
+        assert_eq!(clip.samples, stereo_clip().samples);
+    }
+
+    #[test]
+    fn save_wav_then_load_gives_same_clip() {
+        let clip = AudioClip {
+            samples: vec![0.0, 0.5, -0.5, 0.25, -1.0, 0.75],
+            sample_rate: 8000,
+            channels: 2
+        };
+        let path = std::env::temp_dir().join("pitch_audio_clip_save_load.wav");
+        let path = path.to_str().unwrap();
+        clip.save_wav(path).unwrap();
+        let loaded = AudioClip::load(path).unwrap();
+        std::fs::remove_file(path).unwrap();
+        assert_eq!(clip.sample_rate, loaded.sample_rate);
+        assert_eq!(clip.channels, loaded.channels);
+        assert_eq!(clip.samples.len(), loaded.samples.len());
+        for (expected, actual) in clip.samples.iter().zip(loaded.samples) {
+            assert!((expected - actual).abs() < 1.0 / 16384.0);
+        }
+    }
+
+    #[test]
+    fn append_to_mismatching_file_fails() {
+        let path = std::env::temp_dir().join("pitch_audio_clip_append.wav");
+        let path = path.to_str().unwrap();
+        let mut writer = AudioClip::create_wav(path, 8000, 1).unwrap();
+        assert!(stereo_clip().append_to(&mut writer).is_err());
+        drop(writer);
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn load_mono_16_pcm_resource() {
+        let clip =
+            AudioClip::load("resources/mono_16PCM_440hz_8000sps.wav").unwrap();
+        assert_eq!(1, clip.channels);
+        assert_eq!(8000, clip.sample_rate);
+        assert!(clip.samples.iter().all(|s| s.abs() <= 1.0));
+    }
+
+    #[test]
+    fn duration_is_rounded_up() {
+        assert_eq!(1, stereo_clip().duration());
+        let clip = AudioClip::from_channels(2, &[vec![0.0; 4]]);
+        assert_eq!(2, clip.duration());
+    }
+
+    #[test]
+    fn routing_without_selection_uses_every_channel() {
+        assert_eq!(vec![Some(0); 3], routing(1, &[], 3).unwrap());
+        assert_eq!(vec![Some(0), Some(1), None], routing(2, &[], 3).unwrap());
+    }
+
+    #[test]
+    fn routing_with_selection() {
+        assert_eq!(vec![None, Some(0), Some(0)], routing(1, &[1, 2], 3).unwrap());
+        assert_eq!(vec![Some(1), None, Some(0)], routing(2, &[2, 0], 3).unwrap());
+        assert!(routing(1, &[3], 3).is_err());
+    }
+
+    #[test]
+    fn check_routing_before_recording() {
+        assert_eq!(Ok(()), AudioClip::check_routing(2, &[0, 1], 2));
+        assert!(AudioClip::check_routing(2, &[0, 4], 2).is_err());
+    }
+
+    #[test]
+    fn write_output_data_follows_routes() {
+        let state = Arc::new(Mutex::new(Some((0, stereo_clip()))));
+        let routes = vec![Some(1), None, Some(0)];
+        let mut output = vec![9.0; 12];
+        write_output_data(&mut output, &routes, &state);
+        assert_eq!(
+            vec![-1.0, 0.0, 1.0, -2.0, 0.0, 2.0, -3.0, 0.0, 3.0, 0.0, 0.0, 0.0],
+            output
+        );
+        assert_eq!(4, state.lock().unwrap().as_ref().unwrap().0);
+    }
+}
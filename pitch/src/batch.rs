@@ -0,0 +1,383 @@
+use clap::ValueEnum;
+use serde::Serialize;
+use std::collections::HashSet;
+use std::fmt;
+use std::path::Path;
+use std::str::FromStr;
+use std::time::Instant;
+use crate::pool::WorkerPool;
+
+/// Default template of the names of the output files
+pub const DEFAULT_TEMPLATE: &str = "{stem}_{algo}_{shift}_{fsize}_{hopa}.wav";
+
+/// Transposers of batches
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq)]
+pub enum Algorithm {
+    /// the basic transposer, which shifts by whole semi tons only
+    Basic,
+    /// the phase vocoder, swept over the frame sizes and shifts between
+    /// frames
+    PhaseVocoder,
+}
+
+impl Algorithm {
+
+    /// Returns the name of the algorithm, as written on the command line
+    pub fn name(&self) -> String {
+        self.to_possible_value()
+            .map_or(String::new(), |v| v.get_name().to_string())
+    }
+}
+
+/// Pitch shift of a transposition
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Shift {
+    /// a number of semi tons, negative to lower the pitch
+    Semitones(f32),
+    /// a scale factor of the frequencies
+    Ratio(f32),
+}
+
+impl Shift {
+
+    /// Returns the shift in semi tons
+    pub fn semitones(&self) -> f32 {
+        match *self {
+            Shift::Semitones(semitones) => semitones,
+            Shift::Ratio(ratio) => 12.0 * ratio.log2(),
+        }
+    }
+
+    /// Returns the shift as a scale factor of the frequencies
+    pub fn ratio(&self) -> f32 {
+        match *self {
+            Shift::Semitones(semitones) => 2.0f32.powf(semitones / 12.0),
+            Shift::Ratio(ratio) => ratio,
+        }
+    }
+}
+
+impl fmt::Display for Shift {
+
+    /// Writes semi tons as a number and ratios prefixed by `x`
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Shift::Semitones(semitones) => write!(f, "{}", semitones),
+            Shift::Ratio(ratio) => write!(f, "x{}", ratio),
+        }
+    }
+}
+
+/// Shifts in semi tons, from `from` to `to` included by steps of `step`
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct ShiftRange {
+    pub from: f32,
+    pub to: f32,
+    pub step: f32, // positive, even if `to` is lower than `from`
+}
+
+impl FromStr for ShiftRange {
+    type Err = &'static str;
+
+    /// Parses a shift written `<SEMITONES>`, or a range written
+    /// `<FROM>..<TO>[:<STEP>]`, the step being 1 by default
+    fn from_str(s: &str) -> Result<ShiftRange, Self::Err> {
+        let number = |n: &str| n.trim().parse::<f32>().map_err(|_| "Expected semi tons");
+        let (bounds, step) = match s.split_once(':') {
+            Some((bounds, step)) => (bounds, number(step)?),
+            None => (s, 1.0),
+        };
+        if step.is_nan() || step <= 0.0 {
+            return Err("The step of a range of shifts must be positive");
+        }
+        let (from, to) = match bounds.split_once("..") {
+            Some((from, to)) => (number(from)?, number(to)?),
+            None => {
+                let shift = number(bounds)?;
+                (shift, shift)
+            },
+        };
+        Ok(ShiftRange { from, to, step })
+    }
+}
+
+impl ShiftRange {
+
+    /// Returns the shifts of the range
+    pub fn shifts(&self) -> Vec<Shift> {
+        let direction = if self.to < self.from { -1.0 } else { 1.0 };
+        // tolerates rounding errors on the last step
+        let count = ((self.to - self.from).abs() / self.step + 1e-4).floor() as usize + 1;
+        (0..count)
+            .map(|i| {
+                let semitones = self.from + direction * i as f32 * self.step;
+                Shift::Semitones((semitones * 1e4).round() / 1e4)
+            })
+            .collect()
+    }
+}
+
+/// Transposition of a batch
+#[derive(Clone, Debug, PartialEq)]
+pub struct Job {
+    pub input: String,        // path to the .wav file to transpose
+    pub output: String,       // name of the output file
+    pub algorithm: Algorithm,
+    pub shift: Shift,
+    pub fsize: Option<usize>, // frame size, None for the basic transposer
+    pub hopa: Option<usize>,  // shift between frames, None for the basic transposer
+}
+
+/// Result of a job, as written in the summary manifest
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct JobReport {
+    pub input: String,
+    pub output: String,
+    pub algorithm: String,
+    pub shift: String,
+    pub semitones: f32,
+    pub ratio: f32,
+    pub fsize: Option<usize>,
+    pub hopa: Option<usize>,
+    pub seconds: f32,          // time taken by the transposition
+    pub error: Option<String>,
+}
+
+/// Summary manifest of a batch
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct BatchSummary {
+    pub failed: usize,
+    pub jobs: Vec<JobReport>,
+}
+
+/// Returns the paths matched by `patterns`, in their order. Patterns without
+/// glob characters are kept as they are, others must match a file.
+pub fn expand_inputs(patterns: &[String]) -> Result<Vec<String>, &'static str> {
+    let mut inputs = Vec::new();
+    for pattern in patterns {
+        if !pattern.contains(['*', '?', '[']) {
+            inputs.push(pattern.clone());
+            continue;
+        }
+        let paths = match glob::glob(pattern) {
+            Ok(paths) => paths,
+            Err(_) => return Err("Invalid glob pattern"),
+        };
+        let matched: Vec<String> = paths
+            .filter_map(Result::ok)
+            .map(|p| p.to_string_lossy().into_owned())
+            .collect();
+        if matched.is_empty() {
+            return Err("A glob pattern matches no file");
+        }
+        inputs.extend(matched);
+    }
+    Ok(inputs)
+}
+
+/// Returns the name of the output of a job, replacing in `template`
+/// `{stem}` by the name of its input without extension, `{algo}` by its
+/// algorithm, `{shift}` by its shift and `{fsize}`, `{hopa}` by its frame
+/// size and shift between frames (0 for the basic transposer)
+pub fn output_name(
+    template: &str,
+    input: &str,
+    algorithm: Algorithm,
+    shift: Shift,
+    fsize: Option<usize>,
+    hopa: Option<usize>
+) -> Result<String, &'static str> {
+    let stem = Path::new(input)
+        .file_stem()
+        .map_or(String::new(), |s| s.to_string_lossy().into_owned());
+    let name = template
+        .replace("{stem}", &stem)
+        .replace("{algo}", &algorithm.name())
+        .replace("{shift}", &shift.to_string())
+        .replace("{fsize}", &fsize.unwrap_or(0).to_string())
+        .replace("{hopa}", &hopa.unwrap_or(0).to_string());
+    if name.contains(['{', '}']) {
+        return Err("Unknown placeholder in the template of output names");
+    }
+    Ok(name)
+}
+
+/// Returns the jobs transposing each input with each algorithm by each
+/// shift, the phase vocoder with each frame size and shift between frames.
+/// Jobs writing to the same output are refused.
+pub fn plan(
+    inputs: &[String],
+    algorithms: &[Algorithm],
+    shifts: &[Shift],
+    fsizes: &[usize],
+    hopas: &[usize],
+    template: &str
+) -> Result<Vec<Job>, &'static str> {
+    let mut jobs = Vec::new();
+    let mut outputs = HashSet::new();
+    for input in inputs {
+        for &algorithm in algorithms {
+            let sweep: Vec<(Option<usize>, Option<usize>)> = match algorithm {
+                Algorithm::Basic => vec![(None, None)],
+                Algorithm::PhaseVocoder => fsizes
+                    .iter()
+                    .flat_map(|f| hopas.iter().map(move |h| (Some(*f), Some(*h))))
+                    .collect(),
+            };
+            for &shift in shifts {
+                for &(fsize, hopa) in &sweep {
+                    let output =
+                        output_name(template, input, algorithm, shift, fsize, hopa)?;
+                    if !outputs.insert(output.clone()) {
+                        return Err("Several jobs write the same output file");
+                    }
+                    let input = input.clone();
+                    jobs.push(Job { input, output, algorithm, shift, fsize, hopa });
+                }
+            }
+        }
+    }
+    Ok(jobs)
+}
+
+/// Runs `jobs` with `transpose` on the workers of `pool`, which also
+/// analyze and synthesize the frames of each job, and returns their reports
+/// in the order of the jobs
+pub fn run<F>(jobs: &[Job], pool: &WorkerPool, transpose: F) -> BatchSummary
+where
+    F: Fn(&Job) -> Result<(), &'static str> + Sync,
+{
+    let jobs = pool.map(jobs, || (), |_, _, job| {
+        let start = Instant::now();
+        let result = transpose(job);
+        JobReport {
+            input: job.input.clone(),
+            output: job.output.clone(),
+            algorithm: job.algorithm.name(),
+            shift: job.shift.to_string(),
+            semitones: job.shift.semitones(),
+            ratio: job.shift.ratio(),
+            fsize: job.fsize,
+            hopa: job.hopa,
+            seconds: start.elapsed().as_secs_f32(),
+            error: result.err().map(str::to_string),
+        }
+    });
+    BatchSummary { failed: jobs.iter().filter(|j| j.error.is_some()).count(), jobs }
+}
+
+#[cfg(test)]
+mod batch_tests {
+    use super::*;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    fn semitones(shifts: &[Shift]) -> Vec<f32> {
+        shifts.iter().map(Shift::semitones).collect()
+    }
+
+    #[test]
+    fn parse_shift_ranges() {
+        let range: ShiftRange = "-12..12".parse().unwrap();
+        assert_eq!(25, range.shifts().len());
+        assert_eq!(Shift::Semitones(-12.0), range.shifts()[0]);
+        assert_eq!(Shift::Semitones(12.0), range.shifts()[24]);
+        let range: ShiftRange = "1..0:0.25".parse().unwrap();
+        assert_eq!(vec![1.0, 0.75, 0.5, 0.25, 0.0], semitones(&range.shifts()));
+        let range: ShiftRange = "0..1:0.3".parse().unwrap();
+        assert_eq!(vec![0.0, 0.3, 0.6, 0.9], semitones(&range.shifts()));
+        let single: ShiftRange = "-3.5".parse().unwrap();
+        assert_eq!(vec![-3.5], semitones(&single.shifts()));
+        assert!("1..2:0".parse::<ShiftRange>().is_err());
+        assert!("1..a".parse::<ShiftRange>().is_err());
+        assert!("".parse::<ShiftRange>().is_err());
+    }
+
+    #[test]
+    fn shifts_as_ratios_and_names() {
+        assert!((Shift::Semitones(12.0).ratio() - 2.0).abs() < 1e-6);
+        assert!((Shift::Ratio(0.5).semitones() + 12.0).abs() < 1e-6);
+        assert_eq!("-2", Shift::Semitones(-2.0).to_string());
+        assert_eq!("x1.5", Shift::Ratio(1.5).to_string());
+        let name = output_name(
+            DEFAULT_TEMPLATE,
+            "resources/take.wav",
+            Algorithm::PhaseVocoder,
+            Shift::Semitones(-2.0),
+            Some(1024),
+            Some(256)
+        );
+        assert_eq!(Ok(String::from("take_phase-vocoder_-2_1024_256.wav")), name);
+        let basic = output_name(
+            "{stem}_{algo}_{shift}.wav",
+            "take.wav",
+            Algorithm::Basic,
+            Shift::Ratio(2.0),
+            None,
+            None
+        );
+        assert_eq!(Ok(String::from("take_basic_x2.wav")), basic);
+        let template = "{stem}_{rate}.wav";
+        let ratio = Shift::Ratio(2.0);
+        let basic = Algorithm::Basic;
+        let unknown = output_name(template, "take.wav", basic, ratio, None, None);
+        assert!(unknown.is_err());
+    }
+
+    #[test]
+    fn plan_sweeps() {
+        let inputs = [String::from("a.wav"), String::from("b.wav")];
+        let algorithms = [Algorithm::Basic, Algorithm::PhaseVocoder];
+        let shifts = [Shift::Semitones(-1.0), Shift::Semitones(1.0)];
+        let fsizes = [512, 1024];
+        let (hopas, template) = ([128], DEFAULT_TEMPLATE);
+        let jobs = plan(&inputs, &algorithms, &shifts, &fsizes, &hopas, template);
+        let jobs = jobs.unwrap();
+        // per input, 2 basic jobs and 2 * 2 phase vocoder jobs
+        assert_eq!(12, jobs.len());
+        assert_eq!("a_basic_-1_0_0.wav", jobs[0].output);
+        assert_eq!("a_phase-vocoder_1_1024_128.wav", jobs[5].output);
+        assert_eq!("b.wav", jobs[6].input);
+        // the frame sizes are not in the names
+        let template = "{stem}_{shift}.wav";
+        let same = plan(&inputs, &algorithms, &shifts, &fsizes, &hopas, template);
+        assert!(same.is_err());
+    }
+
+    #[test]
+    fn expand_glob_patterns() {
+        let dir = std::env::temp_dir().join("pitch_batch_inputs");
+        std::fs::create_dir_all(&dir).unwrap();
+        for name in ["b.wav", "a.wav", "c.txt"] {
+            std::fs::write(dir.join(name), "").unwrap();
+        }
+        let pattern = dir.join("*.wav").to_string_lossy().into_owned();
+        let inputs = expand_inputs(&[pattern, String::from("other.wav")]).unwrap();
+        let names: Vec<&str> = inputs
+            .iter()
+            .map(|p| Path::new(p).file_name().unwrap().to_str().unwrap())
+            .collect();
+        assert_eq!(vec!["a.wav", "b.wav", "other.wav"], names);
+        let none = dir.join("*.flac").to_string_lossy().into_owned();
+        assert!(expand_inputs(&[none]).is_err());
+    }
+
+    #[test]
+    fn run_jobs_in_parallel() {
+        let inputs: Vec<String> = (0..10).map(|i| format!("{}.wav", i)).collect();
+        let shifts = [Shift::Semitones(0.0), Shift::Ratio(2.0)];
+        let basic = [Algorithm::Basic];
+        let jobs = plan(&inputs, &basic, &shifts, &[], &[], DEFAULT_TEMPLATE).unwrap();
+        let done = AtomicUsize::new(0);
+        let summary = run(&jobs, &WorkerPool::new(4).unwrap(), |job| {
+            done.fetch_add(1, Ordering::SeqCst);
+            if job.shift == Shift::Ratio(2.0) { Err("failed") } else { Ok(()) }
+        });
+        assert_eq!(20, done.load(Ordering::SeqCst));
+        assert_eq!(10, summary.failed);
+        for (job, report) in jobs.iter().zip(&summary.jobs) {
+            assert_eq!(job.output, report.output);
+            assert_eq!(job.shift == Shift::Ratio(2.0), report.error.is_some());
+        }
+        assert_eq!(12.0, summary.jobs[1].semitones);
+    }
+}
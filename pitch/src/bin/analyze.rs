@@ -0,0 +1,9 @@
+use pitch::config::anconfig::AnalyzeConfiguration;
+use clap::Parser;
+
+fn main() {
+    match pitch::analyze(AnalyzeConfiguration::parse()) {
+        Ok(()) => println!("Successfully analyzed signal !"),
+        Err(why) => println!("main() failed: {}", why),
+    }
+}
@@ -0,0 +1,9 @@
+use pitch::config::genconfig::GenerateConfiguration;
+use clap::Parser;
+
+fn main() {
+    match pitch::generate(GenerateConfiguration::parse()) {
+        Ok(()) => println!("Successfully generated signal !"),
+        Err(why) => println!("main() failed: {}", why),
+    }
+}
@@ -1,61 +1,248 @@
 use pitch;
 use pitch::config::ptconfig::PitchCli;
 use pitch::config::ptconfig::PitchSubcommand;
-use pitch::audio::audio_clip::AudioClip;
+use pitch::config::ptconfig::RealTimeSubCommand;
+use pitch::audio::audio_clip::{AudioClip, ClipWriter};
+use pitch::control::osc::OscServer;
+use pitch::control::params::LiveParameters;
+use pitch::mix::OutputStage;
+use pitch::vocoder::Vocoder;
+use std::sync::Arc;
 use clap::Parser;
+use cpal::{Device, SampleRate, SupportedStreamConfig};
 use cpal::traits::{HostTrait, DeviceTrait};
 
 fn main() {
 
     let command = PitchCli::parse();
+    if let Some(threads) = command.threads {
+        if let Err(why) = pitch::pool::set_threads(threads) {
+            panic!("main(): {}", why)
+        }
+    }
     match command.subcommand {
-       PitchSubcommand::Static { static_subcommand } =>
-            match pitch::transpose_pitch(static_subcommand) {
+       PitchSubcommand::Static { output, static_subcommand } =>
+            match output
+                .stage()
+                .and_then(|output| pitch::transpose_pitch(static_subcommand, &output))
+            {
                 Ok(()) => println!("Successfully transposed signal !"),
                 Err(why) => println!("main() failed: {}", why)
             },
-        PitchSubcommand::RealTime { real_time } => {
-            // provides access to available audio devices on system
+        PitchSubcommand::RealTime {
+            real_time, in_channels, out_channels, record_to, osc_port, osc_host, output
+        } => {
+            let output = match output.stage() {
+                Ok(output) => output,
+                Err(why) => panic!("main(): {}", why)
+            };
+            let params = Arc::new(LiveParameters::new(&real_time));
+            if let Some(port) = osc_port {
+                let server = match OscServer::bind(&format!("{}:{}", osc_host, port)) {
+                    Ok(server) => server,
+                    Err(why) => panic!("main(): {}", why)
+                };
+                println!("{}OSC server listening on {}:{}", AudioClip::STATUS, osc_host, port);
+                server.spawn(params.clone());
+            }
+            real_time_loop(&params, &in_channels, &out_channels, record_to, &output)
+        },
+        PitchSubcommand::Batch { output, batch } => {
+            let summary = match output
+                .stage()
+                .and_then(|output| pitch::transpose_batch(&batch, &output))
+            {
+                Ok(summary) => summary,
+                Err(why) => panic!("main(): {}", why)
+            };
+            for job in summary.jobs.iter().filter(|j| j.error.is_some()) {
+                println!("{}: {}", job.output, job.error.as_deref().unwrap_or_default());
+            }
+            println!(
+                "Transposed {} of {} signals, summary in {}",
+                summary.jobs.len() - summary.failed,
+                summary.jobs.len(),
+                pitch::output_path(&batch.manifest)
+            );
+            if summary.failed > 0 {
+                std::process::exit(1);
+            }
+        },
+        PitchSubcommand::Record { seconds, out_filename } => {
             let host = cpal::default_host();
-
-            // input/ouput stream devices
             let idev = host.default_input_device().expect("no input found");
-            let odev = host.default_input_device().expect("no output found");
-
-            // input/ouput devices configuration
             let iconf = idev.default_input_config().expect("no conf found");
-            let oconf = odev.default_output_config().expect("no conf found");
+            let clip = AudioClip::record(&idev, iconf, seconds);
+            match clip.save_wav(&pitch::output_path(&out_filename)) {
+                Ok(()) => println!("Successfully recorded signal !"),
+                Err(why) => println!("main() failed: {}", why)
+            }
+        },
+        PitchSubcommand::Play { path, out_channels } => {
+            let clip = match AudioClip::load(&path) {
+                Ok(clip) => clip,
+                Err(why) => panic!("main(): {}", why)
+            };
+            let host = cpal::default_host();
+            let odev = host.default_output_device().expect("no output found");
+            let oconf = output_config(&odev, clip.sample_rate);
+            println!("{}Listen...", AudioClip::STATUS);
+            if let Err(why) = clip.play(&odev, oconf, clip.duration(), &out_channels) {
+                println!("main() failed: {}", why)
+            }
+        }
+    }
+}
+
+/// Returns an output configuration of `odev` playing `f32` samples at
+/// `sample_rate` if there is one, its default output configuration otherwise
+fn output_config(odev: &Device, sample_rate: u32) -> SupportedStreamConfig {
+    let rate = SampleRate(sample_rate);
+    if let Ok(mut configs) = odev.supported_output_configs() {
+        let supported = configs.find(|c| {
+            c.sample_format() == cpal::SampleFormat::F32
+                && c.min_sample_rate() <= rate
+                && rate <= c.max_sample_rate()
+        });
+        if let Some(conf) = supported {
+            return conf.with_sample_rate(rate);
+        }
+    }
+    odev.default_output_config().expect("no conf found")
+}
 
-            // all of this in an infinite loop
-            // record clip
-            let len = 5;
+/// Creates the .wav file named `name` in the outputs directory
+fn create_output_wav(name: &str, sample_rate: u32, channels: u16) -> ClipWriter {
+    let path = pitch::output_path(name);
+    match AudioClip::create_wav(&path, sample_rate, channels) {
+        Ok(writer) => writer,
+        Err(why) => panic!("main(): {}", why)
+    }
+}
 
-            println!("Stop program with C-c");
+/// Records clips from the microphone, transposes them with the current live
+/// parameters, passes them through the `output` stage and plays them back,
+/// until the program is stopped. If `record_to` is given, the dry input and
+/// the processed output are also written to disk.
+fn real_time_loop(
+    params: &LiveParameters,
+    in_channels: &[usize],
+    out_channels: &[usize],
+    record_to: Option<String>,
+    output: &OutputStage
+) {
+    // provides access to available audio devices on system
+    let host = cpal::default_host();
 
-            loop {
-                let clip = AudioClip::record(&idev, iconf.clone(), len);
+    // input/ouput stream devices
+    let idev = host.default_input_device().expect("no input found");
+    let odev = host.default_output_device().expect("no output found");
 
-                // transpose clip
-                let transposition_res = pitch::transpose_pitch_real_time(
-                    &real_time,
-                    clip.sample_rate as f32,
-                    &clip.samples[..]
-                );
+    // input/ouput devices configuration
+    let iconf = idev.default_input_config().expect("no conf found");
+    let oconf = odev.default_output_config().expect("no conf found");
 
-                let data: Vec<f32> = match transposition_res {
-                    Ok(data) => data,
-                    Err(why) => panic!("main(): {}", why)
-                };
+    // the output channels are checked before anything is recorded
+    let nbc = in_channels.len() as u16;
+    if let Err(why) = AudioClip::check_routing(nbc, out_channels, oconf.channels()) {
+        panic!("main(): {}", why);
+    }
 
-                let transformed_clip = AudioClip {
-                    sample_rate: clip.sample_rate,
-                    samples: data
-                };
+    // dry/wet output files
+    let mut writers = record_to.map(|name| {
+        let rate = iconf.sample_rate().0;
+        let nbc = in_channels.len() as u16;
+        (
+            create_output_wav(&format!("{}_dry.wav", name), rate, nbc),
+            create_output_wav(&format!("{}_wet.wav", name), rate, nbc)
+        )
+    });
+
+    // all of this in an infinite loop
+    // record clip
+    let len = 5;
+
+    println!("Stop program with C-c");
+
+    // processing contexts of the phase vocoder and transposed signals, one
+    // per channel
+    let mut vocoders: Vec<Option<Vocoder>> = Vec::new();
+    let mut transposed: Vec<Vec<f32>> = Vec::new();
+
+    loop {
+        let clip = AudioClip::record(&idev, iconf.clone(), len);
+        let clip = match clip.select_channels(in_channels) {
+            Ok(clip) => clip,
+            Err(why) => panic!("main(): {}", why)
+        };
 
-                // playback clip
-                println!("{}Listen...", AudioClip::STATUS);                
-                transformed_clip.play(&odev, oconf.clone(), len);
+        // transpose each selected channel, unless bypassed
+        let transformed_clip = if params.bypass() {
+            clip.clone()
+        } else {
+            transpose_clip(
+                &params.snapshot(),
+                &clip,
+                output,
+                &mut vocoders,
+                &mut transposed
+            )
+        };
+
+        // save dry and wet clips
+        if let Some((dry, wet)) = writers.as_mut() {
+            let res = clip
+                .append_to(dry)
+                .and_then(|_| transformed_clip.append_to(wet));
+            if let Err(why) = res {
+                panic!("main(): {}", why);
             }
         }
+
+        // playback clip
+        println!("{}Listen...", AudioClip::STATUS);                
+        if let Err(why) = transformed_clip.play(&odev, oconf.clone(), len, out_channels) {
+            panic!("main(): {}", why);
+        }
+    }
+}
+
+/// Transposes each channel of `clip` with the given configuration and passes
+/// it through the `output` stage, reusing the processing contexts of
+/// `vocoders` and the buffers of `transposed`. If a channel can not be
+/// transposed, the error is printed and `clip` is returned unchanged.
+fn transpose_clip(
+    real_time: &RealTimeSubCommand,
+    clip: &AudioClip,
+    output: &OutputStage,
+    vocoders: &mut Vec<Option<Vocoder>>,
+    transposed: &mut Vec<Vec<f32>>
+) -> AudioClip {
+    let mut data: Vec<Vec<f32>> = Vec::with_capacity(clip.channels as usize);
+    vocoders.resize_with(clip.channels as usize, || None);
+    transposed.resize_with(clip.channels as usize, Vec::new);
+    let contexts = vocoders.iter_mut().zip(transposed.iter_mut());
+    for (channel, (vocoder, transposed)) in clip.to_channels().into_iter().zip(contexts) {
+        let transposition_res = pitch::transpose_pitch_real_time_with(
+            vocoder,
+            real_time,
+            clip.sample_rate as f32,
+            &channel[..],
+            transposed
+        );
+
+        match transposition_res {
+            Ok(()) => {
+                let samp_rate = clip.sample_rate as f32;
+                data.push(output.process(&channel, transposed, 1, samp_rate, 1.0))
+            },
+            // e.g a frame size longer than the clip, the clip is played as is
+            Err(why) => {
+                eprintln!("transposition failed: {}", why);
+                return clip.clone();
+            },
+        };
     }
+
+    AudioClip::from_channels(clip.sample_rate, &data)
 }
@@ -1,19 +1,108 @@
 use pitch::config::qcconfig;
 use crate::qcconfig::QCConfiguration;
+use pitch::accuracy::PitchAccuracy;
+use pitch::loudness::Loudness;
+use pitch::report::{BatchReport, Manifest};
 use clap::Parser;
 
 fn main() {
-    match pitch::check_quality(QCConfiguration::parse()) {
+    let config = QCConfiguration::parse();
+    if let Some(path) = config.manifest() {
+        match Manifest::load(path) {
+            Ok(manifest) => print_batch(&manifest.run(), config.json()),
+            Err(why) => {
+                println!("main() failed: {}", why);
+                std::process::exit(2);
+            },
+        }
+        return;
+    }
+    if config.json() {
+        let report = config.case().unwrap().run();
+        println!("{}", serde_json::to_string_pretty(&report).unwrap());
+        if !report.passed {
+            std::process::exit(1);
+        }
+        return;
+    }
+    if config.loudness() {
+        let paths = [config.path_to_signal(), config.path_to_oracle()];
+        for path in paths.into_iter().flatten() {
+            match pitch::measure_loudness(path) {
+                Ok(l) => print_loudness(path, &l),
+                Err(why) => fail(why),
+            }
+        }
+    }
+    if config.pitch_reference().is_some() {
+        let threshold = config.threshold();
+        match pitch::check_pitch(config) {
+            Ok(accuracy) => print_pitch(&accuracy, threshold),
+            Err(why) => fail(why),
+        }
+        return;
+    }
+    match pitch::check_quality(config) {
         Ok((t, d)) => print_results(t, d),
-        Err(why) => println!("main() failed: {}", why),
+        Err(why) => fail(why),
+    }
+}
+
+/// Prints why the check could not be done and exits with status 2
+fn fail(why: &str) {
+    println!("main() failed: {}", why);
+    std::process::exit(2);
+}
+
+/// Prints the report of a manifest, in JSON if `json` is true, and exits
+/// with status 1 if a case failed
+fn print_batch(report: &BatchReport, json: bool) {
+    if json {
+        println!("{}", serde_json::to_string_pretty(report).unwrap());
+    } else {
+        for case in &report.cases {
+            let name = case.name.as_deref().unwrap_or(&case.signal);
+            let verdict = if case.passed { "passed" } else { "FAILED" };
+            match &case.error {
+                Some(why) => println!("{}: {} ({})", name, verdict, why),
+                None => println!("{}: {}", name, verdict),
+            }
+        }
+        println!("{} cases, {} failed", report.cases.len(), report.failed);
+    }
+    if !report.passed {
+        std::process::exit(1);
+    }
+}
+
+/// Prints the loudness of the signal at `path`
+fn print_loudness(path: &str, loudness: &Loudness) {
+    println!(
+        "{}: {:.1} LUFS, true peak {:.1} dBTP",
+        path, loudness.integrated, loudness.true_peak
+    );
+}
+
+/// Prints the results of the pitch check against `threshold` cents, and exits
+/// with status 1 if it failed
+fn print_pitch(accuracy: &PitchAccuracy, threshold: f32) {
+    let verdict = if accuracy.passes(threshold) { "Good" } else { "Bad" };
+    println!(
+        "{} pitch: mean deviation {:.1} cents, max {:.1} cents over {} frames",
+        verdict, accuracy.mean, accuracy.max, accuracy.frames
+    );
+    if !accuracy.passes(threshold) {
+        std::process::exit(1);
     }
 }
 
-/// Prints the results of the quality check
+/// Prints the results of the quality check, and exits with status 1 if it
+/// failed
 fn print_results(test: bool, distance: f32) {
     if test {
         println!("Good quality: {}", distance);
     } else {
         println!("Bad quality: {}", distance);
+        std::process::exit(1);
     }
 }
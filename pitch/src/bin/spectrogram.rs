@@ -0,0 +1,9 @@
+use pitch::config::spconfig::SpectrogramConfiguration;
+use clap::Parser;
+
+fn main() {
+    match pitch::spectrogram(SpectrogramConfiguration::parse()) {
+        Ok(()) => println!("Successfully drew spectrogram !"),
+        Err(why) => println!("main() failed: {}", why),
+    }
+}
@@ -3,3 +3,12 @@ pub mod ptconfig;
 
 /// Quality checker configuration module
 pub mod qcconfig;
+
+/// Analyzer configuration module
+pub mod anconfig;
+
+/// Test signal generator configuration module
+pub mod genconfig;
+
+/// Spectrogram configuration module
+pub mod spconfig;
@@ -0,0 +1,81 @@
+use clap::Parser;
+use crate::analysis;
+use crate::config::ptconfig::{PreprocessingOptions, SmoothingOptions};
+
+/// Analyzer configuration
+#[derive(Parser)]
+#[command(author, version = "v1.0")]
+pub struct AnalyzeConfiguration {
+    /// the path to the .wav file to analyze.
+    #[arg(short,long)]
+    pub in_path: String,
+
+    /// the frame size to use
+    #[arg(short,long, default_value_t = 2048)]
+    pub fsize: usize,
+
+    /// the shift between frames
+    #[arg(long, default_value_t = 512)]
+    pub hopa: usize,
+
+    /// the name of the MIDI file in which the melody transcribed from the
+    /// .wav file is written.
+    #[arg(long)]
+    pub midi: Option<String>,
+
+    /// the name of the file in which the pitch track is written, as JSON if
+    /// it ends with .json and as CSV otherwise. It holds, for each frame,
+    /// what the pitch detector saw and the scale factor the static phase
+    /// vocoder applies with the same frame size, shift, curve, MIDI, voicing
+    /// and smoothing options.
+    #[arg(long)]
+    pub pitch_track: Option<String>,
+
+    /// the path to the pitch curve the static phase vocoder follows, if any
+    #[arg(long, conflicts_with = "melody")]
+    pub curve: Option<String>,
+
+    /// the path to the MIDI file the static phase vocoder follows, if any
+    #[arg(long)]
+    pub melody: Option<String>,
+
+    /// the track of the melody MIDI file to follow, from 0 (all by default)
+    #[arg(long, requires = "melody")]
+    pub melody_track: Option<usize>,
+
+    /// the channel of the melody MIDI file to follow, from 1 to 16 (all by
+    /// default)
+    #[arg(long, requires = "melody")]
+    pub melody_channel: Option<u8>,
+
+    /// the time, in seconds, at which the melody starts in the .wav file
+    #[arg(long, default_value_t = 0.0, allow_negative_numbers = true)]
+    pub melody_offset: f32,
+
+    /// the minimal duration of a transcribed note, in seconds
+    #[arg(long, default_value_t = 0.06)]
+    pub min_note: f32,
+
+    /// the level, in dBFS, under which frames are considered silent
+    #[arg(
+        long,
+        default_value_t = analysis::DEFAULT_SILENCE,
+        allow_negative_numbers = true
+    )]
+    pub silence: f32,
+
+    /// the voicing confidence, between 0.0 and 1.0, from which frames are
+    /// voiced, i.e hold a note and are corrected by the phase vocoder
+    #[arg(long, default_value_t = analysis::DEFAULT_VOICING_THRESHOLD)]
+    pub voicing: f32,
+
+    #[command(flatten)]
+    pub smoothing: SmoothingOptions,
+
+    #[command(flatten)]
+    pub preprocessing: PreprocessingOptions,
+
+    /// the vibrato depth of the static phase vocoder, if any
+    #[arg(long)]
+    pub vibrato_depth: Option<f32>,
+}
@@ -0,0 +1,30 @@
+use clap::Parser;
+use crate::generate::Signal;
+
+/// Test signal generator configuration
+#[derive(Parser)]
+#[command(author, version = "v1.0")]
+pub struct GenerateConfiguration {
+    /// the name of the .wav file in which the signal is written.
+    #[arg(short,long)]
+    pub out_filename: String,
+
+    /// the sample rate, in Hz
+    #[arg(short,long, default_value_t = 44100)]
+    pub samp_rate: u32,
+
+    /// the bits per sample: 16 or 24 bits PCM, or 32 bits float
+    #[arg(short,long, default_value_t = 16)]
+    pub bit_depth: u16,
+
+    /// the duration, in seconds
+    #[arg(short,long, default_value_t = 1.0)]
+    pub duration: f32,
+
+    /// the peak amplitude, from 0.0 to 1.0 (full scale)
+    #[arg(short,long, default_value_t = 0.5)]
+    pub amplitude: f32,
+
+    #[command(subcommand)]
+    pub signal: Signal,
+}
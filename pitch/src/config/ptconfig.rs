@@ -1,21 +1,99 @@
-use clap::{Parser,Subcommand};
+use clap::{Args,Parser,Subcommand,ValueEnum};
+use crate::analysis;
+use crate::batch::{self, Algorithm, Shift, ShiftRange};
+use crate::analysis::smoothing::Smoothing;
+use crate::analysis::preprocess::{NoiseGate, Preprocessing};
+use crate::harmonizer::Voice;
+use crate::mix::OutputStage;
+use crate::loudness::Target;
 
 #[derive(Parser)]
 #[command(author, version = "v1.0")]
 pub struct PitchCli {
+    /// the number of worker threads analyzing and synthesizing the frames of
+    /// the phase vocoder, one per processor by default. With 1, frames are
+    /// processed on the calling thread.
+    #[arg(long, global = true)]
+    pub threads: Option<usize>,
+
     #[command(subcommand)]
     pub subcommand: PitchSubcommand
 }
 #[derive(Subcommand)]
 pub enum  PitchSubcommand {
     Static {
+        #[command(flatten)]
+        output: OutputOptions,
+
         #[command(subcommand)]
         static_subcommand : PTConfiguration
     },
 
     RealTime {
+        /// the input channels to process, separated by commas. Channels
+        /// are numbered from 0, each one is processed independently.
+        #[arg(long, value_delimiter = ',', default_value = "0")]
+        in_channels: Vec<usize>,
+
+        /// the output channels on which processed channels are played,
+        /// separated by commas. Channels are numbered from 0. If absent,
+        /// every output channel is used.
+        #[arg(long, value_delimiter = ',')]
+        out_channels: Vec<usize>,
+
+        /// the name prefix of the output files in which the dry input and
+        /// the processed output are written, as `<NAME>_dry.wav` and
+        /// `<NAME>_wet.wav`.
+        #[arg(long, value_name = "NAME")]
+        record_to: Option<String>,
+
+        /// the UDP port on which an OSC server listens for live parameter
+        /// changes. No server is started if absent.
+        #[arg(long)]
+        osc_port: Option<u16>,
+
+        /// the address on which the OSC server listens.
+        #[arg(long, default_value = "127.0.0.1")]
+        osc_host: String,
+
+        #[command(flatten)]
+        output: OutputOptions,
+
         #[command(subcommand)]
         real_time : RealTimeSubCommand
+    },
+
+    /// Transposes .wav files by several shifts, with several algorithms and
+    /// parameters, in parallel
+    Batch {
+        #[command(flatten)]
+        output: OutputOptions,
+
+        #[command(flatten)]
+        batch: BatchOptions,
+    },
+
+    /// Records sound from the microphone into a .wav file
+    Record {
+        /// the duration of the recording, in seconds.
+        #[arg(long)]
+        seconds: u64,
+
+        /// the name of the output file.
+        #[arg(short,long)]
+        out_filename: String,
+    },
+
+    /// Plays a .wav file
+    Play {
+        /// the path to the .wav file to play.
+        path: String,
+
+        /// the output channels on which the file channels are played,
+        /// separated by commas. Channels are numbered from 0. If absent,
+        /// every output channel is used.
+        #[arg(long, value_delimiter = ',')]
+        out_channels: Vec<usize>,
     }
 }
 
@@ -43,10 +121,97 @@ pub enum RealTimeSubCommand {
 
         /// Optional if not present autotune
         #[arg(long,short)]
-        shift: Option<f32>
+        shift: Option<f32>,
+
+        /// the key of the musical scale notes are tuned to when autotuning
+        #[arg(long, value_enum, default_value = "C")]
+        key: Key,
+
+        /// the musical scale notes are tuned to when autotuning
+        #[arg(long, value_enum, default_value = "chromatic")]
+        scale: Scale,
+
+        /// the retune speed, between 0.0 (no correction) and 1.0 (notes
+        /// snap to the scale) when autotuning
+        #[arg(long, default_value_t = 1.0)]
+        retune: f32,
+
+        /// the voicing confidence, between 0.0 and 1.0, from which clips are
+        /// autotuned. Silence, breaths and consonants have a low confidence
+        /// and pass through unchanged. 0.0 autotunes every clip.
+        #[arg(long, default_value_t = analysis::DEFAULT_VOICING_THRESHOLD)]
+        voicing: f32,
+
+        #[command(flatten)]
+        preprocessing: PreprocessingOptions,
     },
 }
 
+/// Key of a musical scale, i.e its first note
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq)]
+pub enum Key {
+    #[value(name = "C")]
+    C,
+    #[value(name = "C#")]
+    CSharp,
+    #[value(name = "D")]
+    D,
+    #[value(name = "D#")]
+    DSharp,
+    #[value(name = "E")]
+    E,
+    #[value(name = "F")]
+    F,
+    #[value(name = "F#")]
+    FSharp,
+    #[value(name = "G")]
+    G,
+    #[value(name = "G#")]
+    GSharp,
+    #[value(name = "A")]
+    A,
+    #[value(name = "A#")]
+    ASharp,
+    #[value(name = "B")]
+    B,
+}
+
+impl Key {
+    /// Returns the number of semi tons between C and the key
+    pub fn semitones(&self) -> u8 {
+        *self as u8
+    }
+
+    /// Returns the key that is the given number of semi tons above C
+    pub fn from_semitones(semitones: u8) -> Key {
+        Key::value_variants()[semitones as usize % 12]
+    }
+}
+
+/// Musical scale, i.e the set of notes autotuning can tune to
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq)]
+pub enum Scale {
+    Chromatic,
+    Major,
+    Minor,
+}
+
+impl Scale {
+    /// Returns the scale at index `index` of `Scale::value_variants()`
+    pub fn from_index(index: u8) -> Scale {
+        Scale::value_variants()[index as usize % Scale::value_variants().len()]
+    }
+
+    /// Returns the degrees of the scale, as semi tons above its key
+    pub fn degrees(&self) -> &'static [u8] {
+        match self {
+            Scale::Chromatic => &[0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11],
+            Scale::Major => &[0, 2, 4, 5, 7, 9, 11],
+            Scale::Minor => &[0, 2, 3, 5, 7, 8, 10],
+        }
+    }
+}
+
 
 /// Configurations for static pitch transposers
 #[derive(Subcommand)]
@@ -83,51 +248,406 @@ pub enum PTConfiguration {
         ///the shift between frames
         #[arg(long)]          // must be long to not be mistaken with -h which stands for help
         hopa: usize,          // shift between frames
+
+        /// the path to a target pitch curve to follow, in CSV or JSON (if
+        /// its extension is .json), made of times in seconds and target
+        /// frequencies in Hz (`frequency`) or MIDI cents (`cents`).
+        #[arg(long, conflicts_with = "midi")]
+        curve: Option<String>,
+
+        /// the path to a Standard MIDI File holding the melody to tune to.
+        /// When no note is held, frames are tuned to the nearest note.
+        #[arg(long)]
+        midi: Option<String>,
+
+        /// the track of the MIDI file to read, numbered from 0. Every track
+        /// is read if absent.
+        #[arg(long, requires = "midi")]
+        midi_track: Option<usize>,
+
+        /// the MIDI channel to read, from 1 to 16. Every channel is read if
+        /// absent.
+        #[arg(long, requires = "midi")]
+        midi_channel: Option<u8>,
+
+        /// the time, in seconds, at which the MIDI file starts in the .wav
+        /// file. May be negative.
+        #[arg(
+            long,
+            default_value_t = 0.0,
+            allow_negative_numbers = true,
+            requires = "midi"
+        )]
+        midi_offset: f32,
+
+        /// the voicing confidence, between 0.0 and 1.0, from which frames
+        /// are corrected. Silence, breaths and consonants have a low
+        /// confidence and pass through unchanged. 0.0 corrects every frame.
+        #[arg(long, default_value_t = analysis::DEFAULT_VOICING_THRESHOLD)]
+        voicing: f32,
+
+        #[command(flatten)]
+        smoothing: SmoothingOptions,
+
+        #[command(flatten)]
+        preprocessing: PreprocessingOptions,
+
+        /// corrects only the pitch centre of notes, and keeps their vibrato
+        /// (between 4 and 8 Hz) multiplied by this depth: 0.0 removes it,
+        /// 1.0 keeps it and more exaggerates it. Vibrato is flattened like
+        /// the rest of the pitch if absent.
+        #[arg(long)]
+        vibrato_depth: Option<f32>,
+
+        /// the name of the file in which the pitch track of the frames
+        /// transposed is written, with the scale factor applied to each, as
+        /// JSON if it ends with .json and as CSV otherwise.
+        #[arg(long)]
+        pitch_track: Option<String>,
+    },
+    /// Adds a synthetic vibrato to the pitch, with the phase vocoder
+    Vibrato {
+        /// the path to the .wav file to transform.
+        #[arg(short,long)]
+        in_path: String,      // path to input file
+
+        /// the name of the output file.
+        #[arg(short,long)]
+        out_filename: String, // ouput filename
+
+        /// the frame size to use
+        #[clap(short,long)]
+        fsize: usize,         // frame size
+
+        ///the shift between frames
+        #[arg(long)]          // must be long to not be mistaken with -h which stands for help
+        hopa: usize,          // shift between frames
+
+        /// the number of modulations per second, in Hz
+        #[arg(long, default_value_t = 5.5)]
+        rate: f32,
+
+        /// the largest deviation from the original pitch, in cents
+        #[arg(long, default_value_t = 50.0)]
+        depth: f32,
+
+        /// the time, in seconds, before the vibrato starts
+        #[arg(long, default_value_t = 0.0)]
+        delay: f32,
+
+        /// the time, in seconds, for the vibrato to reach its full depth
+        /// after the delay
+        #[arg(long, default_value_t = 0.0)]
+        fade_in: f32,
+    },
+    /// Mixes harmony voices, shifted from the input, with it in stereo
+    Harmonize {
+        /// the path to the .wav file to transform.
+        #[arg(short,long)]
+        in_path: String,      // path to input file
+
+        /// the name of the output file.
+        #[arg(short,long)]
+        out_filename: String, // ouput filename
+
+        /// the frame size to use
+        #[clap(short,long)]
+        fsize: usize,         // frame size
+
+        ///the shift between frames
+        #[arg(long)]          // must be long to not be mistaken with -h which stands for help
+        hopa: usize,          // shift between frames
+
+        /// a harmony voice, written `<INTERVAL>[,gain=<GAIN>][,pan=<PAN>]
+        /// [,formant=<FACTOR>]`. The interval is in semi tons (e.g `-5`) or
+        /// in degrees of the scale if it ends with `d` (e.g `2d` for a third
+        /// above). The pan goes from -1.0 (left) to 1.0 (right), formants
+        /// follow the pitch unless a factor is given (1.0 keeps them).
+        #[arg(long = "voice", required = true)]
+        voices: Vec<Voice>,
+
+        /// the key of the musical scale of diatonic intervals
+        #[arg(long, value_enum, default_value = "C")]
+        key: Key,
+
+        /// the musical scale of diatonic intervals
+        #[arg(long, value_enum, default_value = "major")]
+        scale: Scale,
+
+        /// the gain of the input in the mix
+        #[arg(long, default_value_t = 1.0)]
+        dry: f32,
+
+        /// the voicing confidence, between 0.0 and 1.0, from which frames
+        /// follow the diatonic intervals. Other frames keep the interval of
+        /// the last voiced frame.
+        #[arg(long, default_value_t = analysis::DEFAULT_VOICING_THRESHOLD)]
+        voicing: f32,
+
+        #[command(flatten)]
+        smoothing: SmoothingOptions,
+
+        #[command(flatten)]
+        preprocessing: PreprocessingOptions,
     },
 }
 
+/// Options of batch transpositions
+#[derive(Args, Clone, Debug, PartialEq)]
+pub struct BatchOptions {
+    /// the .wav files to transpose, or glob patterns matching them (e.g
+    /// 'takes/*.wav').
+    #[arg(required = true)]
+    pub inputs: Vec<String>,
+
+    /// the algorithms transposing each file, separated by commas.
+    #[arg(long, value_enum, value_delimiter = ',', default_value = "phase-vocoder")]
+    pub algo: Vec<Algorithm>,
+
+    /// the shifts in semi tons, separated by commas. `<FROM>..<TO>[:<STEP>]`
+    /// is a range including both ends, by steps of 1 by default.
+    #[arg(
+        long,
+        value_delimiter = ',',
+        allow_hyphen_values = true,
+        required_unless_present = "ratios"
+    )]
+    pub shifts: Vec<ShiftRange>,
+
+    /// the shifts as scale factors of the frequencies, separated by commas.
+    #[arg(long, value_delimiter = ',')]
+    pub ratios: Vec<f32>,
+
+    /// the frame sizes of the phase vocoder, separated by commas.
+    #[arg(long, value_delimiter = ',', default_value = "1024")]
+    pub fsize: Vec<usize>,
+
+    /// the shifts between frames of the phase vocoder, separated by commas.
+    #[arg(long, value_delimiter = ',', default_value = "256")]
+    pub hopa: Vec<usize>,
+
+    /// the template of the names of the output files, in which `{stem}`,
+    /// `{algo}`, `{shift}`, `{fsize}` and `{hopa}` are replaced by the name
+    /// of the input without extension and the parameters of the job.
+    #[arg(long, default_value = batch::DEFAULT_TEMPLATE)]
+    pub template: String,
+
+    /// the name of the JSON summary of the jobs.
+    #[arg(long, default_value = "batch.json")]
+    pub manifest: String,
+}
+
+impl BatchOptions {
+    /// Returns the shifts of the batch, in semi tons then as ratios
+    pub fn shifts(&self) -> Result<Vec<Shift>, &'static str> {
+        if self.ratios.iter().any(|r| r.is_nan() || *r <= 0.0) {
+            return Err("illegal ratio, must be positive");
+        }
+        Ok(self
+            .shifts
+            .iter()
+            .flat_map(ShiftRange::shifts)
+            .chain(self.ratios.iter().map(|r| Shift::Ratio(*r)))
+            .collect())
+    }
+}
+
+/// Options of the output stage of transposers
+#[derive(Args, Clone, Copy, Debug, PartialEq)]
+pub struct OutputOptions {
+    /// the proportion of the transposed signal in the output, from 0.0 (the
+    /// input only) to 1.0 (the transposed signal only).
+    #[arg(long, default_value_t = 1.0)]
+    pub mix: f32,
+
+    /// the output gain, in dB.
+    #[arg(long, default_value_t = 0.0, allow_negative_numbers = true)]
+    pub gain: f32,
 
+    /// the ceiling, in dBFS, of a soft limiter keeping overshoots of the
+    /// output under it. No limiter if absent.
+    #[arg(long, allow_negative_numbers = true)]
+    pub limit: Option<f32>,
 
-// Extracts the filename of the input path
-// fn extract_input_filename(in_path: &str) -> &str {
-//     let bytes = in_path.as_bytes();
-
-//     for (i, &item) in bytes.iter().enumerate().rev() {
-//         if item == b'/' {
-//             return &in_path[i+1..in_path.len()];
-//         }
-//     }
-
-//     &in_path[..]
-// }
-
-// produces the output filename for phase vocoder algorithm transformations
-// fn phase_vocoder_output_filename_from_input(
-//     name: &str,
-//     scale: f32,
-//     fsize: usize,
-//     hopa: usize
-// ) -> String {
-//     let mut res = String::from("phase_vocoder");
-//     res.push('_');
-//     res.push_str(extract_input_filename(name));
-//     res.push('_');
-//     res.push_str(&scale.to_string());
-//     res.push('_');
-//     res.push_str(&fsize.to_string());
-//     res.push('_');
-//     res.push_str(&hopa.to_string());
-//     res.push_str(".wav");
-//     res
-// }
-
-// produces the output filename for basic algorithm transformations
-// fn basic_output_filename_from_input(name: &str, shift: i32) -> String {
-//     let mut res = String::from("basic");
-//     res.push('_');
-//     res.push_str(extract_input_filename(name));
-//     res.push('_');
-//     res.push_str(&shift.to_string());
-//     res.push_str(".wav");
-//     res
-// }
+    /// normalizes the output to this integrated loudness, in LUFS (ITU-R
+    /// BS.1770), e.g -16 or -23.
+    #[arg(long, allow_negative_numbers = true, conflicts_with = "normalize_peak")]
+    pub normalize_lufs: Option<f32>,
+
+    /// normalizes the output to this true peak level, in dBTP.
+    #[arg(long, allow_negative_numbers = true)]
+    pub normalize_peak: Option<f32>,
+}
+
+impl OutputOptions {
+    /// Returns the output stage these options describe
+    pub fn stage(&self) -> Result<OutputStage, &'static str> {
+        if !(0.0..=1.0).contains(&self.mix) {
+            return Err("illegal mix, must be between 0.0 and 1.0");
+        }
+        let normalize = match (self.normalize_lufs, self.normalize_peak) {
+            (Some(lufs), _) => Some(Target::Lufs(lufs)),
+            (None, Some(peak)) => Some(Target::Peak(peak)),
+            (None, None) => None,
+        };
+        Ok(OutputStage { mix: self.mix, gain: self.gain, limit: self.limit, normalize })
+    }
+}
+
+/// Options of the pre-processing of the signal seen by the pitch detector.
+/// The audible output is not processed.
+#[derive(Args, Clone, Copy, Debug, PartialEq)]
+pub struct PreprocessingOptions {
+    /// the cut-off frequency, in Hz, of a high-pass filter removing low
+    /// frequency rumble, e.g 80. No filter if absent.
+    #[arg(long, value_name = "HZ")]
+    pub high_pass: Option<f32>,
+
+    /// the level, in dBFS, under which a noise gate silences room noise,
+    /// e.g -45. No gate if absent.
+    #[arg(long, value_name = "DBFS", allow_negative_numbers = true)]
+    pub gate: Option<f32>,
+
+    /// the time, in seconds, of the opening of the noise gate.
+    #[arg(long, default_value_t = 0.005, requires = "gate")]
+    pub gate_attack: f32,
+
+    /// the time, in seconds, of the closing of the noise gate.
+    #[arg(long, default_value_t = 0.05, requires = "gate")]
+    pub gate_release: f32,
+
+    /// the coefficient of a pre-emphasis filter boosting high frequencies,
+    /// e.g 0.97. No pre-emphasis if absent.
+    #[arg(long, value_name = "COEFFICIENT")]
+    pub pre_emphasis: Option<f32>,
+}
+
+impl Default for PreprocessingOptions {
+    /// Returns the options of the command line when none is given
+    fn default() -> PreprocessingOptions {
+        PreprocessingOptions {
+            high_pass: None,
+            gate: None,
+            gate_attack: 0.005,
+            gate_release: 0.05,
+            pre_emphasis: None,
+        }
+    }
+}
+
+impl PreprocessingOptions {
+    /// Returns the pre-processing these options describe, for a signal of
+    /// amplitudes between `-full_scale` and `full_scale`
+    pub fn preprocessing(&self, full_scale: f32) -> Preprocessing {
+        Preprocessing {
+            high_pass: self.high_pass,
+            gate: self.gate.map(|threshold| NoiseGate {
+                threshold,
+                attack: self.gate_attack,
+                release: self.gate_release,
+            }),
+            pre_emphasis: self.pre_emphasis,
+            full_scale,
+        }
+    }
+}
+
+/// Options of the smoothing of the pitch track before correction
+#[derive(Args, Clone, Copy, Debug, PartialEq)]
+pub struct SmoothingOptions {
+    /// the number of frames around a frame whose median pitch it is moved
+    /// towards by octaves, to correct octave errors, e.g 9. 0 or 1 disables
+    /// it.
+    #[arg(long, default_value_t = 1)]
+    pub octave_window: usize,
+
+    /// enables the Viterbi smoothing correcting short octave jumps
+    #[arg(long)]
+    pub viterbi: bool,
+
+    /// the length, in frames, of the median filter of the pitch track, e.g 5.
+    /// 0 or 1 disables it.
+    #[arg(long, default_value_t = 1)]
+    pub median: usize,
+}
+
+impl SmoothingOptions {
+    /// Returns the smoothing these options describe
+    pub fn smoothing(&self) -> Smoothing {
+        Smoothing {
+            octave_window: self.octave_window,
+            viterbi: self.viterbi,
+            median: self.median,
+        }
+    }
+}
+
+#[cfg(test)]
+mod ptconfig_tests {
+    use super::*;
+    use clap::CommandFactory;
+
+    #[test]
+    fn default_keys_are_valid() {
+        // clap checks the default values of the arguments against the names
+        // of the keys
+        PitchCli::command().debug_assert();
+        let names: Vec<String> = Key::value_variants()
+            .iter()
+            .map(|k| k.to_possible_value().unwrap().get_name().to_string())
+            .collect();
+        let expected = ["C", "C#", "D", "D#", "E", "F", "F#", "G", "G#", "A", "A#", "B"];
+        assert_eq!(expected.to_vec(), names);
+        assert_eq!(Ok(Key::C), Key::from_str("C", false));
+    }
+
+    #[test]
+    fn smoothing_is_opt_in() {
+        use clap::Parser;
+        let smoothing = |args: &[&str]| {
+            let base = [
+                "pitch_transposer", "static", "phase-vocoder",
+                "-i", "a.wav", "-o", "b.wav", "--fsize", "1024", "--hopa", "256"
+            ];
+            let cli = PitchCli::try_parse_from(base.iter().chain(args)).unwrap();
+            match cli.subcommand {
+                PitchSubcommand::Static {
+                    static_subcommand: PTConfiguration::PhaseVocoder { smoothing, .. },
+                    ..
+                } => smoothing.smoothing(),
+                _ => panic!("not the static phase vocoder"),
+            }
+        };
+        let none = Smoothing { octave_window: 1, viterbi: false, median: 1 };
+        assert_eq!(none, smoothing(&[]));
+        let args = ["--octave-window", "9", "--viterbi", "--median", "5"];
+        let all = Smoothing { octave_window: 9, viterbi: true, median: 5 };
+        assert_eq!(all, smoothing(&args));
+    }
+
+    #[test]
+    fn midi_options_require_midi() {
+        use clap::Parser;
+        let base = [
+            "pitch_transposer", "static", "phase-vocoder",
+            "-i", "a.wav", "-o", "b.wav", "--fsize", "1024", "--hopa", "256"
+        ];
+        let parse = |args: &[&str]| PitchCli::try_parse_from(base.iter().chain(args));
+        for option in ["--midi-offset", "--midi-track", "--midi-channel"] {
+            assert!(parse(&[option, "1"]).is_err(), "{}", option);
+            assert!(parse(&[option, "1", "--midi", "m.mid"]).is_ok(), "{}", option);
+        }
+        assert!(parse(&[]).is_ok());
+    }
+
+    #[test]
+    fn default_preprocessing_is_the_one_of_the_command_line() {
+        #[derive(Parser)]
+        struct Cli {
+            #[command(flatten)]
+            preprocessing: PreprocessingOptions,
+        }
+        let cli = Cli::try_parse_from(["cli"]).unwrap();
+        assert_eq!(PreprocessingOptions::default(), cli.preprocessing);
+    }
+}
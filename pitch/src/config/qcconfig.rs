@@ -1,20 +1,86 @@
 use clap::{Parser};
+use crate::accuracy::PitchReference;
+use crate::align::LengthPolicy;
+use crate::analysis;
+use crate::metrics::{self, Metric};
+use crate::midi;
+use crate::report::{Case, CheckOptions, PitchCheck};
 
 /// Quality checker configuration
 #[derive(Parser)]
 #[command(author, version)]
 pub struct QCConfiguration {
-    #[arg(long)]
+    #[arg(long, required_unless_present = "manifest")]
     /// the path to the .wav signal to check
-    ps: String,
+    ps: Option<String>,
+
+    #[arg(long, required_unless_present_any = ["expected_note", "manifest"])]
+    /// the path to the .wav oracle signal, or to the input of the transposer
+    /// with `--expected-shift`
+    po: Option<String>,
+
+    #[arg(long, allow_negative_numbers = true, required_unless_present = "manifest")]
+    /// the threshold of the metric: the largest distance, or the smallest
+    /// ratio for the snr and si-sdr metrics, of a signal of good quality
+    th: Option<f32>,
+
+    #[arg(long, value_enum, default_value_t = Metric::Euclidean)]
+    /// the metric comparing the signal to the oracle
+    metric: Metric,
+
+    #[arg(long, default_value_t = metrics::FRAME_SIZE)]
+    /// the size of the frames of the spectrograms and of the pitch detection
+    fsize: usize,
+
+    #[arg(long, default_value_t = metrics::HOP_SIZE)]
+    /// the shift between the frames of the spectrograms and of the pitch
+    /// detection
+    hopa: usize,
+
+    #[arg(long, value_enum, default_value_t = CheckOptions::default().length)]
+    /// how a signal and an oracle of different lengths are compared, after
+    /// the alignment
+    length: LengthPolicy,
+
+    #[arg(long)]
+    /// aligns the signal on the oracle before comparing them, the delay
+    /// between them being estimated by cross-correlation
+    align: bool,
+
+    #[arg(long, default_value_t = 0.5)]
+    /// the largest delay, in seconds, searched by `--align`
+    max_lag: f32,
+
+    #[arg(long)]
+    /// checks the pitch of the signal instead of comparing it with a metric:
+    /// the deviation in cents of its voiced frames from the pitch of the
+    /// oracle, the threshold being the largest mean deviation
+    pitch: bool,
+
+    #[arg(long, value_parser = parse_note, conflicts_with = "expected_shift")]
+    /// checks the pitch of the signal against this note held all along, e.g
+    /// `A4` or `Bb3`, instead of the oracle
+    expected_note: Option<u8>,
+
+    #[arg(long, allow_negative_numbers = true)]
+    /// checks the pitch of the signal against the pitch of the oracle, the
+    /// input of the transposer, shifted by this number of semi tons
+    expected_shift: Option<f32>,
 
     #[arg(long)]
-    /// the path to the .wav oracle signal
-    po: String,
+    /// also reports the integrated loudness (ITU-R BS.1770) and the true peak
+    /// of both signals
+    loudness: bool,
 
     #[arg(long)]
-    /// the threshold of the euclidian distance
-    th: f32,
+    /// prints a JSON report holding the values of all the metrics, the
+    /// thresholds and the verdicts
+    json: bool,
+
+    #[arg(long, conflicts_with_all = ["ps", "po"])]
+    /// runs the checks listed in this manifest (TOML, or JSON if it ends with
+    /// `.json`) instead of checking a single signal
+    manifest: Option<String>,
 }
 
 impl QCConfiguration {
@@ -22,26 +88,105 @@ impl QCConfiguration {
     /// Returns a new QCConfiguration from the given parameters
     fn new(path_signal: &str, path_oracle: &str, threshold: f32) -> Self {
         Self {
-            ps: path_signal.to_string(),
-            po: path_oracle.to_string(),
-            th: threshold
+            ps: Some(path_signal.to_string()),
+            po: Some(path_oracle.to_string()),
+            th: Some(threshold),
+            loudness: false,
+            metric: Metric::Euclidean,
+            fsize: metrics::FRAME_SIZE,
+            hopa: metrics::HOP_SIZE,
+            length: CheckOptions::default().length,
+            align: false,
+            max_lag: 0.5,
+            pitch: false,
+            expected_note: None,
+            expected_shift: None,
+            json: false,
+            manifest: None,
         }
     }
 
-    /// Returns a slice of the path to the signal
-    pub fn path_to_signal(&self) -> &str {
-        &self.ps[..]
+    /// Returns a slice of the path to the signal, if any
+    pub fn path_to_signal(&self) -> Option<&str> {
+        self.ps.as_deref()
     }
 
-    /// Returns a slice of the path to the oracle
-    pub fn path_to_oracle(&self) -> &str {
-        &self.po[..]
+    /// Returns a slice of the path to the oracle, if any
+    pub fn path_to_oracle(&self) -> Option<&str> {
+        self.po.as_deref()
     }
 
-    /// Returns the threshold value
+    /// Returns the threshold value, 0 in manifest mode
     pub fn threshold(&self) -> f32 {
-        self.th
+        self.th.unwrap_or(0.0)
+    }
+
+    /// Returns the metric comparing the signal to the oracle
+    pub fn metric(&self) -> Metric {
+        self.metric
     }
+
+    /// Returns how the signal is compared to the oracle
+    pub fn options(&self) -> CheckOptions {
+        CheckOptions {
+            fsize: self.fsize,
+            hopa: self.hopa,
+            length: self.length,
+            max_lag: if self.align { Some(self.max_lag) } else { None },
+        }
+    }
+
+    /// Returns the pitch the signal is expected to have if its pitch must be
+    /// checked, None if it must be compared with the metric
+    pub fn pitch_reference(&self) -> Option<PitchReference> {
+        if let Some(key) = self.expected_note {
+            let frequency = midi::note_frequency(key);
+            return Some(PitchReference::Note(frequency));
+        }
+        match self.expected_shift {
+            Some(semitones) => Some(PitchReference::Shift(semitones)),
+            None if self.pitch => Some(PitchReference::Shift(0.0)),
+            None => None,
+        }
+    }
+
+    /// Returns true if the loudness of the signals must be reported
+    pub fn loudness(&self) -> bool {
+        self.loudness
+    }
+
+    /// Returns true if a JSON report must be printed
+    pub fn json(&self) -> bool {
+        self.json
+    }
+
+    /// Returns the path to the manifest of the checks to run, if any
+    pub fn manifest(&self) -> Option<&str> {
+        self.manifest.as_deref()
+    }
+
+    /// Returns the check of the signal as a case of a manifest, holding the
+    /// threshold of its metric or of its pitch. None in manifest mode.
+    pub fn case(&self) -> Option<Case> {
+        let signal = self.ps.clone()?;
+        let mut case = Case::new(&signal, self.po.as_deref(), self.options());
+        case.loudness = self.loudness;
+        if self.pitch_reference().is_some() {
+            case.pitch = Some(PitchCheck {
+                threshold: self.threshold(),
+                expected_note: self.expected_note.map(analysis::note_name),
+                expected_shift: self.expected_shift,
+            });
+        } else {
+            case.thresholds.insert(self.metric.name(), self.threshold());
+        }
+        Some(case)
+    }
+}
+
+/// Parses the MIDI note number of the note `name`, e.g `A4`
+fn parse_note(name: &str) -> Result<u8, &'static str> {
+    analysis::note_number(name).ok_or("expected a note such as A4, C#3 or Bb2")
 }
 
 /// Parses the arguments from the `main()` function and loads the corresponding
@@ -0,0 +1,71 @@
+use clap::Parser;
+use crate::analysis;
+use crate::spectrogram::{Colormap, FrequencyScale, Window};
+
+/// Spectrogram configuration
+#[derive(Parser)]
+#[command(author, version = "v1.0")]
+pub struct SpectrogramConfiguration {
+    /// the path to the .wav file to draw.
+    #[arg(short,long)]
+    pub in_path: String,
+
+    /// the name of the PNG file in which the spectrogram is drawn.
+    #[arg(short,long)]
+    pub out_filename: String,
+
+    /// the frame size to use
+    #[arg(short,long, default_value_t = 2048)]
+    pub fsize: usize,
+
+    /// the shift between frames
+    #[arg(long, default_value_t = 512)]
+    pub hopa: usize,
+
+    /// the window applied to frames
+    #[arg(long, value_enum, default_value = "hann")]
+    pub window: Window,
+
+    /// the scale of the frequency axis
+    #[arg(long, value_enum, default_value = "linear")]
+    pub scale: FrequencyScale,
+
+    /// the lowest frequency drawn, in Hz, 0 by default (20 for a
+    /// logarithmic axis)
+    #[arg(long)]
+    pub min_freq: Option<f32>,
+
+    /// the highest frequency drawn, in Hz, the Nyquist frequency by default
+    #[arg(long)]
+    pub max_freq: Option<f32>,
+
+    /// the levels drawn, in dB under the loudest bin of the spectrogram.
+    /// Lower levels are drawn as the lowest color of the colormap.
+    #[arg(long, default_value_t = 80.0)]
+    pub db_range: f32,
+
+    /// the colormap of levels
+    #[arg(long, value_enum, default_value = "magma")]
+    pub colormap: Colormap,
+
+    /// the height of the image, in pixels
+    #[arg(long, default_value_t = 512)]
+    pub height: usize,
+
+    /// the width of the image, in pixels, one per frame by default
+    #[arg(long)]
+    pub width: Option<usize>,
+
+    /// draws the pitch detected in voiced frames over the spectrogram
+    #[arg(long)]
+    pub pitch: bool,
+
+    /// the voicing confidence, between 0.0 and 1.0, from which the pitch of
+    /// frames is drawn
+    #[arg(
+        long,
+        default_value_t = analysis::DEFAULT_VOICING_THRESHOLD,
+        requires = "pitch"
+    )]
+    pub voicing: f32,
+}
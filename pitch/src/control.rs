@@ -0,0 +1,4 @@
+/// Live parameters shared with the real-time processor
+pub mod params;
+/// OSC server updating live parameters
+pub mod osc;
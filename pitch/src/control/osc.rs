@@ -0,0 +1,340 @@
+use std::net::{SocketAddr, UdpSocket};
+use std::sync::Arc;
+use std::thread;
+use clap::ValueEnum;
+use crate::config::ptconfig::{Key, Scale};
+use crate::control::params::LiveParameters;
+
+/// Argument of an OSC message
+#[derive(Clone, Debug, PartialEq)]
+pub enum OscArg {
+    Int(i32),
+    Long(i64),
+    Float(f32),
+    Double(f64),
+    Str(String),
+    Bool(bool),
+}
+
+impl OscArg {
+    /// Returns the argument as a float if it is a number
+    fn as_f32(&self) -> Result<f32, &'static str> {
+        match self {
+            OscArg::Int(i) => Ok(*i as f32),
+            OscArg::Long(l) => Ok(*l as f32),
+            OscArg::Float(f) => Ok(*f),
+            OscArg::Double(d) => Ok(*d as f32),
+            _ => Err("expected a number"),
+        }
+    }
+
+    /// Returns the argument as a positive integer if it is a number
+    fn as_usize(&self) -> Result<usize, &'static str> {
+        let f = self.as_f32()?;
+        if f < 0.0 {
+            return Err("expected a positive number");
+        }
+        Ok(f.round() as usize)
+    }
+
+    /// Returns the argument as a boolean: numbers are true if they are not 0
+    fn as_bool(&self) -> Result<bool, &'static str> {
+        match self {
+            OscArg::Bool(b) => Ok(*b),
+            _ => Ok(self.as_f32()? != 0.0),
+        }
+    }
+}
+
+/// An OSC message: an address pattern followed by arguments
+#[derive(Clone, Debug, PartialEq)]
+pub struct OscMessage {
+    pub address: String,
+    pub args: Vec<OscArg>,
+}
+
+/// Reads the OSC string starting at `*pos` in `packet` and moves `*pos` after
+/// its padding. OSC strings are null terminated and padded to 4 bytes.
+fn read_string(packet: &[u8], pos: &mut usize) -> Result<String, &'static str> {
+    let start = *pos;
+    let len = match packet[start.min(packet.len())..].iter().position(|&b| b == 0) {
+        Some(len) => len,
+        None => return Err("unterminated OSC string"),
+    };
+    *pos = start + (len / 4 + 1) * 4;
+    match std::str::from_utf8(&packet[start..start + len]) {
+        Ok(s) => Ok(s.to_string()),
+        Err(_) => Err("OSC string is not valid UTF-8"),
+    }
+}
+
+/// Reads the `N` bytes starting at `*pos` in `packet` and moves `*pos` after
+/// them
+fn read_bytes<const N: usize>(
+    packet: &[u8],
+    pos: &mut usize
+) -> Result<[u8; N], &'static str> {
+    match packet.get(*pos..*pos + N) {
+        Some(bytes) => {
+            *pos += N;
+            Ok(bytes.try_into().unwrap())
+        },
+        None => Err("truncated OSC packet"),
+    }
+}
+
+/// Decodes an OSC message
+fn decode_message(packet: &[u8]) -> Result<OscMessage, &'static str> {
+    let mut pos = 0;
+    let address = read_string(packet, &mut pos)?;
+    if !address.starts_with('/') {
+        return Err("OSC address must start with '/'");
+    }
+    let mut args = Vec::new();
+    if pos >= packet.len() {
+        return Ok(OscMessage { address, args }); // no type tag string
+    }
+    let tags = read_string(packet, &mut pos)?;
+    if !tags.starts_with(',') {
+        return Err("OSC type tag string must start with ','");
+    }
+    for tag in tags.chars().skip(1) {
+        let arg = match tag {
+            'i' => OscArg::Int(i32::from_be_bytes(read_bytes(packet, &mut pos)?)),
+            'h' => OscArg::Long(i64::from_be_bytes(read_bytes(packet, &mut pos)?)),
+            'f' => OscArg::Float(f32::from_be_bytes(read_bytes(packet, &mut pos)?)),
+            'd' => OscArg::Double(f64::from_be_bytes(read_bytes(packet, &mut pos)?)),
+            's' => OscArg::Str(read_string(packet, &mut pos)?),
+            'T' => OscArg::Bool(true),
+            'F' => OscArg::Bool(false),
+            _ => return Err("unsupported OSC argument type"),
+        };
+        args.push(arg);
+    }
+    Ok(OscMessage { address, args })
+}
+
+/// Decodes an OSC packet, i.e a message or a bundle of packets, and returns
+/// the messages it contains. Time tags of bundles are ignored.
+pub fn decode(packet: &[u8]) -> Result<Vec<OscMessage>, &'static str> {
+    if !packet.starts_with(b"#bundle\0") {
+        return Ok(vec![decode_message(packet)?]);
+    }
+    let mut pos = 16; // "#bundle\0" and time tag
+    let mut messages = Vec::new();
+    while pos < packet.len() {
+        let size = i32::from_be_bytes(read_bytes(packet, &mut pos)?);
+        let element = match packet.get(pos..pos + size.max(0) as usize) {
+            Some(element) => element,
+            None => return Err("truncated OSC bundle"),
+        };
+        messages.append(&mut decode(element)?);
+        pos += size as usize;
+    }
+    Ok(messages)
+}
+
+/// Appends the OSC string `s` to `packet`
+fn write_string(packet: &mut Vec<u8>, s: &str) {
+    packet.extend_from_slice(s.as_bytes());
+    let padding = 4 - s.len() % 4;
+    packet.extend(std::iter::repeat_n(0, padding));
+}
+
+/// Encodes the given message as an OSC packet
+pub fn encode(message: &OscMessage) -> Vec<u8> {
+    let mut packet = Vec::new();
+    write_string(&mut packet, &message.address);
+    let mut tags = String::from(",");
+    let mut data = Vec::new();
+    for arg in &message.args {
+        match arg {
+            OscArg::Int(i) => {
+                tags.push('i');
+                data.extend_from_slice(&i.to_be_bytes());
+            },
+            OscArg::Long(l) => {
+                tags.push('h');
+                data.extend_from_slice(&l.to_be_bytes());
+            },
+            OscArg::Float(f) => {
+                tags.push('f');
+                data.extend_from_slice(&f.to_be_bytes());
+            },
+            OscArg::Double(d) => {
+                tags.push('d');
+                data.extend_from_slice(&d.to_be_bytes());
+            },
+            OscArg::Str(s) => {
+                tags.push('s');
+                write_string(&mut data, s);
+            },
+            OscArg::Bool(b) => tags.push(if *b { 'T' } else { 'F' }),
+        }
+    }
+    write_string(&mut packet, &tags);
+    packet.append(&mut data);
+    packet
+}
+
+/// Applies the given message to the live parameters. Supported addresses are:
+///   - `/pitch/shift <number>`: the shift, as in the command line
+///   - `/pitch/autotune`: computes the shift from the nearest note
+///   - `/pitch/key <string|int>`: the key, as a name or semi tons above C
+///   - `/pitch/scale <string>`: the musical scale
+///   - `/pitch/retune <number>`: the retune speed
+///   - `/pitch/voicing <number>`: the voicing threshold of autotuning
+///   - `/pitch/bypass <bool|number>`: lets the signal through unchanged
+///   - `/pitch/fsize <number>` and `/pitch/hopa <number>`
+pub fn apply(
+    params: &LiveParameters,
+    message: &OscMessage
+) -> Result<(), &'static str> {
+    let arg = message.args.first();
+    let arg = || arg.ok_or("missing OSC argument");
+    match &message.address[..] {
+        "/pitch/shift" => params.set_shift(arg()?.as_f32()?),
+        "/pitch/autotune" => params.set_autotune(),
+        "/pitch/key" => {
+            let key = match arg()? {
+                OscArg::Str(s) => match Key::from_str(s, true) {
+                    Ok(key) => key,
+                    Err(_) => return Err("unknown key"),
+                },
+                a => Key::from_semitones(a.as_usize()? as u8),
+            };
+            params.set_key(key);
+            Ok(())
+        },
+        "/pitch/scale" => match arg()? {
+            OscArg::Str(s) => match Scale::from_str(s, true) {
+                Ok(scale) => {
+                    params.set_scale(scale);
+                    Ok(())
+                },
+                Err(_) => Err("unknown scale"),
+            },
+            _ => Err("expected a string"),
+        },
+        "/pitch/retune" => params.set_retune(arg()?.as_f32()?),
+        "/pitch/voicing" => params.set_voicing(arg()?.as_f32()?),
+        "/pitch/bypass" => {
+            params.set_bypass(arg()?.as_bool()?);
+            Ok(())
+        },
+        "/pitch/fsize" => params.set_fsize(arg()?.as_usize()?),
+        "/pitch/hopa" => params.set_hopa(arg()?.as_usize()?),
+        _ => Err("unknown OSC address"),
+    }
+}
+
+/// OSC server listening on UDP for messages updating live parameters
+pub struct OscServer {
+    socket: UdpSocket,
+}
+
+impl OscServer {
+
+    /// Binds an OSC server to the given address, e.g `127.0.0.1:9000`
+    pub fn bind(addr: &str) -> Result<OscServer, &'static str> {
+        match UdpSocket::bind(addr) {
+            Ok(socket) => Ok(OscServer { socket }),
+            Err(_) => Err("could not bind OSC server"),
+        }
+    }
+
+    /// Returns the address the server is bound to
+    pub fn local_addr(&self) -> Result<SocketAddr, &'static str> {
+        match self.socket.local_addr() {
+            Ok(addr) => Ok(addr),
+            Err(_) => Err("could not get OSC server address"),
+        }
+    }
+
+    /// Starts a thread applying received messages to `params`. Invalid
+    /// messages are reported on standard error and ignored.
+    pub fn spawn(self, params: Arc<LiveParameters>) -> thread::JoinHandle<()> {
+        thread::spawn(move || {
+            let mut buf = [0u8; 65536];
+            loop {
+                let len = match self.socket.recv_from(&mut buf) {
+                    Ok((len, _)) => len,
+                    Err(why) => {
+                        eprintln!("osc server: {}", why);
+                        continue;
+                    },
+                };
+                let messages = match decode(&buf[..len]) {
+                    Ok(messages) => messages,
+                    Err(why) => {
+                        eprintln!("osc server: {}", why);
+                        continue;
+                    },
+                };
+                for message in messages {
+                    if let Err(why) = apply(&params, &message) {
+                        eprintln!("osc server: {}: {}", message.address, why);
+                    }
+                }
+            }
+        })
+    }
+}
+
+#[cfg(test)]
+mod osc_tests {
+    use super::*;
+
+    #[test]
+    fn encode_then_decode_gives_same_message() {
+        let message = OscMessage {
+            address: String::from("/pitch/key"),
+            args: vec![
+                OscArg::Str(String::from("F#")),
+                OscArg::Int(-3),
+                OscArg::Float(1.5),
+                OscArg::Bool(true),
+                OscArg::Double(0.25),
+                OscArg::Long(1 << 40),
+            ],
+        };
+        let packet = encode(&message);
+        assert_eq!(0, packet.len() % 4);
+        assert_eq!(vec![message], decode(&packet).unwrap());
+    }
+
+    #[test]
+    fn decode_known_packet() {
+        let packet = b"/pitch/shift\0\0\0\0,f\0\0\x3f\xc0\0\0";
+        let messages = decode(packet).unwrap();
+        assert_eq!("/pitch/shift", messages[0].address);
+        assert_eq!(vec![OscArg::Float(1.5)], messages[0].args);
+    }
+
+    #[test]
+    fn decode_bundle() {
+        let first = encode(&OscMessage {
+            address: String::from("/pitch/bypass"),
+            args: vec![OscArg::Int(1)],
+        });
+        let second = encode(&OscMessage {
+            address: String::from("/pitch/autotune"),
+            args: vec![],
+        });
+        let mut packet = b"#bundle\0\0\0\0\0\0\0\0\x01".to_vec();
+        for element in [&first, &second] {
+            packet.extend_from_slice(&(element.len() as i32).to_be_bytes());
+            packet.extend_from_slice(element);
+        }
+        let messages = decode(&packet).unwrap();
+        assert_eq!(2, messages.len());
+        assert_eq!("/pitch/autotune", messages[1].address);
+    }
+
+    #[test]
+    fn decode_truncated_packet_fails() {
+        assert!(decode(b"/pitch/shift\0\0\0\0,f\0\0\x3f").is_err());
+        assert!(decode(b"/pitch").is_err());
+        assert!(decode(b"pitch\0\0\0").is_err());
+    }
+}
@@ -0,0 +1,225 @@
+use std::sync::atomic::{AtomicBool, AtomicU32, AtomicU8, AtomicUsize};
+use std::sync::atomic::Ordering::Relaxed;
+use crate::config::ptconfig::{Key, PreprocessingOptions, RealTimeSubCommand, Scale};
+
+/// Largest frame size of the phase vocoder that can be set while running
+pub const MAX_FSIZE: usize = 1 << 16;
+
+/// Largest shift of the basic transposer while running, in semi tons either
+/// way
+pub const MAX_SHIFT: f32 = 24.0;
+
+/// Largest scale factor of the phase vocoder while running, its inverse
+/// being the smallest
+pub const MAX_SCALE_FACTOR: f32 = 4.0;
+
+/// Parameters of a running real-time transposer. They can be updated from any
+/// thread without locking, the processor reads them once per processed clip
+/// through `snapshot()`. Floats are stored as their bit representation.
+pub struct LiveParameters {
+    phase_vocoder: bool,   // algorithm, can not change while running
+    shift: AtomicU32,      // semi tons (basic) or scale factor (phase vocoder)
+    autotune: AtomicBool,  // true if shift is ignored
+    fsize: AtomicUsize,
+    hopa: AtomicUsize,
+    key: AtomicU8,         // semi tons above C
+    scale: AtomicU8,       // index in Scale::value_variants()
+    retune: AtomicU32,
+    voicing: AtomicU32,
+    bypass: AtomicBool,
+    preprocessing: PreprocessingOptions, // can not change while running
+}
+
+impl LiveParameters {
+
+    /// Returns live parameters initialized from the given configuration
+    pub fn new(config: &RealTimeSubCommand) -> Self {
+        match config {
+            RealTimeSubCommand::Basic { shift } => Self {
+                phase_vocoder: false,
+                shift: AtomicU32::new((*shift as f32).to_bits()),
+                autotune: AtomicBool::new(false),
+                fsize: AtomicUsize::new(0),
+                hopa: AtomicUsize::new(0),
+                key: AtomicU8::new(Key::C.semitones()),
+                scale: AtomicU8::new(Scale::Chromatic as u8),
+                retune: AtomicU32::new(1.0f32.to_bits()),
+                voicing: AtomicU32::new(0.0f32.to_bits()),
+                bypass: AtomicBool::new(false),
+                preprocessing: PreprocessingOptions::default(),
+            },
+            RealTimeSubCommand::PhaseVocoder {
+                fsize, hopa, shift, key, scale, retune, voicing, preprocessing
+            } => Self {
+                phase_vocoder: true,
+                shift: AtomicU32::new(shift.unwrap_or(1.0).to_bits()),
+                autotune: AtomicBool::new(shift.is_none()),
+                fsize: AtomicUsize::new(*fsize),
+                hopa: AtomicUsize::new(*hopa),
+                key: AtomicU8::new(key.semitones()),
+                scale: AtomicU8::new(*scale as u8),
+                retune: AtomicU32::new(retune.to_bits()),
+                voicing: AtomicU32::new(voicing.to_bits()),
+                bypass: AtomicBool::new(false),
+                preprocessing: *preprocessing,
+            },
+        }
+    }
+
+    /// Returns the configuration corresponding to the current values
+    pub fn snapshot(&self) -> RealTimeSubCommand {
+        let shift = f32::from_bits(self.shift.load(Relaxed));
+        if !self.phase_vocoder {
+            return RealTimeSubCommand::Basic { shift: shift.round() as i32 };
+        }
+        RealTimeSubCommand::PhaseVocoder {
+            fsize: self.fsize.load(Relaxed),
+            hopa: self.hopa.load(Relaxed),
+            shift: if self.autotune.load(Relaxed) { None } else { Some(shift) },
+            key: Key::from_semitones(self.key.load(Relaxed)),
+            scale: Scale::from_index(self.scale.load(Relaxed)),
+            retune: f32::from_bits(self.retune.load(Relaxed)),
+            voicing: f32::from_bits(self.voicing.load(Relaxed)),
+            preprocessing: self.preprocessing,
+        }
+    }
+
+    /// Returns true if the processor must let the signal through unchanged
+    pub fn bypass(&self) -> bool {
+        self.bypass.load(Relaxed)
+    }
+
+    /// Sets the shift, in semi tons for the basic transposer or as a scale
+    /// factor for the phase vocoder. Autotuning is disabled.
+    pub fn set_shift(&self, shift: f32) -> Result<(), &'static str> {
+        let legal = if self.phase_vocoder {
+            (1.0 / MAX_SCALE_FACTOR..=MAX_SCALE_FACTOR).contains(&shift)
+        } else {
+            (-MAX_SHIFT..=MAX_SHIFT).contains(&shift)
+        };
+        if !legal {
+            return Err("illegal shift value");
+        }
+        self.shift.store(shift.to_bits(), Relaxed);
+        self.autotune.store(false, Relaxed);
+        Ok(())
+    }
+
+    /// Enables autotuning, i.e the shift is computed from the nearest note
+    pub fn set_autotune(&self) -> Result<(), &'static str> {
+        if !self.phase_vocoder {
+            return Err("autotune needs the phase vocoder");
+        }
+        self.autotune.store(true, Relaxed);
+        Ok(())
+    }
+
+    /// Sets the frame size of the phase vocoder, from 2 to `MAX_FSIZE` and no
+    /// less than the shift between frames
+    pub fn set_fsize(&self, fsize: usize) -> Result<(), &'static str> {
+        if !(2..=MAX_FSIZE).contains(&fsize) || fsize < self.hopa.load(Relaxed) {
+            return Err("illegal frame size");
+        }
+        self.fsize.store(fsize, Relaxed);
+        Ok(())
+    }
+
+    /// Sets the shift between frames of the phase vocoder, from 1 to the
+    /// frame size
+    pub fn set_hopa(&self, hopa: usize) -> Result<(), &'static str> {
+        if hopa == 0 || hopa > self.fsize.load(Relaxed) {
+            return Err("illegal shift between frames");
+        }
+        self.hopa.store(hopa, Relaxed);
+        Ok(())
+    }
+
+    /// Sets the key of the musical scale used to autotune
+    pub fn set_key(&self, key: Key) {
+        self.key.store(key.semitones(), Relaxed);
+    }
+
+    /// Sets the musical scale used to autotune
+    pub fn set_scale(&self, scale: Scale) {
+        self.scale.store(scale as u8, Relaxed);
+    }
+
+    /// Sets the retune speed, between 0.0 and 1.0
+    pub fn set_retune(&self, retune: f32) -> Result<(), &'static str> {
+        if !(0.0..=1.0).contains(&retune) {
+            return Err("illegal retune speed");
+        }
+        self.retune.store(retune.to_bits(), Relaxed);
+        Ok(())
+    }
+
+    /// Sets the voicing confidence from which clips are autotuned, between
+    /// 0.0 and 1.0
+    pub fn set_voicing(&self, voicing: f32) -> Result<(), &'static str> {
+        if !(0.0..=1.0).contains(&voicing) {
+            return Err("illegal voicing threshold");
+        }
+        self.voicing.store(voicing.to_bits(), Relaxed);
+        Ok(())
+    }
+
+    /// Enables or disables the bypass
+    pub fn set_bypass(&self, bypass: bool) {
+        self.bypass.store(bypass, Relaxed);
+    }
+}
+
+#[cfg(test)]
+mod params_tests {
+    use super::*;
+
+    fn phase_vocoder() -> LiveParameters {
+        LiveParameters::new(&RealTimeSubCommand::PhaseVocoder {
+            fsize: 1024,
+            hopa: 256,
+            shift: None,
+            key: Key::C,
+            scale: Scale::Chromatic,
+            retune: 1.0,
+            voicing: 0.5,
+            preprocessing: PreprocessingOptions::default(),
+        })
+    }
+
+    #[test]
+    fn frames_within_bounds() {
+        let params = phase_vocoder();
+        for fsize in [0, 1, 128, MAX_FSIZE + 1, usize::MAX] {
+            assert!(params.set_fsize(fsize).is_err(), "{}", fsize);
+        }
+        for hopa in [0, 1025] {
+            assert!(params.set_hopa(hopa).is_err(), "{}", hopa);
+        }
+        params.set_fsize(2048).unwrap();
+        params.set_hopa(2048).unwrap();
+        assert!(params.set_fsize(1024).is_err());
+        params.set_hopa(512).unwrap();
+        params.set_fsize(512).unwrap();
+        let RealTimeSubCommand::PhaseVocoder { fsize, hopa, .. } = params.snapshot()
+        else {
+            panic!("not a phase vocoder");
+        };
+        assert_eq!((512, 512), (fsize, hopa));
+    }
+
+    #[test]
+    fn shifts_within_bounds() {
+        let params = phase_vocoder();
+        for shift in [0.0, 0.2, -1.0, 4.5, f32::INFINITY, f32::NAN] {
+            assert!(params.set_shift(shift).is_err(), "{}", shift);
+        }
+        params.set_shift(0.25).unwrap();
+        params.set_shift(4.0).unwrap();
+
+        let basic = LiveParameters::new(&RealTimeSubCommand::Basic { shift: 0 });
+        for shift in [-25.0, 25.0, f32::NAN] {
+            assert!(basic.set_shift(shift).is_err(), "{}", shift);
+        }
+        basic.set_shift(-24.0).unwrap();
+    }
+}
@@ -35,12 +35,36 @@ pub fn extract_data_from_file(
     } else {
         panic!("Error!");
     };
+    (header, samples_of(data).unwrap_or_default())
+}
 
+/// Returns the samples of `data`, an error if they are not 16-bit, 24-bit or
+/// 32-bit float samples
+fn samples_of(data: BitDepth) -> Result<Vec<f32>, &'static str> {
     match data {
-        BitDepth::Sixteen(res) => (header, i16_to_f32_vector(res)),
-        BitDepth::TwentyFour(res) => (header, i32_to_f32_vector(res)),
-        BitDepth::ThirtyTwoFloat(res) => (header, res),
-        _ => (header, vec![] as Vec<f32>),
+        BitDepth::Sixteen(res) => Ok(i16_to_f32_vector(res)),
+        BitDepth::TwentyFour(res) => Ok(i32_to_f32_vector(res)),
+        BitDepth::ThirtyTwoFloat(res) => Ok(res),
+        _ => Err("Unsupported bit depth"),
+    }
+}
+
+/// Extracts the audio data from the .wav file at the given path, like
+/// `extract_data_from_wav()`, but returns an error instead of panicking if
+/// it is not a readable .wav file
+pub fn read_wav(path: &str) -> Result<(wav::header::Header, Vec<f32>), &'static str> {
+    match FileFormat::from_file(path) {
+        Ok(FileFormat::WaveformAudio) => (),
+        Ok(_) => return Err("Not a .wav file"),
+        Err(_) => return Err("Could not open the .wav file"),
+    }
+    let mut file = match File::open(Path::new(path)) {
+        Ok(file) => file,
+        Err(_) => return Err("Could not open the .wav file"),
+    };
+    match wav::read(&mut file) {
+        Ok((header, data)) => Ok((header, samples_of(data)?)),
+        Err(_) => Err("Could not read the .wav file"),
     }
 }
 
@@ -52,6 +76,19 @@ pub fn extract_data_from_wav(path: &str) -> (wav::header::Header, Vec<f32>) {
     extract_data_from_file(&file)
 }
 
+/// Returns the maximal amplitude of the data extracted by
+/// `extract_data_from_file()` from a file with the given header. Only 16-bit,
+/// 24-bit and 32-bit float files are supported.
+pub fn full_scale(header: &wav::header::Header) -> Result<f32, &'static str> {
+    match header.bits_per_sample {
+        16 => Ok(32768.0),
+        // 24-bit samples are read left-justified in 32 bits
+        24 => Ok(2147483648.0),
+        32 => Ok(1.0),
+        _ => Err("Unsupported bit depth"),
+    }
+}
+
 /// converts the given i16 vector to an f32 vector.
 fn i16_to_f32_vector(to_convert: Vec<i16>) -> Vec<f32> {
     let mut res: Vec<f32> = Vec::with_capacity(to_convert.len());
@@ -78,18 +115,22 @@ pub fn to_u8(to_convert: &[f32]) -> Vec<u8> {
     res
 }
 
+/// converts the given f32 vector to an i16 vector, rounding each value to
+/// the nearest integer and saturating out of range values
 pub fn to_i16(to_convert: &[f32]) -> Vec<i16> {
     let mut res: Vec<i16> = Vec::with_capacity(to_convert.len());
-    for i in 0..to_convert.len() {
-        res.push(to_convert[i] as i16);
+    for elt in to_convert {
+        res.push(elt.round() as i16);
     }
     res
 }
 
+/// converts the given f32 vector to an i32 vector, rounding each value to
+/// the nearest integer
 pub fn to_i32(to_convert: &[f32]) -> Vec<i32> {
     let mut res: Vec<i32> = Vec::with_capacity(to_convert.len());
-    for i in 0..to_convert.len() {
-        res.push(to_convert[i] as i32);
+    for elt in to_convert {
+        res.push(elt.round() as i32);
     }
     res
 }
@@ -134,6 +175,56 @@ mod convert_tests {
         assert!(!is_wav_file(&text_file_path));
     }
 
+    #[test]
+    fn test_read_wav_errors() {
+        let path = "resources/mono_16PCM_440hz_8000sps.wav";
+        let (header, data) = read_wav(path).unwrap();
+        assert_eq!(8000, header.sampling_rate);
+        assert_eq!(extract_data_from_wav(path).1, data);
+        assert!(read_wav("resources/not_a_wav_file.txt").is_err());
+        assert!(read_wav("resources/no_such_file.wav").is_err());
+    }
+
+    #[test]
+    fn test_full_scale() {
+        let header = |bits| wav::Header::new(wav::WAV_FORMAT_PCM, 1, 8000, bits);
+        assert_eq!(Ok(32768.0), full_scale(&header(16)));
+        assert_eq!(Ok(2147483648.0), full_scale(&header(24)));
+        assert!(full_scale(&header(8)).is_err());
+        let float = wav::Header::new(wav::WAV_FORMAT_IEEE_FLOAT, 1, 8000, 32);
+        assert_eq!(Ok(1.0), full_scale(&float));
+    }
+
+    #[test]
+    fn full_scale_of_24_bit_files() {
+        // a half scale 24-bit file, written byte by byte
+        let samples: [i32; 2] = [4194304, -4194304];
+        let mut bytes = Vec::new();
+        bytes.extend(b"RIFF");
+        bytes.extend((36 + 3 * samples.len() as u32).to_le_bytes());
+        bytes.extend(b"WAVEfmt ");
+        bytes.extend(16u32.to_le_bytes());
+        bytes.extend(1u16.to_le_bytes()); // PCM
+        bytes.extend(1u16.to_le_bytes()); // mono
+        bytes.extend(8000u32.to_le_bytes());
+        bytes.extend((3 * 8000u32).to_le_bytes());
+        bytes.extend(3u16.to_le_bytes());
+        bytes.extend(24u16.to_le_bytes());
+        bytes.extend(b"data");
+        bytes.extend((3 * samples.len() as u32).to_le_bytes());
+        for sample in samples {
+            bytes.extend(&sample.to_le_bytes()[..3]);
+        }
+        let path = std::env::temp_dir().join("pitch_convert_24bit.wav");
+        std::fs::write(&path, bytes).unwrap();
+
+        let (header, data) = read_wav(path.to_str().unwrap()).unwrap();
+        let full_scale = full_scale(&header).unwrap();
+        let scaled: Vec<f32> = data.iter().map(|s| s / full_scale).collect();
+        assert_eq!(vec![0.5, -0.5], scaled);
+        std::fs::remove_file(path).unwrap();
+    }
+
     #[test]
     fn test_f32_to_complex_vector() {
         let res = f32_to_complex_vector(&vec![2.4, 4.64, 5.68]);
@@ -147,6 +238,12 @@ mod convert_tests {
         );
     }
 
+    #[test]
+    fn test_to_i16_rounds_and_saturates() {
+        let res = to_i16(&[2.6, -2.6, 0.4, 40000.0, -40000.0]);
+        assert_eq!(res, vec![3, -3, 0, 32767, -32768]);
+    }
+
     /*#[test]
     fn test_stereo_to_mono() {
         let ch1 = [54.0, -13.0, 15.0, 19.0];
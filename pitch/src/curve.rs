@@ -0,0 +1,198 @@
+use std::fs;
+use serde_json::Value;
+use crate::analysis::midi_note_number;
+
+/// A target pitch curve, e.g drawn in an external editor: points of time in
+/// seconds and target pitch in MIDI cents (100 per semi tone, 6900 being
+/// A4). Points without pitch are gaps, where the signal is not corrected.
+#[derive(Clone, Debug, PartialEq)]
+pub struct PitchCurve {
+    points: Vec<(f64, Option<f32>)>, // sorted by time
+}
+
+/// Converts a frequency in Hz to MIDI cents, or to a gap if it is not positive
+fn hz_to_cents(hz: f32) -> Option<f32> {
+    if hz > 0.0 {
+        Some(100.0 * midi_note_number(hz))
+    } else {
+        None
+    }
+}
+
+/// Converts MIDI cents to a frequency in Hz
+fn cents_to_hz(cents: f32) -> f32 {
+    440.0 * 2.0f32.powf((cents - 6900.0) / 1200.0)
+}
+
+impl PitchCurve {
+
+    /// Returns the curve made of the given points of time and MIDI cents
+    pub fn new(mut points: Vec<(f64, Option<f32>)>) -> PitchCurve {
+        points.sort_by(|a, b| a.0.total_cmp(&b.0));
+        PitchCurve { points }
+    }
+
+    /// Parses a CSV curve. The header line names the columns: `time`, and
+    /// either `frequency` (or `hz`) in Hz or `cents` in MIDI cents. Other
+    /// columns are ignored, so that a pitch track written by the analyzer can
+    /// be edited and used as is. Empty, null or non positive frequencies are
+    /// gaps.
+    pub fn parse_csv(text: &str) -> Result<PitchCurve, &'static str> {
+        let mut lines = text.lines().filter(|l| !l.trim().is_empty());
+        let header: Vec<String> = match lines.next() {
+            Some(header) => header.split(',').map(|c| c.trim().to_lowercase()).collect(),
+            None => return Err("empty pitch curve"),
+        };
+        let column = |names: &[&str]| header.iter().position(|c| names.contains(&&c[..]));
+        let time_col = column(&["time"]).ok_or("pitch curve has no time column")?;
+        let hz_col = column(&["frequency", "hz"]);
+        let (pitch_col, in_hz) = match (hz_col, column(&["cents"])) {
+            (Some(col), _) => (col, true),
+            (None, Some(col)) => (col, false),
+            (None, None) => {
+                return Err("pitch curve has no frequency nor cents column")
+            },
+        };
+
+        let mut points = Vec::new();
+        for line in lines {
+            let fields: Vec<&str> = line.split(',').map(|f| f.trim()).collect();
+            let time = match fields.get(time_col).map(|t| t.parse::<f64>()) {
+                Some(Ok(time)) => time,
+                _ => return Err("invalid time in pitch curve"),
+            };
+            let pitch = match fields.get(pitch_col) {
+                None | Some(&"") => None,
+                Some(p) if p.eq_ignore_ascii_case("null") => None,
+                Some(p) => match p.parse::<f32>() {
+                    Ok(p) => Some(p),
+                    Err(_) => return Err("invalid pitch in pitch curve"),
+                },
+            };
+            points.push((time, Self::to_cents(pitch, in_hz)));
+        }
+        Ok(PitchCurve::new(points))
+    }
+
+    /// Parses a JSON curve: an array of objects with a `time` and either a
+    /// `frequency` (or `hz`) in Hz or a `cents` in MIDI cents. Null or non
+    /// positive frequencies are gaps.
+    pub fn parse_json(text: &str) -> Result<PitchCurve, &'static str> {
+        let value: Value = match serde_json::from_str(text) {
+            Ok(value) => value,
+            Err(_) => return Err("invalid JSON pitch curve"),
+        };
+        let array = value.as_array().ok_or("JSON pitch curve must be an array")?;
+        let mut points = Vec::with_capacity(array.len());
+        for point in array {
+            let time = match point.get("time").and_then(Value::as_f64) {
+                Some(time) => time,
+                None => return Err("invalid time in pitch curve"),
+            };
+            let (pitch, in_hz) = match (
+                point.get("frequency").or_else(|| point.get("hz")),
+                point.get("cents")
+            ) {
+                (Some(hz), _) => (hz, true),
+                (None, Some(cents)) => (cents, false),
+                (None, None) => return Err("pitch curve point has no pitch"),
+            };
+            let pitch = match pitch {
+                Value::Null => None,
+                p => match p.as_f64() {
+                    Some(p) => Some(p as f32),
+                    None => return Err("invalid pitch in pitch curve"),
+                },
+            };
+            points.push((time, Self::to_cents(pitch, in_hz)));
+        }
+        Ok(PitchCurve::new(points))
+    }
+
+    /// Loads the curve of the file at `path`, in JSON if its extension is
+    /// `.json` and in CSV otherwise
+    pub fn load(path: &str) -> Result<PitchCurve, &'static str> {
+        let text = match fs::read_to_string(path) {
+            Ok(text) => text,
+            Err(_) => return Err("could not read pitch curve file"),
+        };
+        if path.to_lowercase().ends_with(".json") {
+            PitchCurve::parse_json(&text)
+        } else {
+            PitchCurve::parse_csv(&text)
+        }
+    }
+
+    /// Converts a parsed pitch to MIDI cents
+    fn to_cents(pitch: Option<f32>, in_hz: bool) -> Option<f32> {
+        match pitch {
+            Some(hz) if in_hz => hz_to_cents(hz),
+            cents => cents,
+        }
+    }
+
+    /// Returns the target frequency at `time` seconds, linearly interpolated
+    /// in cents between the points around it. There is none before the first
+    /// point, after the last one, or next to a gap.
+    pub fn target_at(&self, time: f64) -> Option<f32> {
+        let after = self.points.partition_point(|p| p.0 <= time);
+        if after == 0 {
+            return None;
+        }
+        let (t0, c0) = self.points[after - 1];
+        if t0 == time {
+            return c0.map(cents_to_hz);
+        }
+        let (t1, c1) = *self.points.get(after)?;
+        let (c0, c1) = (c0?, c1?);
+        let progress = ((time - t0) / (t1 - t0)) as f32;
+        Some(cents_to_hz(c0 + progress * (c1 - c0)))
+    }
+}
+
+#[cfg(test)]
+mod curve_tests {
+    use super::*;
+
+    #[test]
+    fn parse_csv_in_hz_with_gaps() {
+        let text = "time,frequency,note\n1.0,,\n0.0,440.0,A4\n";
+        let curve = PitchCurve::parse_csv(text).unwrap();
+        assert_eq!(PitchCurve::new(vec![(0.0, Some(6900.0)), (1.0, None)]), curve);
+    }
+
+    #[test]
+    fn parse_csv_in_cents() {
+        let curve = PitchCurve::parse_csv("cents, time\n6900,0\n7100,2\n").unwrap();
+        let expected = PitchCurve::new(vec![(0.0, Some(6900.0)), (2.0, Some(7100.0))]);
+        assert_eq!(expected, curve);
+        assert!(PitchCurve::parse_csv("time,pitch\n0,440\n").is_err());
+        assert!(PitchCurve::parse_csv("time,hz\n0,abc\n").is_err());
+    }
+
+    #[test]
+    fn parse_json_in_hz_and_cents() {
+        let hz = PitchCurve::parse_json(
+            r#"[{"time": 0.0, "frequency": 880.0}, {"time": 0.5, "hz": null}]"#
+        ).unwrap();
+        assert_eq!(PitchCurve::new(vec![(0.0, Some(8100.0)), (0.5, None)]), hz);
+        let cents = PitchCurve::parse_json(r#"[{"time": 1, "cents": 6000}]"#).unwrap();
+        assert_eq!(PitchCurve::new(vec![(1.0, Some(6000.0))]), cents);
+        assert!(PitchCurve::parse_json(r#"{"time": 1, "cents": 6000}"#).is_err());
+    }
+
+    #[test]
+    fn target_at_interpolates_in_cents() {
+        let curve = PitchCurve::new(vec![
+            (0.0, Some(6900.0)), (1.0, Some(8100.0)), (2.0, None), (3.0, Some(6900.0))
+        ]);
+        assert_eq!(None, curve.target_at(-0.1));
+        assert_eq!(Some(440.0), curve.target_at(0.0));
+        assert!((curve.target_at(0.5).unwrap() - 622.25).abs() < 0.01);
+        assert_eq!(Some(880.0), curve.target_at(1.0));
+        assert_eq!(None, curve.target_at(1.5));
+        assert_eq!(None, curve.target_at(2.5));
+        assert_eq!(Some(440.0), curve.target_at(3.0));
+        assert_eq!(None, curve.target_at(3.1));
+    }
+}
@@ -0,0 +1,124 @@
+use std::f64::consts::PI;
+
+/// Second order IIR filter, of transfer function
+/// `(b0 + b1 z^-1 + b2 z^-2) / (1 + a1 z^-1 + a2 z^-2)`
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Biquad {
+    pub b0: f64,
+    pub b1: f64,
+    pub b2: f64,
+    pub a1: f64,
+    pub a2: f64,
+}
+
+impl Biquad {
+
+    /// Returns a second order Butterworth high-pass filter of cut-off
+    /// frequency `cutoff` Hz, for a signal sampled at `samp_rate` Hz
+    pub fn high_pass(cutoff: f32, samp_rate: f32) -> Biquad {
+        let w0 = 2.0 * PI * cutoff as f64 / samp_rate as f64;
+        let alpha = w0.sin() * std::f64::consts::FRAC_1_SQRT_2;
+        let a0 = 1.0 + alpha;
+        Biquad {
+            b0: (1.0 + w0.cos()) / 2.0 / a0,
+            b1: -(1.0 + w0.cos()) / a0,
+            b2: (1.0 + w0.cos()) / 2.0 / a0,
+            a1: -2.0 * w0.cos() / a0,
+            a2: (1.0 - alpha) / a0,
+        }
+    }
+
+    /// Returns the pre-emphasis filter `y[n] = x[n] - coefficient * x[n-1]`,
+    /// which boosts high frequencies
+    pub fn pre_emphasis(coefficient: f32) -> Biquad {
+        Biquad { b0: 1.0, b1: -coefficient as f64, b2: 0.0, a1: 0.0, a2: 0.0 }
+    }
+
+    /// Returns `signal` filtered, the filter starting at rest
+    pub fn filter(&self, signal: &[f32]) -> Vec<f32> {
+        let mut filtered = signal.to_vec();
+        self.filter_in_place(&mut filtered);
+        filtered
+    }
+
+    /// Filters `signal` in place, the filter starting at rest
+    pub fn filter_in_place(&self, signal: &mut [f32]) {
+        // transposed direct form II
+        let (mut s1, mut s2) = (0.0, 0.0);
+        for sample in signal.iter_mut() {
+            let x = *sample as f64;
+            let y = self.b0 * x + s1;
+            s1 = self.b1 * x - self.a1 * y + s2;
+            s2 = self.b2 * x - self.a2 * y;
+            *sample = y as f32;
+        }
+    }
+
+    /// Returns the gain of the filter at `frequency` Hz, for a signal
+    /// sampled at `samp_rate` Hz
+    pub fn response(&self, frequency: f32, samp_rate: f32) -> f32 {
+        let w = 2.0 * PI * frequency as f64 / samp_rate as f64;
+        // z^-1 and z^-2 on the unit circle
+        let (c1, s1, c2, s2) = (w.cos(), -w.sin(), (2.0 * w).cos(), -(2.0 * w).sin());
+        let num = (self.b0 + self.b1 * c1 + self.b2 * c2, self.b1 * s1 + self.b2 * s2);
+        let den = (1.0 + self.a1 * c1 + self.a2 * c2, self.a1 * s1 + self.a2 * s2);
+        (num.0.hypot(num.1) / den.0.hypot(den.1)) as f32
+    }
+}
+
+#[cfg(test)]
+mod filter_tests {
+    use super::*;
+
+    #[test]
+    fn filter_follows_difference_equation() {
+        let biquad = Biquad { b0: 0.5, b1: 0.25, b2: 0.0, a1: -0.5, a2: 0.0 };
+        // y[n] = 0.5 x[n] + 0.25 x[n-1] + 0.5 y[n-1]
+        assert_eq!(vec![0.5, 0.5, 0.25], biquad.filter(&[1.0, 0.0, 0.0]));
+    }
+
+    /// Returns the amplitude of a second of sine of frequency `freq` filtered
+    /// by `biquad`, from the root mean square of its last half second, once
+    /// the filter is in its steady state
+    fn filtered_amplitude(biquad: &Biquad, freq: f32, samp_rate: f32) -> f32 {
+        let signal: Vec<f32> = (0..samp_rate as usize)
+            .map(|n| (2.0 * std::f32::consts::PI * freq * n as f32 / samp_rate).sin())
+            .collect();
+        let filtered = biquad.filter(&signal);
+        let steady = &filtered[filtered.len() / 2..];
+        (2.0 * steady.iter().map(|s| s * s).sum::<f32>() / steady.len() as f32).sqrt()
+    }
+
+    #[test]
+    fn high_pass_response() {
+        let biquad = Biquad::high_pass(100.0, 8000.0);
+        let half = std::f32::consts::FRAC_1_SQRT_2;
+        assert!((biquad.response(100.0, 8000.0) - half).abs() < 1e-4);
+        assert!((biquad.response(1000.0, 8000.0) - 1.0).abs() < 0.01);
+        // 12 dB per octave under the cut-off frequency
+        assert!((biquad.response(10.0, 8000.0) - 0.01).abs() < 0.001);
+        assert!(biquad.response(0.0, 8000.0) < 1e-6);
+        for freq in [30.0, 100.0, 440.0] {
+            let expected = biquad.response(freq, 8000.0);
+            assert!((filtered_amplitude(&biquad, freq, 8000.0) - expected).abs() < 0.01);
+        }
+    }
+
+    #[test]
+    fn pre_emphasis_response() {
+        let biquad = Biquad::pre_emphasis(0.9);
+        assert!((biquad.response(0.0, 8000.0) - 0.1).abs() < 1e-6);
+        assert!((biquad.response(4000.0, 8000.0) - 1.9).abs() < 1e-6);
+        let expected = biquad.response(1000.0, 8000.0);
+        assert!((filtered_amplitude(&biquad, 1000.0, 8000.0) - expected).abs() < 0.01);
+    }
+
+    #[test]
+    fn response_of_moving_average() {
+        let biquad = Biquad { b0: 0.5, b1: 0.5, b2: 0.0, a1: 0.0, a2: 0.0 };
+        assert!((biquad.response(0.0, 8000.0) - 1.0).abs() < 1e-6);
+        assert!(biquad.response(4000.0, 8000.0).abs() < 1e-6);
+        let half = std::f32::consts::FRAC_1_SQRT_2;
+        assert!((biquad.response(2000.0, 8000.0) - half).abs() < 1e-6);
+    }
+}
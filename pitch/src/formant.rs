@@ -0,0 +1,115 @@
+use rustfft::num_complex::Complex;
+
+/// Width, in Hz, of the spectral envelope smoothing of frames without pitch
+const DEFAULT_ENVELOPE_WIDTH: f32 = 500.0;
+
+/// Returns the number of bins of a frame of `fsize` samples over which its
+/// spectral envelope is smoothed: two harmonics of its main `frequency`, so
+/// that the envelope does not follow the harmonics themselves.
+pub fn envelope_width(frequency: f32, fsize: usize, samp_rate: f32) -> usize {
+    let hz = if frequency > 0.0 { 2.0 * frequency } else { DEFAULT_ENVELOPE_WIDTH };
+    ((hz * fsize as f32 / samp_rate).round() as usize).max(3)
+}
+
+/// Returns the spectral envelope of the bins 0 to `frame.len() / 2` of
+/// `frame`: the mean magnitude of the `width` bins around each bin
+pub fn spectral_envelope(frame: &[Complex<f32>], width: usize) -> Vec<f32> {
+    let half = frame.len() / 2;
+    let magnitudes: Vec<f32> = frame[..=half].iter().map(|c| c.norm()).collect();
+    (0..=half)
+        .map(|k| {
+            let start = k.saturating_sub(width / 2);
+            let end = (k + width / 2 + 1).min(magnitudes.len());
+            magnitudes[start..end].iter().sum::<f32>() / (end - start) as f32
+        })
+        .collect()
+}
+
+/// Returns the value of `envelope` at the fractional bin `bin`, linearly
+/// interpolated, and 0 above its last bin
+fn envelope_at(envelope: &[f32], bin: f32) -> f32 {
+    let k = bin.floor() as usize;
+    if k + 1 >= envelope.len() {
+        return if k + 1 == envelope.len() { envelope[k] } else { 0.0 };
+    }
+    let progress = bin - k as f32;
+    envelope[k] + progress * (envelope[k + 1] - envelope[k])
+}
+
+/// Moves the spectral envelope of `frame`, smoothed over `width` bins, so
+/// that bin k takes the envelope of bin `k * factor`, keeping its fine
+/// structure: a factor under 1.0 moves the formants up. Bins k and
+/// `frame.len() - k` are scaled alike, so that the frame stays the spectrum
+/// of a real signal.
+pub fn shift_envelope(frame: &mut [Complex<f32>], factor: f32, width: usize) {
+    let envelope = spectral_envelope(frame, width);
+    let floor = envelope.iter().fold(0.0f32, |max, e| max.max(*e)) * 1e-3
+        + f32::MIN_POSITIVE;
+    let len = frame.len();
+    for (k, e) in envelope.iter().enumerate() {
+        let gain = (envelope_at(&envelope, k as f32 * factor) + floor) / (e + floor);
+        frame[k] *= gain;
+        if k != 0 && k != len - k {
+            frame[len - k] *= gain;
+        }
+    }
+}
+
+#[cfg(test)]
+mod formant_tests {
+    use super::*;
+
+    /// Spectrum of a real signal of `len` samples, whose magnitude is a peak
+    /// around bin `peak`
+    fn formant_spectrum(len: usize, peak: f32) -> Vec<Complex<f32>> {
+        (0..len)
+            .map(|k| {
+                let bin = k.min(len - k) as f32;
+                let magnitude = (-((bin - peak) / 10.0).powi(2)).exp() + 0.01;
+                let phase = if bin == 0.0 || 2 * k == len { 0.0 } else { 0.3 };
+                Complex::from_polar(magnitude, if 2 * k < len { phase } else { -phase })
+            })
+            .collect()
+    }
+
+    fn peak_bin(frame: &[Complex<f32>]) -> usize {
+        (0..=frame.len() / 2)
+            .max_by(|&a, &b| frame[a].norm().total_cmp(&frame[b].norm()))
+            .unwrap()
+    }
+
+    #[test]
+    fn envelope_width_covers_two_harmonics() {
+        assert_eq!(26, envelope_width(100.0, 1024, 8000.0));
+        assert_eq!(64, envelope_width(0.0, 1024, 8000.0));
+        assert_eq!(3, envelope_width(1.0, 1024, 8000.0));
+    }
+
+    #[test]
+    fn envelope_of_flat_spectrum_is_flat() {
+        let frame = vec![Complex::new(2.0, 0.0); 16];
+        assert_eq!(vec![2.0; 9], spectral_envelope(&frame, 5));
+    }
+
+    #[test]
+    fn shift_envelope_moves_formant() {
+        let mut frame = formant_spectrum(1024, 100.0);
+        assert_eq!(100, peak_bin(&frame));
+        shift_envelope(&mut frame, 0.5, 3);
+        assert!((peak_bin(&frame) as i32 - 200).abs() <= 2);
+        // still the spectrum of a real signal
+        for k in 1..512 {
+            assert!((frame[k] - frame[1024 - k].conj()).norm() < 1e-5);
+        }
+    }
+
+    #[test]
+    fn shift_envelope_by_one_keeps_frame() {
+        let frame = formant_spectrum(256, 40.0);
+        let mut shifted = frame.clone();
+        shift_envelope(&mut shifted, 1.0, 7);
+        for (a, b) in frame.iter().zip(shifted) {
+            assert!((a - b).norm() < 1e-5);
+        }
+    }
+}
@@ -0,0 +1,405 @@
+use clap::Subcommand;
+use std::f64::consts::PI;
+use std::fs::File;
+use wav::BitDepth;
+use crate::convert;
+use crate::modulation;
+
+/// Test signals, synthesized in [-1.0, 1.0]
+#[derive(Subcommand, Clone, Copy, Debug, PartialEq)]
+pub enum Signal {
+    /// a pure tone
+    Sine {
+        /// the frequency of the tone, in Hz
+        #[arg(long, default_value_t = 440.0)]
+        frequency: f32,
+    },
+    /// a tone and its harmonics, the k-th one of amplitude 1/k
+    Harmonics {
+        /// the frequency of the fundamental, in Hz
+        #[arg(long, default_value_t = 440.0)]
+        frequency: f32,
+
+        /// the number of partials, the fundamental included. Those above
+        /// the Nyquist frequency are left out.
+        #[arg(long, default_value_t = 8)]
+        harmonics: usize,
+    },
+    /// a band-limited rising sawtooth
+    Sawtooth {
+        /// the frequency of the sawtooth, in Hz
+        #[arg(long, default_value_t = 440.0)]
+        frequency: f32,
+    },
+    /// a sine gliding from one frequency to another over the whole signal
+    Chirp {
+        /// the frequency at the start, in Hz
+        #[arg(long, default_value_t = 220.0)]
+        from: f32,
+
+        /// the frequency at the end, in Hz
+        #[arg(long, default_value_t = 880.0)]
+        to: f32,
+
+        /// glides linearly in Hz instead of exponentially (linearly in
+        /// semi tons)
+        #[arg(long)]
+        linear: bool,
+    },
+    /// a sine with a vibrato (see `modulation::Vibrato`)
+    Vibrato {
+        /// the frequency of the tone, in Hz
+        #[arg(long, default_value_t = 440.0)]
+        frequency: f32,
+
+        /// the modulations per second, in Hz
+        #[arg(long, default_value_t = 5.5)]
+        rate: f32,
+
+        /// the largest deviation from the frequency, in cents
+        #[arg(long, default_value_t = 50.0)]
+        depth: f32,
+
+        /// the seconds before the modulation starts
+        #[arg(long, default_value_t = 0.0)]
+        delay: f32,
+
+        /// the seconds for the depth to go from 0 to `depth`
+        #[arg(long, default_value_t = 0.0)]
+        fade_in: f32,
+    },
+    /// uniform white noise
+    WhiteNoise {
+        /// the seed of the pseudo-random generator
+        #[arg(long, default_value_t = 1)]
+        seed: u32,
+    },
+    /// pink noise, whose power falls by 3 dB per octave
+    PinkNoise {
+        /// the seed of the pseudo-random generator
+        #[arg(long, default_value_t = 1)]
+        seed: u32,
+    },
+    /// digital silence
+    Silence,
+}
+
+/// Returns the next value of the linear congruential generator of state
+/// `seed`, between -1.0 and 1.0
+fn next_random(seed: &mut u32) -> f32 {
+    *seed = seed.wrapping_mul(1664525).wrapping_add(1013904223);
+    (*seed as f64 / u32::MAX as f64 * 2.0 - 1.0) as f32
+}
+
+/// Returns the sines of the phases accumulated over the instantaneous
+/// frequencies, in Hz, given by `frequency_at(time)`
+fn swept_sine<F>(len: usize, samp_rate: f64, frequency_at: F) -> Vec<f32>
+where
+    F: Fn(f64) -> f64,
+{
+    let mut phase = 0.0f64;
+    (0..len)
+        .map(|n| {
+            let value = phase.sin() as f32;
+            phase += 2.0 * PI * frequency_at(n as f64 / samp_rate) / samp_rate;
+            phase %= 2.0 * PI;
+            value
+        })
+        .collect()
+}
+
+/// Returns the polynomial correction of the discontinuity of a sawtooth at
+/// phase `t`, in periods, for a phase increment `dt` (PolyBLEP)
+fn poly_blep(t: f64, dt: f64) -> f64 {
+    if t < dt {
+        let t = t / dt;
+        2.0 * t - t * t - 1.0
+    } else if t > 1.0 - dt {
+        let t = (t - 1.0) / dt;
+        t * t + 2.0 * t + 1.0
+    } else {
+        0.0
+    }
+}
+
+/// Returns `signal` scaled so that its peak is `amplitude`, unchanged if it
+/// is silent
+fn with_peak(mut signal: Vec<f32>, amplitude: f32) -> Vec<f32> {
+    let peak = signal.iter().fold(0.0f32, |max, s| max.max(s.abs()));
+    if peak > 0.0 {
+        signal.iter_mut().for_each(|s| *s *= amplitude / peak);
+    }
+    signal
+}
+
+impl Signal {
+
+    /// Returns the highest frequency of the signal, in Hz, 0 for noises
+    /// and silence
+    fn highest_frequency(&self) -> f32 {
+        match *self {
+            Signal::Sine { frequency }
+            | Signal::Harmonics { frequency, .. }
+            | Signal::Sawtooth { frequency } => frequency,
+            Signal::Chirp { from, to, .. } => from.max(to),
+            Signal::Vibrato { frequency, depth, .. } => {
+                frequency * 2.0f32.powf(depth.abs() / 1200.0)
+            },
+            Signal::WhiteNoise { .. } | Signal::PinkNoise { .. } | Signal::Silence => 0.0,
+        }
+    }
+
+    /// Returns `duration` seconds of the signal sampled at `samp_rate` Hz,
+    /// scaled so that its peak is `amplitude` (1.0 being full scale)
+    pub fn synthesize(
+        &self,
+        samp_rate: f32,
+        duration: f32,
+        amplitude: f32
+    ) -> Result<Vec<f32>, &'static str> {
+        if samp_rate <= 0.0 || duration.is_nan() || duration < 0.0 {
+            return Err("Illegal sample rate or duration");
+        }
+        if !(0.0..=1.0).contains(&amplitude) {
+            return Err("The amplitude must be between 0.0 and 1.0");
+        }
+        let lowest = match *self {
+            Signal::Chirp { from, to, .. } => from.min(to),
+            _ => self.highest_frequency(),
+        };
+        // an exponential glide never leaves 0 Hz
+        let exponential = matches!(self, Signal::Chirp { linear: false, .. });
+        let nyquist = samp_rate / 2.0;
+        if lowest < 0.0
+            || (exponential && lowest == 0.0)
+            || self.highest_frequency() >= nyquist
+        {
+            return Err("Frequencies must be between 0 and the Nyquist frequency");
+        }
+
+        let len = (duration as f64 * samp_rate as f64).round() as usize;
+        let rate = samp_rate as f64;
+        let signal = match *self {
+            Signal::Sine { frequency } => swept_sine(len, rate, |_| frequency as f64),
+            Signal::Harmonics { frequency, harmonics } => {
+                let partials = (1..=harmonics)
+                    .take_while(|k| (*k as f32 * frequency) < samp_rate / 2.0);
+                let mut signal = vec![0.0; len];
+                for k in partials {
+                    let partial = swept_sine(len, rate, |_| k as f64 * frequency as f64);
+                    for (s, p) in signal.iter_mut().zip(partial) {
+                        *s += p / k as f32;
+                    }
+                }
+                signal
+            },
+            Signal::Sawtooth { frequency } => {
+                let dt = frequency as f64 / rate;
+                (0..len)
+                    .map(|n| {
+                        let t = (n as f64 * dt).fract();
+                        (2.0 * t - 1.0 - poly_blep(t, dt)) as f32
+                    })
+                    .collect()
+            },
+            Signal::Chirp { from, to, linear } => {
+                let (from, to) = (from as f64, to as f64);
+                let duration = duration.max(f32::EPSILON) as f64;
+                if linear {
+                    swept_sine(len, rate, |t| from + (to - from) * t / duration)
+                } else {
+                    swept_sine(len, rate, |t| from * (to / from).powf(t / duration))
+                }
+            },
+            Signal::Vibrato { frequency, rate: vibrato_rate, depth, delay, fade_in } => {
+                let vibrato =
+                    modulation::Vibrato { rate: vibrato_rate, depth, delay, fade_in };
+                swept_sine(len, rate, |t| frequency as f64 * vibrato.ratio_at(t) as f64)
+            },
+            Signal::WhiteNoise { seed } => {
+                let mut seed = seed;
+                (0..len).map(|_| next_random(&mut seed)).collect()
+            },
+            Signal::PinkNoise { seed } => {
+                // Paul Kellet's filter of white noise
+                let mut seed = seed;
+                let mut b = [0.0f32; 7];
+                (0..len)
+                    .map(|_| {
+                        let white = next_random(&mut seed);
+                        b[0] = 0.99886 * b[0] + white * 0.0555179;
+                        b[1] = 0.99332 * b[1] + white * 0.0750759;
+                        b[2] = 0.96900 * b[2] + white * 0.153852;
+                        b[3] = 0.86650 * b[3] + white * 0.3104856;
+                        b[4] = 0.55000 * b[4] + white * 0.5329522;
+                        b[5] = -0.7616 * b[5] - white * 0.0168980;
+                        let pink = b[..6].iter().sum::<f32>() + b[6] + white * 0.5362;
+                        b[6] = white * 0.115926;
+                        pink
+                    })
+                    .collect()
+            },
+            Signal::Silence => vec![0.0; len],
+        };
+        Ok(with_peak(signal, amplitude))
+    }
+}
+
+/// Writes `signal`, in [-1.0, 1.0], sampled at `samp_rate` Hz, to a mono
+/// .wav file at `path` of `bits_per_sample` bits: 16 or 24 bits PCM, or 32
+/// bits float
+pub fn write_wav(
+    path: &str,
+    signal: &[f32],
+    samp_rate: u32,
+    bits_per_sample: u16
+) -> Result<(), &'static str> {
+    let scaled = |full_scale: f32| -> Vec<f32> {
+        signal.iter().map(|s| s.clamp(-1.0, 1.0) * full_scale).collect()
+    };
+    let data = match bits_per_sample {
+        16 => BitDepth::Sixteen(convert::to_i16(&scaled(32767.0))),
+        // written left-justified in 32 bits, the low byte being dropped
+        24 => BitDepth::TwentyFour(convert::to_i32(&scaled(2147483647.0))),
+        32 => BitDepth::ThirtyTwoFloat(scaled(1.0)),
+        _ => return Err("Unsupported bit depth"),
+    };
+    let format = if bits_per_sample == 32 {
+        wav::header::WAV_FORMAT_IEEE_FLOAT
+    } else {
+        wav::header::WAV_FORMAT_PCM
+    };
+    let header = wav::Header::new(format, 1, samp_rate, bits_per_sample);
+    let mut writer = match File::create(path) {
+        Ok(writer) => writer,
+        Err(_) => return Err("could not create file for the generated signal"),
+    };
+    match wav::write(header, &data, &mut writer) {
+        Ok(()) => Ok(()),
+        Err(_) => Err("could not create file for the generated signal"),
+    }
+}
+
+#[cfg(test)]
+mod generate_tests {
+    use super::*;
+    use crate::analysis::pitch_track;
+
+    /// Returns the median of the pitch detected over `signal`
+    fn pitch_of(signal: &[f32], samp_rate: f32) -> f32 {
+        let mut frequencies: Vec<f32> = pitch_track(signal, 2048, 512, samp_rate)
+            .iter()
+            .map(|f| f.frequency)
+            .collect();
+        frequencies.sort_by(|a, b| a.partial_cmp(b).unwrap());
+        frequencies[frequencies.len() / 2]
+    }
+
+    #[test]
+    fn tones_have_their_pitch_and_peak() {
+        let tones = [
+            Signal::Sine { frequency: 440.0 },
+            Signal::Harmonics { frequency: 220.0, harmonics: 6 },
+            Signal::Sawtooth { frequency: 330.0 },
+        ];
+        for tone in tones {
+            let signal = tone.synthesize(44100.0, 1.0, 0.5).unwrap();
+            assert_eq!(44100, signal.len());
+            let peak = signal.iter().fold(0.0f32, |max, s| max.max(s.abs()));
+            assert!((peak - 0.5).abs() < 1e-6);
+            let expected = tone.highest_frequency();
+            let pitch = pitch_of(&signal, 44100.0);
+            assert!((pitch - expected).abs() < expected * 0.01, "{:?} {}", tone, pitch);
+        }
+        let silence = Signal::Silence.synthesize(8000.0, 0.5, 0.5).unwrap();
+        assert_eq!(vec![0.0; 4000], silence);
+    }
+
+    #[test]
+    fn chirp_and_vibrato_follow_their_frequencies() {
+        let glide = Signal::Chirp { from: 220.0, to: 880.0, linear: false };
+        let signal = glide.synthesize(44100.0, 4.0, 1.0).unwrap();
+        let start = pitch_of(&signal[..8192], 44100.0);
+        let middle = pitch_of(&signal[83000..93000], 44100.0);
+        let end = pitch_of(&signal[168000..], 44100.0);
+        assert!((start - 230.0).abs() < 15.0, "{}", start);
+        // an exponential glide is half way in semi tons half way in time
+        assert!((middle - 440.0).abs() < 15.0, "{}", middle);
+        assert!((end - 860.0).abs() < 25.0, "{}", end);
+        let linear = Signal::Chirp { from: 220.0, to: 880.0, linear: true };
+        let signal = linear.synthesize(44100.0, 4.0, 1.0).unwrap();
+        let middle = pitch_of(&signal[83000..93000], 44100.0);
+        assert!((middle - 550.0).abs() < 15.0, "{}", middle);
+
+        let vibrato = Signal::Vibrato {
+            frequency: 440.0, rate: 2.0, depth: 100.0, delay: 0.0, fade_in: 0.0
+        };
+        let signal = vibrato.synthesize(44100.0, 1.0, 1.0).unwrap();
+        // a quarter of period, at the top of the modulation
+        let top = pitch_of(&signal[5000..6000 + 2048], 44100.0);
+        assert!((top - 466.2).abs() < 10.0, "{}", top);
+    }
+
+    #[test]
+    fn noises_are_reproducible_and_pink_noise_falls() {
+        let noise =
+            |seed| Signal::WhiteNoise { seed }.synthesize(8000.0, 1.0, 1.0).unwrap();
+        let white = noise(3);
+        assert_eq!(white, noise(3));
+        assert_ne!(white, noise(4));
+        let mean = white.iter().sum::<f32>() / white.len() as f32;
+        assert!(mean.abs() < 0.05, "{}", mean);
+
+        // power of the first differences relative to the power: low for
+        // a signal whose power is in low frequencies
+        let roughness = |x: &[f32]| {
+            let power: f32 = x.iter().map(|s| s * s).sum();
+            x.windows(2).map(|w| (w[1] - w[0]).powi(2)).sum::<f32>() / power
+        };
+        let pink = Signal::PinkNoise { seed: 3 }.synthesize(8000.0, 1.0, 1.0).unwrap();
+        assert!((roughness(&white) - 2.0).abs() < 0.2, "{}", roughness(&white));
+        assert!(roughness(&pink) < 0.5, "{}", roughness(&pink));
+    }
+
+    #[test]
+    fn illegal_signals() {
+        let sine = Signal::Sine { frequency: 5000.0 };
+        assert!(sine.synthesize(8000.0, 1.0, 0.5).is_err());
+        assert!(sine.synthesize(44100.0, -1.0, 0.5).is_err());
+        assert!(sine.synthesize(44100.0, 1.0, 1.5).is_err());
+        let chirp = Signal::Chirp { from: -1.0, to: 440.0, linear: true };
+        assert!(chirp.synthesize(44100.0, 1.0, 0.5).is_err());
+    }
+
+    #[test]
+    fn write_and_read_back() {
+        let sine = Signal::Sine { frequency: 440.0 };
+        let signal = sine.synthesize(8000.0, 0.5, 0.5).unwrap();
+        for (bits, full_scale) in [(16, 32767.0), (24, 2147483647.0), (32, 1.0)] {
+            let path = std::env::temp_dir().join(format!("pitch_generate_{}.wav", bits));
+            let path = path.to_str().unwrap();
+            write_wav(path, &signal, 8000, bits).unwrap();
+            let (header, data) = convert::read_wav(path).unwrap();
+            assert_eq!((8000, bits), (header.sampling_rate, header.bits_per_sample));
+            assert_eq!(signal.len(), data.len());
+            for (d, s) in data.iter().zip(&signal) {
+                assert!((d / full_scale - s).abs() < 1e-4, "{} {}", d, s);
+            }
+
+            // the level read by another crate
+            let mut reader = hound::WavReader::open(path).unwrap();
+            let peak = match reader.spec().sample_format {
+                hound::SampleFormat::Float => {
+                    reader.samples::<f32>().map(|s| s.unwrap().abs()).fold(0.0, f32::max)
+                },
+                hound::SampleFormat::Int => {
+                    let max = reader.samples::<i32>().map(|s| s.unwrap().abs()).max();
+                    max.unwrap() as f32 / 2f32.powi(bits as i32 - 1)
+                },
+            };
+            assert!((peak - 0.5).abs() < 1e-3, "{} bits peak {}", bits, peak);
+        }
+        assert!(write_wav("pitch_generate.wav", &signal, 8000, 8).is_err());
+        assert!(write_wav("pitch_generate.wav", &signal, 8000, 12).is_err());
+    }
+}
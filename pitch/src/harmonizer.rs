@@ -0,0 +1,271 @@
+use std::f32::consts::FRAC_PI_4;
+use std::str::FromStr;
+use crate::analysis::{self, midi_note_number, smoothing::Smoothing, PitchFrame, Voicing};
+use crate::analysis::preprocess::Preprocessing;
+use crate::config::ptconfig::{Key, Scale};
+use crate::formant;
+
+/// Interval between the lead voice and a harmony voice
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Interval {
+    /// a fixed number of semi tons, negative below the lead
+    Semitones(f32),
+    /// a number of degrees of the musical scale, e.g 2 for a third above
+    Diatonic(i32),
+}
+
+impl Interval {
+
+    /// Returns the scale factor of the harmony voice when the lead sings the
+    /// MIDI note number `note`. Diatonic intervals are counted from the note
+    /// of `scale` in `key` nearest to `note`, so that the interval in semi
+    /// tons follows the scale.
+    pub fn ratio(&self, note: f32, key: Key, scale: Scale) -> f32 {
+        match self {
+            Interval::Semitones(semitones) => 2.0f32.powf(semitones / 12.0),
+            Interval::Diatonic(steps) => {
+                let notes: Vec<i32> = (0..128)
+                    .filter(|n| {
+                        let degree = (n + 12 - key.semitones() as i32) % 12;
+                        scale.degrees().contains(&(degree as u8))
+                    })
+                    .collect();
+                let nearest = (0..notes.len())
+                    .min_by(|&a, &b| {
+                        (notes[a] as f32 - note).abs()
+                            .total_cmp(&(notes[b] as f32 - note).abs())
+                    })
+                    .unwrap();
+                let target = (nearest as i32 + steps).clamp(0, notes.len() as i32 - 1);
+                2.0f32.powf((notes[target as usize] - notes[nearest]) as f32 / 12.0)
+            },
+        }
+    }
+}
+
+/// A harmony voice: the lead voice transposed by an interval, with its own
+/// gain and position in the stereo field
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Voice {
+    pub interval: Interval,
+    pub gain: f32,
+    pub pan: f32,             // from -1.0 (left) to 1.0 (right)
+    pub formant: Option<f32>, // formant scale factor, None if they follow the pitch
+}
+
+impl FromStr for Voice {
+    type Err = &'static str;
+
+    /// Parses a voice written `<INTERVAL>[,gain=<GAIN>][,pan=<PAN>]
+    /// [,formant=<FACTOR>]`, where the interval is a number of semi tons,
+    /// e.g `-5` or `+7`, or of degrees of the scale when it ends with `d`,
+    /// e.g `2d` for a third above. The gain is 1.0 and the voice is centered
+    /// by default.
+    fn from_str(s: &str) -> Result<Voice, Self::Err> {
+        let mut fields = s.split(',').map(str::trim);
+        let interval = match fields.next() {
+            Some(i) if i.ends_with('d') => match i[..i.len() - 1].parse() {
+                Ok(steps) => Interval::Diatonic(steps),
+                Err(_) => return Err("invalid diatonic interval"),
+            },
+            Some(i) => match i.parse() {
+                Ok(semitones) => Interval::Semitones(semitones),
+                Err(_) => return Err("invalid interval"),
+            },
+            None => return Err("missing interval"),
+        };
+        let mut voice = Voice { interval, gain: 1.0, pan: 0.0, formant: None };
+        for field in fields {
+            let (name, value) = field.split_once('=').ok_or("invalid voice setting")?;
+            let value: f32 = value.trim().parse().map_err(|_| "invalid voice setting")?;
+            match name.trim() {
+                "gain" => voice.gain = value,
+                "pan" if (-1.0..=1.0).contains(&value) => voice.pan = value,
+                "formant" if value > 0.0 => voice.formant = Some(value),
+                "pan" | "formant" => return Err("voice setting out of range"),
+                _ => return Err("unknown voice setting"),
+            }
+        }
+        Ok(voice)
+    }
+}
+
+impl Voice {
+
+    /// Returns the scale factor of each frame of `track`. Diatonic intervals
+    /// follow the pitch of voiced frames and keep their last value on other
+    /// frames.
+    fn scale_factors(
+        &self,
+        track: &[PitchFrame],
+        key: Key,
+        scale: Scale,
+        voicing: &Voicing
+    ) -> Vec<f32> {
+        let tonic = 60.0 + key.semitones() as f32;
+        let mut ratio = self.interval.ratio(tonic, key, scale);
+        track
+            .iter()
+            .map(|f| {
+                if voicing.is_voiced(f) && f.frequency > 0.0 {
+                    let note = midi_note_number(f.frequency);
+                    ratio = self.interval.ratio(note, key, scale);
+                }
+                ratio
+            })
+            .collect()
+    }
+}
+
+/// Returns the gains of the left and right channels of a signal at `pan`,
+/// keeping its power constant
+fn pan_gains(pan: f32) -> (f32, f32) {
+    let angle = (pan + 1.0) * FRAC_PI_4;
+    (angle.cos(), angle.sin())
+}
+
+/// Generates harmony voices from a lead voice and mixes them with it
+#[derive(Clone, Debug, PartialEq)]
+pub struct Harmonizer {
+    pub voices: Vec<Voice>,
+    pub key: Key,          // key of the scale of diatonic intervals
+    pub scale: Scale,      // scale of diatonic intervals
+    pub dry: f32,          // gain of the lead voice, centered
+    pub voicing: Voicing,  // frames following diatonic intervals
+    pub smoothing: Smoothing,
+    pub preprocessing: Preprocessing, // of the signal seen by the pitch detector
+}
+
+impl Harmonizer {
+
+    /// Returns the left and right channels of the mix of `signal` and of its
+    /// harmony voices. The signal is analyzed once with the phase vocoder,
+    /// each voice being synthesized from the same analyzed frames.
+    pub fn harmonize(
+        &self,
+        signal: &[f32],
+        fsize: usize,
+        hopa: usize,
+        samp_rate: f32
+    ) -> [Vec<f32>; 2] {
+        let frames = crate::frame_slices(signal, fsize, hopa);
+        let analyzed_frames =
+            crate::parallelized_analysis(&crate::pool::global(), &frames, hopa);
+        let track = analysis::preprocessed_pitch_frames(
+            signal,
+            &frames,
+            &analyzed_frames,
+            hopa,
+            samp_rate,
+            &self.preprocessing
+        );
+        let track = self.smoothing.apply(&track, &self.voicing);
+
+        let (left_gain, right_gain) = pan_gains(0.0);
+        let mut left: Vec<f32> =
+            signal.iter().map(|s| self.dry * left_gain * s).collect();
+        let mut right: Vec<f32> =
+            signal.iter().map(|s| self.dry * right_gain * s).collect();
+        for voice in &self.voices {
+            let scale_factors =
+                voice.scale_factors(&track, self.key, self.scale, &self.voicing);
+            let shifted = crate::phase_vocoder_synthesis(
+                &analyzed_frames,
+                hopa,
+                samp_rate,
+                &scale_factors,
+                signal.len(),
+                |i, frame| if let Some(formant) = voice.formant {
+                    let width =
+                        formant::envelope_width(track[i].frequency, fsize, samp_rate);
+                    formant::shift_envelope(frame, scale_factors[i] / formant, width);
+                }
+            );
+            let (left_gain, right_gain) = pan_gains(voice.pan);
+            for (n, s) in shifted.iter().enumerate() {
+                left[n] += voice.gain * left_gain * s;
+                right[n] += voice.gain * right_gain * s;
+            }
+        }
+        [left, right]
+    }
+}
+
+#[cfg(test)]
+mod harmonizer_tests {
+    use super::*;
+
+    fn voice(interval: Interval, pan: f32, formant: Option<f32>) -> Voice {
+        Voice { interval, gain: 1.0, pan, formant }
+    }
+
+    /// Returns the median frequency detected in `signal`
+    fn median_frequency(signal: &[f32], samp_rate: f32) -> f32 {
+        let track = analysis::pitch_track(signal, 1024, 256, samp_rate);
+        let mut frequencies: Vec<f32> = track
+            .iter()
+            .map(|f| f.frequency)
+            .collect();
+        frequencies.sort_by(|a, b| a.total_cmp(b));
+        frequencies[frequencies.len() / 2]
+    }
+
+    #[test]
+    fn diatonic_intervals_follow_scale() {
+        let third = Interval::Diatonic(2);
+        // A4 -> C5 is a minor third, C5 -> E5 a major third in C major
+        assert_eq!(2.0f32.powf(3.0 / 12.0), third.ratio(69.0, Key::C, Scale::Major));
+        assert_eq!(2.0f32.powf(4.0 / 12.0), third.ratio(72.1, Key::C, Scale::Major));
+        // C5 -> A4 a sixth below
+        let below = Interval::Diatonic(-2);
+        assert_eq!(2.0f32.powf(-3.0 / 12.0), below.ratio(72.0, Key::C, Scale::Major));
+        // A4 -> C#5 in A major
+        assert_eq!(2.0f32.powf(4.0 / 12.0), third.ratio(69.0, Key::A, Scale::Major));
+        let fifth = Interval::Semitones(7.0);
+        assert_eq!(2.0f32.powf(7.0 / 12.0), fifth.ratio(0.0, Key::C, Scale::Major));
+    }
+
+    #[test]
+    fn parse_voices() {
+        let expected = voice(Interval::Semitones(-5.0), 0.0, None);
+        assert_eq!(Ok(expected), "-5".parse());
+        let expected = Voice {
+            interval: Interval::Diatonic(2), gain: 0.5, pan: -0.25, formant: Some(1.0)
+        };
+        assert_eq!(Ok(expected), "2d, gain=0.5, pan=-0.25, formant=1".parse());
+        assert!("third".parse::<Voice>().is_err());
+        assert!("2d,pan=2".parse::<Voice>().is_err());
+        assert!("2d,speed=2".parse::<Voice>().is_err());
+    }
+
+    #[test]
+    fn pan_keeps_power() {
+        assert_eq!((1.0, 0.0), pan_gains(-1.0));
+        let (left, right) = pan_gains(0.3);
+        assert!((left * left + right * right - 1.0).abs() < 1e-6);
+    }
+
+    #[test]
+    fn voices_are_shifted_and_panned() {
+        let samp_rate = 8000.0;
+        let signal: Vec<f32> = (0..16000)
+            .map(|n| (2.0 * std::f32::consts::PI * 440.0 * n as f32 / samp_rate).sin())
+            .collect();
+        let harmonizer = Harmonizer {
+            voices: vec![
+                voice(Interval::Semitones(4.0), -1.0, None),
+                voice(Interval::Diatonic(2), 1.0, Some(1.0)),
+            ],
+            key: Key::C,
+            scale: Scale::Major,
+            dry: 0.0,
+            voicing: Voicing::new(1.0),
+            smoothing: Smoothing::none(),
+            preprocessing: Preprocessing::none(1.0),
+        };
+        let [left, right] = harmonizer.harmonize(&signal, 1024, 256, samp_rate);
+        assert_eq!(signal.len(), left.len());
+        assert!((median_frequency(&left, samp_rate) - 554.37).abs() < 5.0);
+        assert!((median_frequency(&right, samp_rate) - 523.25).abs() < 5.0);
+    }
+}
@@ -1,80 +1,415 @@
 use rustfft::{num_complex::Complex, FftPlanner};
 use std::fs::File;
 use std::path::Path;
-use std::sync::Arc;
-use std::thread;
 use std::cmp::min;
 use crate::config::ptconfig::PTConfiguration;
 use crate::config::ptconfig::PTConfiguration::Basic;
 use crate::config::ptconfig::PTConfiguration::PhaseVocoder;
+use crate::config::ptconfig::PTConfiguration::Vibrato;
+use crate::config::ptconfig::PTConfiguration::Harmonize;
 use crate::config::qcconfig::QCConfiguration;
+use crate::config::anconfig::AnalyzeConfiguration;
+use crate::config::genconfig::GenerateConfiguration;
+use crate::config::spconfig::SpectrogramConfiguration;
 use crate::config::ptconfig::RealTimeSubCommand;
+use crate::config::ptconfig::BatchOptions;
+use crate::config::ptconfig::{Key, Scale};
 
 pub mod config;
 pub mod convert;
 pub mod audio;
+pub mod control;
+pub mod midi;
+pub mod analysis;
+pub mod curve;
+pub mod modulation;
+pub mod formant;
+pub mod harmonizer;
+pub mod mix;
+pub mod filter;
+pub mod loudness;
+pub mod metrics;
+pub mod accuracy;
+pub mod align;
+pub mod report;
+pub mod generate;
+pub mod batch;
+pub mod spectrogram;
+pub mod study;
+pub mod pool;
+pub mod vocoder;
 
 pub fn transpose_pitch_real_time(
     config: &RealTimeSubCommand,
     samp_rate: f32,
     signal: &[f32]
 ) -> Result<Vec<f32>, &'static str> {
-    let transposition = match config {
+    let mut transposition = Vec::new();
+    transpose_pitch_real_time_with(
+        &mut None,
+        config,
+        samp_rate,
+        signal,
+        &mut transposition
+    )?;
+    Ok(transposition)
+}
+
+/// Same as `transpose_pitch_real_time()`, the phase vocoder running in the
+/// processing context in `vocoder`, reused from a call to the next if the
+/// frame size and shift between frames are unchanged. The transposition is
+/// written to `output`, cleared first, whose capacity is reused; with a fixed
+/// shift, the phase vocoder does not allocate once its buffers have grown.
+pub fn transpose_pitch_real_time_with(
+    vocoder: &mut Option<vocoder::Vocoder>,
+    config: &RealTimeSubCommand,
+    samp_rate: f32,
+    signal: &[f32],
+    output: &mut Vec<f32>
+) -> Result<(), &'static str> {
+    match config {
         RealTimeSubCommand::Basic { shift } => {
             let mut signal1 = vec![];
             signal1.extend_from_slice(&signal);
-            basic_transposer(&signal1, *shift, samp_rate)
+            output.clear();
+            output.extend(basic_transposer(&signal1, *shift, samp_rate));
         },
-        RealTimeSubCommand::PhaseVocoder { fsize, hopa, shift} => {
+        RealTimeSubCommand::PhaseVocoder {
+            fsize, hopa, shift, key, scale: musical_scale, retune, voicing,
+            preprocessing
+        } => {
+            let vocoder = vocoder::Vocoder::reuse(vocoder, *fsize, *hopa, samp_rate)?;
             let scale =  match shift {
                 Some(s) => *s,
-                None => match get_closest_scale_factor(signal, samp_rate) {
-                    Some(sc) => sc,
-                    None => return Err("could not find nearest note"),
-                },
+                None => vocoder
+                    .autotune(
+                        signal,
+                        &preprocessing.preprocessing(1.0),
+                        *key,
+                        *musical_scale,
+                        *voicing
+                    )?
+                    .powf(retune.clamp(0.0, 1.0)),
             };
-
-            phase_vocoder_transposer(signal, *fsize, *hopa, samp_rate, scale)
+            vocoder.transpose(signal, scale, output)?;
         },
-    };
-    Ok(transposition)
+    }
+    Ok(())
 }
 
 pub fn get_closest_scale_factor(signal : &[f32], samp_rate: f32) -> Option<f32> {
+    get_closest_scale_factor_in(signal, samp_rate, Key::C, Scale::Chromatic)
+}
+
+/// Returns the scale factor between the main frequency of `signal` and the
+/// nearest note of the musical scale `scale` in `key`
+pub fn get_closest_scale_factor_in(
+    signal : &[f32],
+    samp_rate: f32,
+    key: Key,
+    scale: Scale
+) -> Option<f32> {
     let mut planner = FftPlanner::new();
     let analyzed = apply_fft(&mut planner, &signal);
-    let freq = get_main_frequency(&analyzed, samp_rate);
-    let known_freq = known_frequencies();
-    let closest_i = find_index_of_nearest_to(freq,
-                                             0,
-                                             known_freq.len(),
-                                             &known_freq[..]);
+    scale_factor_to_nearest(get_main_frequency(&analyzed, samp_rate), key, scale)
+}
+
+/// Returns the scale factor between `freq` and the nearest note of the
+/// musical scale `scale` in `key`, None if `freq` is not positive. It does
+/// not allocate.
+fn scale_factor_to_nearest(freq: f32, key: Key, scale: Scale) -> Option<f32> {
+    if freq <= 0.0 {
+        return None;
+    }
+    let mut notes = [0.0; KNOWN_FREQUENCIES.len()];
+    let count = scale_notes(key, scale, &mut notes);
+    nearest_note(freq, &notes[..count]).map(|note| note / freq)
+}
+
+/// Correction applied by the static phase vocoder
+enum Correction {
+    /// the same scale factor for the whole signal
+    Global(f32),
+    /// tuning to the notes of a melody
+    Melody(midi::Melody),
+    /// following a target pitch curve
+    Curve(curve::PitchCurve),
+}
+
+impl Correction {
+
+    /// Returns the correction of `signal` to the pitch curve of the file at
+    /// `curve_path` if it is given (see `curve::PitchCurve::load()`), or else
+    /// to the melody of the MIDI file at `midi_path` if it is given (see
+    /// `midi::Melody::load()`), or else to the note nearest to the main
+    /// frequency of the whole signal
+    fn new(
+        signal: &[f32],
+        samp_rate: f32,
+        curve_path: Option<&str>,
+        midi_path: Option<&str>,
+        midi_track: Option<usize>,
+        midi_channel: Option<u8>,
+        midi_offset: f32
+    ) -> Result<Correction, &'static str> {
+        match (curve_path, midi_path) {
+            (Some(path), _) => Ok(Correction::Curve(curve::PitchCurve::load(path)?)),
+            (None, Some(path)) => Ok(Correction::Melody(midi::Melody::load(
+                path,
+                midi_track,
+                midi_channel,
+                midi_offset as f64
+            )?)),
+            (None, None) => match get_closest_scale_factor(signal, samp_rate) {
+                Some(s) => Ok(Correction::Global(s)),
+                None => Err("could not find nearest note"),
+            },
+        }
+    }
+
+    /// Returns the scale factor applied to each frame of `track`
+    fn scale_factors(&self, track: &[analysis::PitchFrame]) -> Vec<f32> {
+        match self {
+            Correction::Global(s) => vec![*s; track.len()],
+            Correction::Melody(melody) => melody_scale_factors(track, melody),
+            Correction::Curve(curve) => curve_scale_factors(track, curve),
+        }
+    }
+
+    /// Returns the scale factor applied to each frame of `track`, analyzed
+    /// `frame_rate` times per second, where frames that are not voiced are
+    /// not transposed. If `vibrato_depth` is given, only the pitch centre of
+    /// frames is corrected and their vibrato is multiplied by it (see
+    /// `analysis::vibrato::reapply()`).
+    fn voiced_scale_factors(
+        &self,
+        track: &[analysis::PitchFrame],
+        voicing: &analysis::Voicing,
+        vibrato_depth: Option<f32>,
+        frame_rate: f32
+    ) -> Vec<f32> {
+        let scale_factors = match vibrato_depth {
+            Some(depth) => {
+                let (centre, vibrato) =
+                    analysis::vibrato::split(track, voicing, frame_rate);
+                let centre_scale_factors = self.scale_factors(&centre);
+                analysis::vibrato::reapply(&centre_scale_factors, &vibrato, depth)
+            },
+            None => self.scale_factors(track),
+        };
+        voicing.apply(track, &scale_factors)
+    }
+
+    /// Returns the scale factor applied to each frame of `track`, as seen by
+    /// the pitch detector and smoothed by `smoothing` (see
+    /// `voiced_scale_factors()`), and the trace of the correction
+    fn traced_scale_factors(
+        &self,
+        track: &[analysis::PitchFrame],
+        smoothing: &analysis::smoothing::Smoothing,
+        voicing: &analysis::Voicing,
+        vibrato_depth: Option<f32>,
+        frame_rate: f32
+    ) -> (Vec<f32>, Vec<analysis::TraceFrame>) {
+        let track = smoothing.apply(track, voicing);
+        let scale_factors =
+            self.voiced_scale_factors(&track, voicing, vibrato_depth, frame_rate);
+        let trace = analysis::pitch_trace(&track, &scale_factors, voicing);
+        (scale_factors, trace)
+    }
+}
+
+/// Returns the scale factors tuning the frames of `track` to the note of
+/// `melody` held at the middle of each frame, or to the nearest note if there
+/// is none. Frames without main frequency are not transposed.
+fn melody_scale_factors(
+    track: &[analysis::PitchFrame],
+    melody: &midi::Melody
+) -> Vec<f32> {
+    let notes = known_frequencies();
+    track.iter().map(|pitch_frame| {
+        let freq = pitch_frame.frequency;
+        let target = match melody.note_at(pitch_frame.time) {
+            Some(key) => Some(midi::note_frequency(key)),
+            None => nearest_note(freq, &notes[..]),
+        };
+        match target {
+            Some(target) if freq > 0.0 => target / freq,
+            _ => 1.0,
+        }
+    }).collect()
+}
+
+/// Returns the scale factors tuning the frames of `track` to the frequency of
+/// `curve` at the middle of each frame. Frames without main frequency or
+/// target frequency are not transposed.
+fn curve_scale_factors(
+    track: &[analysis::PitchFrame],
+    curve: &curve::PitchCurve
+) -> Vec<f32> {
+    track.iter().map(|pitch_frame| {
+        let freq = pitch_frame.frequency;
+        match curve.target_at(pitch_frame.time) {
+            Some(target) if freq > 0.0 => target / freq,
+            _ => 1.0,
+        }
+    }).collect()
+}
+
+/// Returns the nearest note to `freq` in `notes`, which must be sorted
+fn nearest_note(freq: f32, notes: &[f32]) -> Option<f32> {
+    let closest_i = find_index_of_nearest_to(freq, 0, notes.len(), notes);
     if closest_i == -1 {
         return None;
     }
-    Some(freq / known_freq[closest_i as usize])
+    Some(notes[closest_i as usize])
 }
 
-/// Transposes the pitch using the given configuration
-pub fn transpose_pitch(config: PTConfiguration) -> Result<(), &'static str> {
+/// Transposes a .wav file using the given configuration, the transposed
+/// signal going through the `output` stage before being written
+pub fn transpose_pitch(
+    config: PTConfiguration,
+    output: &mix::OutputStage
+) -> Result<(), &'static str> {
     let path = match config {
         Basic { ref in_path, ..} => &in_path[..],
-        PhaseVocoder { ref in_path, .. } => &in_path[..]
+        PhaseVocoder { ref in_path, .. } => &in_path[..],
+        Vibrato { ref in_path, .. } => &in_path[..],
+        Harmonize { ref in_path, .. } => &in_path[..]
     };
 
-    let (header, signal) = convert::extract_data_from_wav(path);
+    let (mut header, signal) = convert::extract_data_from_wav(path);
+    let in_channels = header.channel_count;
     let samp_rate: f32 = header.sampling_rate as f32;
+    let full_scale = convert::full_scale(&header)?;
     let transposition = match config {
         Basic {shift, ..} => basic_transposer(&signal, shift, samp_rate),
-        PhaseVocoder {fsize, hopa, ..} => {
-            
-            let scale_factor = match get_closest_scale_factor(&signal, samp_rate) {
-                Some(s) => s,
-                None => return Err("could not find nearest note"),
+        PhaseVocoder {
+            fsize, hopa, ref curve, ref midi, midi_track, midi_channel,
+            midi_offset, voicing, smoothing, preprocessing, vibrato_depth,
+            ref pitch_track, ..
+        } => {
+            let smoothing = smoothing.smoothing();
+            let voicing = analysis::Voicing {
+                threshold: voicing,
+                ..analysis::Voicing::new(full_scale)
             };
-            phase_vocoder_transposer(&signal, fsize, hopa, samp_rate, scale_factor)
+            // what the pitch detector sees, pre-processed once
+            let preprocessing = preprocessing.preprocessing(full_scale);
+            let analyzed = preprocessing.apply(&signal, samp_rate);
+            let correction = Correction::new(
+                &analyzed,
+                samp_rate,
+                curve.as_deref(),
+                midi.as_deref(),
+                midi_track,
+                midi_channel,
+                midi_offset
+            )?;
+            let frame_rate = samp_rate / hopa as f32;
+            let mut trace = vec![];
+            let transposition = phase_vocoder_corrector_with(
+                &signal,
+                (!preprocessing.is_none()).then_some(&analyzed[..]),
+                fsize,
+                hopa,
+                samp_rate,
+                |t| {
+                    let (scale_factors, t) = correction.traced_scale_factors(
+                        t, &smoothing, &voicing, vibrato_depth, frame_rate
+                    );
+                    trace = t;
+                    scale_factors
+                }
+            );
+            if let Some(filename) = pitch_track {
+                analysis::save_trace(&trace, &output_path(filename))?;
+            }
+            transposition
+        },
+        Vibrato { fsize, hopa, rate, depth, delay, fade_in, .. } => {
+            if fsize == 0 || hopa == 0 || fsize > signal.len() {
+                return Err("Illegal frame size or shift between frames");
+            }
+            let vibrato = modulation::Vibrato { rate, depth, delay, fade_in };
+            add_vibrato(&signal, fsize, hopa, samp_rate, &vibrato)
         },
+        Harmonize {
+            fsize, hopa, ref voices, key, scale, dry, voicing, smoothing,
+            preprocessing, ..
+        } => {
+            if fsize == 0 || hopa == 0 || fsize > signal.len() {
+                return Err("Illegal frame size or shift between frames");
+            }
+            let harmonizer = harmonizer::Harmonizer {
+                voices: voices.clone(),
+                key,
+                scale,
+                dry,
+                voicing: analysis::Voicing {
+                    threshold: voicing,
+                    ..analysis::Voicing::new(full_scale)
+                },
+                smoothing: smoothing.smoothing(),
+                preprocessing: preprocessing.preprocessing(full_scale),
+            };
+            let [left, right] = harmonizer.harmonize(&signal, fsize, hopa, samp_rate);
+
+            // the output is stereo, with interleaved channels
+            header = wav::Header::new(
+                header.audio_format,
+                2,
+                header.sampling_rate,
+                header.bits_per_sample
+            );
+            left.iter().zip(&right).flat_map(|(l, r)| [*l, *r]).collect()
+        },
+    };
+
+    let filename = match config {
+        Basic {
+            ref out_filename, ..
+        } => &out_filename[..],
+        PhaseVocoder {
+            ref out_filename, ..
+        } => &out_filename[..],
+        Vibrato {
+            ref out_filename, ..
+        } => &out_filename[..],
+        Harmonize {
+            ref out_filename, ..
+        } => &out_filename[..],
     };
+    let out_path = output_path(filename);
+    write_transposition(header, in_channels, &signal, &transposition, output, &out_path)
+}
+
+/// Passes `transposition`, the transposition of `signal` read from a .wav
+/// file of `in_channels` channels, through the `output` stage and writes it
+/// to a .wav file at `out_path` with the format of `header`
+fn write_transposition(
+    header: wav::Header,
+    in_channels: u16,
+    signal: &[f32],
+    transposition: &[f32],
+    output: &mix::OutputStage,
+    out_path: &str
+) -> Result<(), &'static str> {
+    let samp_rate = header.sampling_rate as f32;
+
+    // the dry signal is duplicated on the channels added by the transposition
+    let channels = header.channel_count as usize;
+    let in_channels = in_channels.max(1) as usize;
+    let copies = (channels / in_channels).max(1);
+    let dry: Vec<f32> = signal
+        .chunks(in_channels)
+        .flat_map(|frame| std::iter::repeat_n(frame, copies).flatten().copied())
+        .collect();
+    // the transposition is mixed over the whole dry signal
+    let mut wet = transposition.to_vec();
+    wet.resize(dry.len(), 0.0);
+    let full_scale = convert::full_scale(&header)?;
+    let transposition = output.process(&dry, &wet, channels, samp_rate, full_scale);
 
     // creating BitDepth acording to source file BitDepth
     let bit_depth = match header.bits_per_sample {
@@ -92,18 +427,7 @@ pub fn transpose_pitch(config: PTConfiguration) -> Result<(), &'static str> {
     };
 
     // write to file
-    let filename = match config {
-        Basic {
-            ref out_filename, ..
-        } => &out_filename[..],
-        PhaseVocoder {
-            ref out_filename, ..
-        } => &out_filename[..],
-    };
-
-    let mut out_path = String::from("resources/outputs/");
-    out_path.push_str(filename);
-    let path = Path::new(&out_path);
+    let path = Path::new(out_path);
     let mut writer = match File::create(path) {
         Ok(writer) => writer,
         _ => return Err("could not create file for transposition")
@@ -114,46 +438,407 @@ pub fn transpose_pitch(config: PTConfiguration) -> Result<(), &'static str> {
     }
 }
 
+/// Transposes the .wav files of a batch, in parallel, the transposed signals
+/// going through the `output` stage before being written. A summary of the
+/// jobs is written to the manifest of `options`.
+pub fn transpose_batch(
+    options: &BatchOptions,
+    output: &mix::OutputStage
+) -> Result<batch::BatchSummary, &'static str> {
+    let inputs = batch::expand_inputs(&options.inputs)?;
+    let jobs = batch::plan(
+        &inputs,
+        &options.algo,
+        &options.shifts()?,
+        &options.fsize,
+        &options.hopa,
+        &options.template
+    )?;
+    let summary = batch::run(&jobs, &pool::global(), |job| transpose_job(job, output));
+
+    let path = output_path(&options.manifest);
+    let written = File::create(&path)
+        .map_err(|_| ())
+        .and_then(|file| serde_json::to_writer_pretty(file, &summary).map_err(|_| ()));
+    match written {
+        Ok(()) => Ok(summary),
+        Err(()) => Err("could not write the batch manifest"),
+    }
+}
+
+/// Runs a job of a batch
+fn transpose_job(
+    job: &batch::Job,
+    output: &mix::OutputStage
+) -> Result<(), &'static str> {
+    let (header, signal) = convert::read_wav(&job.input)?;
+    let transposition = transpose_signal(
+        &signal,
+        header.sampling_rate as f32,
+        job.algorithm,
+        job.shift,
+        job.fsize.unwrap_or(0),
+        job.hopa.unwrap_or(0)
+    )?;
+    let out_path = output_path(&job.output);
+    let in_channels = header.channel_count;
+    write_transposition(header, in_channels, &signal, &transposition, output, &out_path)
+}
+
+/// Transposes `signal`, sampled at `samp_rate` Hz, by `shift` with
+/// `algorithm`, the phase vocoder using frames of `fsize` samples every
+/// `hopa` samples as the static phase vocoder does
+pub fn transpose_signal(
+    signal: &[f32],
+    samp_rate: f32,
+    algorithm: batch::Algorithm,
+    shift: batch::Shift,
+    fsize: usize,
+    hopa: usize
+) -> Result<Vec<f32>, &'static str> {
+    match algorithm {
+        batch::Algorithm::Basic => {
+            let semitones = shift.semitones();
+            if (semitones - semitones.round()).abs() > 1e-3 {
+                return Err("the basic transposer shifts by whole semi tons");
+            }
+            Ok(basic_transposer(signal, semitones.round() as i32, samp_rate))
+        },
+        batch::Algorithm::PhaseVocoder => {
+            if fsize == 0 || hopa == 0 || fsize > signal.len() {
+                return Err("Illegal frame size or shift between frames");
+            }
+            // the engine of the static phase vocoder, with a constant correction
+            let ratio = shift.ratio();
+            Ok(phase_vocoder_corrector(signal, fsize, hopa, samp_rate, |track| {
+                vec![ratio; track.len()]
+            }))
+        },
+    }
+}
+
+/// Analyzes the pitch of a .wav file using the given configuration
+pub fn analyze(config: AnalyzeConfiguration) -> Result<(), &'static str> {
+    if config.midi.is_none() && config.pitch_track.is_none() {
+        return Err("Nothing to write, neither a MIDI file nor a pitch track");
+    }
+    let (header, signal) = convert::extract_data_from_wav(&config.in_path);
+    let samp_rate = header.sampling_rate as f32;
+    if config.fsize == 0 || config.hopa == 0 || config.fsize > signal.len() {
+        return Err("Illegal frame size or shift between frames");
+    }
+    let voicing = analysis::Voicing {
+        full_scale: convert::full_scale(&header)?,
+        silence: config.silence,
+        threshold: config.voicing,
+    };
+    let preprocessing = config.preprocessing.preprocessing(voicing.full_scale);
+    let analyzed = preprocessing.apply(&signal, samp_rate);
+    let smoothing = config.smoothing.smoothing();
+    let track = smoothing.apply(
+        &analysis::pitch_track(&analyzed, config.fsize, config.hopa, samp_rate),
+        &voicing
+    );
+
+    if let Some(ref filename) = config.pitch_track {
+        let correction = Correction::new(
+            &analyzed,
+            samp_rate,
+            config.curve.as_deref(),
+            config.melody.as_deref(),
+            config.melody_track,
+            config.melody_channel,
+            config.melody_offset
+        )?;
+        // the frames of the static phase vocoder, seen by its pitch detector
+        let (_, pitch_frames) = corrector_analysis(
+            &signal,
+            (!preprocessing.is_none()).then_some(&analyzed[..]),
+            config.fsize,
+            config.hopa,
+            samp_rate
+        );
+        let (_, trace) = correction.traced_scale_factors(
+            &pitch_frames,
+            &smoothing,
+            &voicing,
+            config.vibrato_depth,
+            samp_rate / config.hopa as f32
+        );
+        analysis::save_trace(&trace, &output_path(filename))?;
+    }
+
+    if let Some(ref filename) = config.midi {
+        let notes = analysis::transcribe(
+            &track,
+            config.hopa as f64 / samp_rate as f64,
+            &voicing,
+            config.min_note as f64
+        );
+        midi::notes_to_smf(&notes).save(&output_path(filename))?;
+    }
+    Ok(())
+}
+
+/// Generates a test signal using the given configuration
+pub fn generate(config: GenerateConfiguration) -> Result<(), &'static str> {
+    let signal = config.signal.synthesize(
+        config.samp_rate as f32,
+        config.duration,
+        config.amplitude
+    )?;
+    generate::write_wav(
+        &output_path(&config.out_filename),
+        &signal,
+        config.samp_rate,
+        config.bit_depth
+    )
+}
+
+/// Draws the spectrogram of a .wav file using the given configuration
+pub fn spectrogram(config: SpectrogramConfiguration) -> Result<(), &'static str> {
+    let (header, data) = convert::read_wav(&config.in_path)?;
+    if config.fsize == 0 || config.hopa == 0 {
+        return Err("Illegal frame size or shift between frames");
+    }
+    // the mean of the channels
+    let channels = header.channel_count.max(1) as usize;
+    let mut signal: Vec<f32> = data
+        .chunks(channels)
+        .map(|c| c.iter().sum::<f32>() / channels as f32)
+        .collect();
+    signal.resize(signal.len().max(config.fsize), 0.0);
+    let samp_rate = header.sampling_rate as f32;
+
+    let spectrogram = spectrogram::Spectrogram::new(
+        &signal,
+        samp_rate,
+        config.fsize,
+        config.hopa,
+        config.window
+    );
+    let default_min = match config.scale {
+        spectrogram::FrequencyScale::Log => 20.0,
+        _ => 0.0,
+    };
+    let rendering = spectrogram::Rendering {
+        width: config.width,
+        height: config.height,
+        scale: config.scale,
+        min_frequency: config.min_freq.unwrap_or(default_min),
+        max_frequency: config.max_freq.unwrap_or(samp_rate / 2.0),
+        db_range: config.db_range,
+        colormap: config.colormap,
+    };
+    let voicing = analysis::Voicing {
+        threshold: config.voicing,
+        ..analysis::Voicing::new(convert::full_scale(&header)?)
+    };
+    let track = config
+        .pitch
+        .then(|| analysis::pitch_track(&signal, config.fsize, config.hopa, samp_rate));
+    let image = rendering.render(&spectrogram, track.as_deref().map(|t| (t, &voicing)))?;
+    image.save_png(&output_path(&config.out_filename))
+}
+
+/// Returns the path of the output file named `filename`. Output files are
+/// stored in `resources/outputs/`.
+pub fn output_path(filename: &str) -> String {
+    let mut out_path = String::from("resources/outputs/");
+    out_path.push_str(filename);
+    out_path
+}
+
+/// Returns the loudness of the .wav file at `path`
+pub fn measure_loudness(path: &str) -> Result<loudness::Loudness, &'static str> {
+    let (header, signal) = convert::extract_data_from_wav(path);
+    if signal.is_empty() {
+        return Err("Unsupported codec");
+    }
+    Ok(loudness::measure(
+        &signal,
+        header.channel_count as usize,
+        header.sampling_rate as f32,
+        convert::full_scale(&header)?
+    ))
+}
+
+/// Signal and oracle of a quality check, ready to be compared
+struct Comparison {
+    signal: Vec<f32>, // amplitudes between -1 and 1
+    oracle: Vec<f32>, // amplitudes between -1 and 1
+    samp_rate: f32,   // sampling rate of both
+    full_scale: f32,  // maximal amplitude of the raw samples of the oracle
+}
+
+/// Returns the samples of the .wav file at `path` between -1 and 1, and its
+/// sampling rate, full scale and number of channels
+fn normalized_wav(path: &str) -> Result<(Vec<f32>, f32, f32, usize), &'static str> {
+    let (header, signal) = convert::read_wav(path)?;
+    let full_scale = convert::full_scale(&header)?;
+    let signal: Vec<f32> = signal.iter().map(|s| s / full_scale).collect();
+    let channels = header.channel_count as usize;
+    Ok((signal, header.sampling_rate as f32, full_scale, channels))
+}
+
+/// Returns the signal at `path_signal` and the oracle at `path_oracle`, the
+/// signal resampled at the sampling rate of the oracle, aligned on it if
+/// `options` ask to and brought to its length by their length policy
+fn compared_signals(
+    path_signal: &str,
+    path_oracle: &str,
+    options: &report::CheckOptions
+) -> Result<Comparison, &'static str> {
+    let (signal, samp_rate, _, channels) = normalized_wav(path_signal)?;
+    let (oracle, oracle_rate, full_scale, _) = normalized_wav(path_oracle)?;
+    let signal = align::resample_channels(&signal, channels, samp_rate, oracle_rate);
+    let (mut signal, mut oracle) = match options.max_lag {
+        Some(seconds) => {
+            let max_lag = (seconds * oracle_rate).round() as usize;
+            let lag = align::estimate_lag(&signal, &oracle, max_lag);
+            align::align(&signal, &oracle, lag)
+        },
+        None => (signal, oracle),
+    };
+    options.length.apply(&mut signal, &mut oracle)?;
+    Ok(Comparison { signal, oracle, samp_rate: oracle_rate, full_scale })
+}
+
+/// Returns the values of `metrics` for the signal at `path_signal` against
+/// the oracle at `path_oracle`, compared with `options` (see
+/// `compared_signals()`). The Euclidean distance is in the unit of the raw
+/// samples of the oracle.
+pub fn measure_metrics(
+    path_signal: &str,
+    path_oracle: &str,
+    metrics: &[metrics::Metric],
+    options: &report::CheckOptions
+) -> Result<Vec<f32>, &'static str> {
+    let c = compared_signals(path_signal, path_oracle, options)?;
+    let (fsize, hopa) = (options.fsize, options.hopa);
+    Ok(metrics
+        .iter()
+        .map(|metric| {
+            let value =
+                metric.compute_framed(&c.signal, &c.oracle, c.samp_rate, fsize, hopa);
+            if *metric == metrics::Metric::Euclidean {
+                value * c.full_scale
+            } else {
+                value
+            }
+        })
+        .collect())
+}
+
+/// Returns the accuracy of the pitch of the signal at `path_signal` against
+/// `reference`, the oracle at `path_oracle` for a shift, compared with
+/// `options` (see `compared_signals()`). Pitches are tracked on the frames
+/// of `options`.
+pub fn measure_pitch(
+    path_signal: &str,
+    path_oracle: Option<&str>,
+    reference: accuracy::PitchReference,
+    options: &report::CheckOptions
+) -> Result<accuracy::PitchAccuracy, &'static str> {
+    let (fsize, hopa) = (options.fsize, options.hopa);
+    let track_of = |signal: &[f32], samp_rate: f32| {
+        if signal.len() < fsize {
+            return Err("Signal shorter than a pitch detection frame");
+        }
+        Ok(analysis::pitch_track(signal, fsize, hopa, samp_rate))
+    };
+    let voicing = analysis::Voicing::new(1.0);
+    let (track, reference_track) = match (reference, path_oracle) {
+        (accuracy::PitchReference::Note(_), _) => {
+            let (signal, samp_rate, _, _) = normalized_wav(path_signal)?;
+            (track_of(&signal, samp_rate)?, Vec::new())
+        },
+        (accuracy::PitchReference::Shift(_), Some(path_oracle)) => {
+            let c = compared_signals(path_signal, path_oracle, options)?;
+            (track_of(&c.signal, c.samp_rate)?, track_of(&c.oracle, c.samp_rate)?)
+        },
+        (accuracy::PitchReference::Shift(_), None) => {
+            return Err("No oracle signal to compare with");
+        },
+    };
+    accuracy::pitch_accuracy(&track, &voicing, reference, &reference_track, &voicing)
+        .ok_or("No voiced frame to compare")
+}
+
 /// Checks the quality using the given configuration
+/// Compares the signal to the oracle of `config` with its metric and returns
+/// whether the value of the metric passes the threshold, and the value (see
+/// `measure_metrics()`)
 pub fn check_quality(
     config: QCConfiguration
 ) -> Result<(bool, f32), &'static str> {
-    let path_signal = config.path_to_signal();
-    let path_oracle = config.path_to_oracle();
-    let (_, signal) = convert::extract_data_from_wav(path_signal);
-    let (_, oracle) = convert::extract_data_from_wav(path_oracle);
-    if signal.len() != oracle.len() {
-        return Err("Can not compare signals of different length");
+    let paths = (config.path_to_signal(), config.path_to_oracle());
+    let (path_signal, path_oracle) = match paths {
+        (Some(signal), Some(oracle)) => (signal, oracle),
+        (None, _) => return Err("No signal to check"),
+        (_, None) => return Err("No oracle signal to compare with"),
+    };
+    let metric = config.metric();
+    let value =
+        measure_metrics(path_signal, path_oracle, &[metric], &config.options())?[0];
+    Ok((metric.passes(value, config.threshold()), value))
+}
+
+/// Returns the accuracy of the pitch of the signal of `config` against its
+/// pitch reference (see `QCConfiguration::pitch_reference()` and
+/// `measure_pitch()`)
+pub fn check_pitch(
+    config: QCConfiguration
+) -> Result<accuracy::PitchAccuracy, &'static str> {
+    let reference = match config.pitch_reference() {
+        Some(reference) => reference,
+        None => return Err("No expected pitch to check"),
+    };
+    match (config.path_to_signal(), config.path_to_oracle()) {
+        (Some(signal), oracle) => {
+            measure_pitch(signal, oracle, reference, &config.options())
+        },
+        (None, _) => Err("No signal to check"),
     }
-    let mut planner = FftPlanner::new();
-    let sspec = apply_fft(&mut planner, &signal[..]);
-    let ospec = apply_fft(&mut planner, &oracle[..]);
-    Ok(compute_and_compare_distance(config.threshold(), &sspec[..], &ospec[..]))
 }
 
 /// Shifts the given amplitudes in order to shift the corresponding frequencies
-/// by the given shift value. For values that could not be shifted, 0 is
-/// assigned.
+/// by the given shift value. Shifted frequencies between two bins are linearly
+/// interpolated. For values that could not be shifted, 0 is assigned.
 fn shift_amplitudes(
     amplitudes: &[Complex<f32>],
     shift: i32,
     sampling_rate: f32,
 ) -> Vec<Complex<f32>> {
     let mut shifted = vec![Complex { re: 0.0, im: 0.0 }; amplitudes.len()];
-    let len = amplitudes.len() as f32;
+    let len = amplitudes.len();
     let scale = 2.0f32.powf(shift as f32 / 12.0);
-    let time_step = sampling_rate / len;
-    let mut bin_freqs = vec![0.0; amplitudes.len()];
-    for i in 0..bin_freqs.len() {
-        bin_freqs[i] = bin_frequency(i as u32, sampling_rate, len as u32);
-    }
-    for i in 0..amplitudes.len() {
-        let src_freq = i as f32 * sampling_rate / len / scale;
-        if (src_freq / time_step).fract() == 0.0 && src_freq < sampling_rate {
-            let i_src_freq = (src_freq * len / sampling_rate) as usize;
-            shifted[i] = amplitudes[i_src_freq];
-        } else {
-            shifted[i] = Complex { re: 0.0, im: 0.0 };
+    let step = sampling_rate / len as f32;
+    if scale >= 1.0 {
+        // each amplitude is shared by the two bins around its shifted frequency
+        for (i, amplitude) in amplitudes.iter().enumerate() {
+            let freq = i as f32 * step;
+            let dst = freq * scale / step;
+            let (i_dst, weight) = (dst.floor() as usize, dst.fract());
+            if i_dst < len {
+                shifted[i_dst] += amplitude * (1.0 - weight);
+            }
+            if weight > 0.0 && i_dst + 1 < len {
+                shifted[i_dst + 1] += amplitude * weight;
+            }
+        }
+    } else {
+        // each bin takes the amplitude between the two bins around its source
+        for (i, bin) in shifted.iter_mut().enumerate() {
+            let freq = i as f32 * step;
+            let src = freq / scale / step;
+            let (i_src, weight) = (src.floor() as usize, src.fract());
+            if i_src < len {
+                *bin = amplitudes[i_src] * (1.0 - weight);
+            }
+            if weight > 0.0 && i_src + 1 < len {
+                *bin += amplitudes[i_src + 1] * weight;
+            }
         }
     }
     shifted
@@ -169,11 +854,21 @@ fn basic_transposer(
     shift: i32, sampling_rate: f32
 ) -> Vec<f32> {
     let mut planner = FftPlanner::new();
-    let frequencies: Vec<Complex<f32>> = apply_fft(&mut planner, signal);
+    let mut frequencies: Vec<Complex<f32>> = apply_fft(&mut planner, signal);
+
+    // the negative frequencies, conjugates of the positive ones for a real
+    // signal, are folded onto them so that each frequency is shifted once
+    let len = frequencies.len();
+    for (i, amplitude) in frequencies.iter_mut().enumerate().skip(1) {
+        if 2 * i < len {
+            *amplitude *= 2.0;
+        } else if 2 * i > len {
+            *amplitude = Complex { re: 0.0, im: 0.0 };
+        }
+    }
     let shifted = shift_amplitudes(&frequencies[..], shift, sampling_rate);
     let shifted_time_domain = apply_ifft(&mut planner, &shifted[..]);
     let mut reals_normalized = vec![0.0; shifted_time_domain.len()];
-    let len = shifted_time_domain.len();
     for i in 0..len {
         reals_normalized[i] = shifted_time_domain[i].re / len as f32;
     }
@@ -182,7 +877,7 @@ fn basic_transposer(
 
 /// Staticly transposes the `signal` by shifting it using the phase vocoder
 /// algorithm
-fn phase_vocoder_transposer(
+pub fn phase_vocoder_transposer(
     signal: &[f32],
     fsize: usize,
     hopa: usize,
@@ -190,10 +885,165 @@ fn phase_vocoder_transposer(
     scale_factor: f32
 ) -> Vec<f32> {
     let hops = (scale_factor * hopa as f32).round() as u32;
-    let frames = frame(signal, fsize, hopa);
-    let (frames, analyzed_frames) = parallelized_analysis(frames, hopa);
+    let pool = pool::global();
+    let frames = frame_slices(signal, fsize, hopa);
+    let analyzed_frames = parallelized_analysis(&pool, &frames, hopa);
+
+    // process frames
+    let hops_per_frame = vec![hops; analyzed_frames.len()];
+    let processed_frames =
+        process_frames(&analyzed_frames, hopa, samp_rate, &hops_per_frame);
+
+    // synthetize frames
+    let frames_for_oa = parallelized_synthesis(&pool, &processed_frames, &hops_per_frame);
+
+    // overlap-add frames
+    let scaled_signal = overlap_add(&frames_for_oa[..], hops as usize);
+
+    // resample scaled signal
+    sample_audio(&scaled_signal[..], scale_factor)
+}
+
+/// Modulates the pitch of `signal` by `vibrato` with the phase vocoder
+/// algorithm, each analysis frame being scaled by the ratio of the vibrato at
+/// its middle. The output signal has the length of `signal`.
+pub fn add_vibrato(
+    signal: &[f32],
+    fsize: usize,
+    hopa: usize,
+    samp_rate: f32,
+    vibrato: &modulation::Vibrato
+) -> Vec<f32> {
+    phase_vocoder_corrector(signal, fsize, hopa, samp_rate, |track| {
+        track.iter().map(|f| vibrato.ratio_at(f.time)).collect()
+    })
+}
+
+/// Transposes `signal` with the phase vocoder algorithm, using a scale factor
+/// per analysis frame (see `frame()`). `correction` receives what the pitch
+/// detector saw in each analyzed frame and returns the scale factor of each
+/// frame. The output signal has the length of `signal`.
+fn phase_vocoder_corrector<F>(
+    signal: &[f32],
+    fsize: usize,
+    hopa: usize,
+    samp_rate: f32,
+    correction: F
+) -> Vec<f32>
+where
+    F: FnOnce(&[analysis::PitchFrame]) -> Vec<f32>
+{
+    phase_vocoder_corrector_with(signal, None, fsize, hopa, samp_rate, correction)
+}
+
+/// Same as `phase_vocoder_corrector()`, the pitch detector seeing `analyzed`,
+/// e.g `signal` pre-processed, instead of `signal` if given
+fn phase_vocoder_corrector_with<F>(
+    signal: &[f32],
+    analyzed: Option<&[f32]>,
+    fsize: usize,
+    hopa: usize,
+    samp_rate: f32,
+    correction: F
+) -> Vec<f32>
+where
+    F: FnOnce(&[analysis::PitchFrame]) -> Vec<f32>
+{
+    let (analyzed_frames, track) =
+        corrector_analysis(signal, analyzed, fsize, hopa, samp_rate);
+    let scale_factors = correction(&track[..]);
+    phase_vocoder_synthesis(
+        &analyzed_frames,
+        hopa,
+        samp_rate,
+        &scale_factors,
+        signal.len(),
+        |_, _| ()
+    )
+}
+
+/// Returns the spectrums of the frames of `signal` analyzed by the phase
+/// vocoder, and what the pitch detector saw in each of them, given `analyzed`
+/// instead of `signal` if any (see `phase_vocoder_corrector_with()`)
+fn corrector_analysis(
+    signal: &[f32],
+    analyzed: Option<&[f32]>,
+    fsize: usize,
+    hopa: usize,
+    samp_rate: f32
+) -> (Vec<Vec<Complex<f32>>>, Vec<analysis::PitchFrame>) {
+    let frames = frame_slices(signal, fsize, hopa);
+    let analyzed_frames = parallelized_analysis(&pool::global(), &frames, hopa);
+    let track = match analyzed {
+        Some(analyzed) => analysis::pitch_track(analyzed, fsize, hopa, samp_rate),
+        None => analysis::pitch_frames(&frames, &analyzed_frames, hopa, samp_rate),
+    };
+    (analyzed_frames, track)
+}
+
+/// Synthesizes the signal of `len` samples whose analysis frames, taken every
+/// `hopa` samples, are `analyzed_frames`, transposed by a scale factor per
+/// frame. `shape` may change the spectrum of each processed frame, given its
+/// index, before its synthesis. Without frames, the output is silent.
+fn phase_vocoder_synthesis<F>(
+    analyzed_frames: &[Vec<Complex<f32>>],
+    hopa: usize,
+    samp_rate: f32,
+    scale_factors: &[f32],
+    len: usize,
+    shape: F
+) -> Vec<f32>
+where
+    F: Fn(usize, &mut [Complex<f32>])
+{
+    let Some(fsize) = analyzed_frames.first().map(Vec::len) else {
+        return vec![0.0; len];
+    };
+
+    // synthesis positions of frames: the shift between frames i-1 and i is
+    // the scale factor of frame i times hopa, rounding errors do not add up
+    let mut positions: Vec<usize> = Vec::with_capacity(scale_factors.len() + 1);
+    let mut hops: Vec<u32> = Vec::with_capacity(scale_factors.len());
+    let mut exact_position = 0.0;
+    for (i, scale_factor) in scale_factors.iter().enumerate() {
+        exact_position += scale_factor * hopa as f32;
+        let hop = if i == 0 {
+            (scale_factor * hopa as f32).round().max(1.0) as usize
+        } else {
+            let previous = positions[i - 1];
+            (exact_position.round() as usize).max(previous + 1) - previous
+        };
+        positions.push(if i == 0 { 0 } else { positions[i - 1] + hop });
+        hops.push(hop as u32);
+    }
+
+    let mut processed_frames = process_frames(analyzed_frames, hopa, samp_rate, &hops);
+    for (i, frame) in processed_frames.iter_mut().enumerate() {
+        shape(i, frame);
+    }
+    let frames_for_oa = parallelized_synthesis(&pool::global(), &processed_frames, &hops);
+    let scaled_signal = overlap_add_at(&frames_for_oa[..], &positions[..]);
+
+    // position after the last frame, to resample up to the end of signal
+    positions.push(positions[positions.len() - 1] + hops[hops.len() - 1] as usize);
+    resample_along_from(&scaled_signal[..], &positions[..], hopa, fsize / 2, len)
+}
+
+/// Adjusts the phases of the analyzed frames so that there are no
+/// discontinuities when frame i is placed `hops[i]` samples after frame i-1
+/// for synthesis.
+fn process_frames(
+    analyzed_frames: &[Vec<Complex<f32>>],
+    hopa: usize,
+    samp_rate: f32,
+    hops: &[u32]
+) -> Vec<Vec<Complex<f32>>> {
+    let Some(fsize) = analyzed_frames.first().map(Vec::len) else {
+        return Vec::new();
+    };
     let zero_frame = vec![Complex { re: 0.0, im: 0.0 }; fsize];
-    let mut processed_frames = Vec::with_capacity(frames.len());
+    let mut processed_frames: Vec<Vec<Complex<f32>>> =
+        Vec::with_capacity(analyzed_frames.len());
 
     for i in 0..analyzed_frames.len() {
         let curr_xa = &analyzed_frames[i];
@@ -220,27 +1070,86 @@ fn phase_vocoder_transposer(
                 k as u32,
                 fsize as u32,
             );
-            let wrap_freq_dev = wrapped_frequency_deviation(freq_dev);
+            let wrap_freq_dev =
+                wrapped_frequency_deviation(freq_dev, hopa as u32, samp_rate);
             let bin_freq = bin_frequency(k as u32, samp_rate, fsize as u32);
             let true_freq = true_frequency(wrap_freq_dev, bin_freq);
             let phi = if i != 0 {
-                phase_adjustment(prev_xp[k].arg(), hops, samp_rate, true_freq)
+                phase_adjustment(prev_xp[k].arg(), hops[i], samp_rate, true_freq)
             } else {
-                analyzed_frames[0][k].arg()
+                curr_xa[k].arg()
             };
-            curr_xp[k] = Complex::from_polar(analyzed_frames[i][k].norm(), phi);
+            curr_xp[k] = Complex::from_polar(curr_xa[k].norm(), phi);
         }
         processed_frames.push(curr_xp);
     }
+    processed_frames
+}
 
-    // synthetize frames
-    let frames_for_oa = parallelized_synthesis(processed_frames, hops);
+/// Resamples `signal`, stretched by the phase vocoder, to a signal of `len`
+/// samples. The analysis frame i, which started at `i * hopa` in the original
+/// signal, starts at `positions[i]` in `signal`: sample n of the output is the
+/// amplitude of `signal` at the position linearly interpolated between the
+/// positions of the frames around n, deduced by linear interpolation too.
+fn resample_along(
+    signal: &[f32],
+    positions: &[usize],
+    hopa: usize,
+    len: usize
+) -> Vec<f32> {
+    let mut resampled_signal = Vec::with_capacity(len);
+    let last_segment = positions.len().saturating_sub(2);
+    for n in 0..len {
+        let i = min(n / hopa, last_segment);
+        let progress = (n - i * hopa) as f32 / hopa as f32;
+        let start = positions[i] as f32;
+        let end = positions[min(i + 1, positions.len() - 1)] as f32;
+        let sample_time = start + progress * (end - start);
+        resampled_signal.push(amplitude_at(signal, sample_time));
+    }
+    resampled_signal
+}
 
-    // overlap-add frames
-    let scaled_signal = overlap_add(&frames_for_oa[..], hops as usize);
+/// Same as `resample_along()`, where the content of the original signal at
+/// `i * hopa + offset` (e.g the middle of the frame) is at
+/// `positions[i] + offset` in `signal`. The samples before `offset` follow
+/// the first frames backwards.
+fn resample_along_from(
+    signal: &[f32],
+    positions: &[usize],
+    hopa: usize,
+    offset: usize,
+    len: usize
+) -> Vec<f32> {
+    let first = positions[0] as f32;
+    let second = positions[min(1, positions.len() - 1)] as f32;
+    let mut resampled_signal: Vec<f32> = (0..min(offset, len))
+        .map(|n| {
+            let progress = (n as f32 - offset as f32) / hopa as f32;
+            let sample_time = offset as f32 + first + progress * (second - first);
+            amplitude_at(signal, sample_time.max(0.0))
+        })
+        .collect();
+    let shifted = signal.get(offset..).unwrap_or(&[]);
+    let rest = len.saturating_sub(offset);
+    resampled_signal.extend(resample_along(shifted, positions, hopa, rest));
+    resampled_signal
+}
 
-    // resample scaled signal
-    sample_audio(&scaled_signal[..], scale_factor)
+/// Returns the amplitude of `signal` at `sample_time`, linearly interpolated
+/// between the samples around it, or 0 after the end of the signal
+fn amplitude_at(signal: &[f32], sample_time: f32) -> f32 {
+    let x0 = sample_time.floor();
+    if x0 as usize + 1 < signal.len() {
+        let x1 = x0 + 1.0;
+        let y0 = signal[x0 as usize];
+        let y1 = signal[x1 as usize];
+        linear_interpolation(x0, y0, x1, y1, sample_time)
+    } else if (x0 as usize) < signal.len() {
+        signal[x0 as usize]
+    } else {
+        0.0
+    }
 }
 
 /// Samples the given signal (audio) as if it was played scale_factor times
@@ -250,13 +1159,24 @@ fn phase_vocoder_transposer(
 /// factor should be of the form:
 ///     2^(t/12), where t corresponds to a number of semi-tons to shift.
 fn sample_audio(signal: &[f32], scale_factor: f32) -> Vec<f32> {
+    let mut resampled_signal = Vec::new();
+    sample_audio_into(signal, scale_factor, &mut resampled_signal);
+    resampled_signal
+}
+
+/// Appends to `resampled_signal` the samples of `sample_audio()`, reusing its
+/// capacity
+fn sample_audio_into(
+    signal: &[f32],
+    scale_factor: f32,
+    resampled_signal: &mut Vec<f32>
+) {
     let len = signal.len();
 
     // computes the number of samples to take: signal_length / scale_factor
     let nb_samples = (len as f32 / scale_factor).round() as u32;
 
-    // vector that will contain the amplitudes of the sampled signal
-    let mut resampled_signal = Vec::with_capacity(nb_samples as usize);
+    resampled_signal.reserve(nb_samples as usize);
 
     // sampling...
     for i in 0..nb_samples {
@@ -271,18 +1191,18 @@ fn sample_audio(signal: &[f32], scale_factor: f32) -> Vec<f32> {
         } else {
             let x0 = sample_time.floor() as f32;
             let y0 = signal[x0 as usize];
-            let tmp = sample_time.ceil() as f32;
-            let x1 = if tmp >= len as f32 {
-                (len - 1) as f32
+            let x1 = sample_time.ceil();
+
+            // past the last sample, its amplitude is held
+            if x1 >= len as f32 {
+                resampled_signal.push(y0);
             } else {
-                tmp
-            };
-            let y1 = signal[x1 as usize];
-            let amplitude = linear_interpolation(x0, y0, x1, y1, sample_time);
-            resampled_signal.push(amplitude);
+                let y1 = signal[x1 as usize];
+                let amplitude = linear_interpolation(x0, y0, x1, y1, sample_time);
+                resampled_signal.push(amplitude);
+            }
         }
     }
-    resampled_signal
 }
 
 /// Computes f(x) by linear interpolation where f is a linear function
@@ -325,6 +1245,12 @@ fn frame(signal: &[f32], frame_size: usize, hop_a: usize) -> Vec<Vec<f32>> {
     frames
 }
 
+/// Same as `frame()`, the frames borrowing `signal` instead of being copied.
+/// There is no frame if `frame_size` is greater than the signal length.
+fn frame_slices(signal: &[f32], frame_size: usize, hop_a: usize) -> Vec<&[f32]> {
+    signal.windows(frame_size).step_by(hop_a).collect()
+}
+
 /// Applies a forward FFT on the given array
 fn apply_fft(
     planner: &mut FftPlanner<f32>,
@@ -383,6 +1309,21 @@ fn overlap_add(frames: &[Vec<f32>], hops: usize) -> Vec<f32> {
     signal
 }
 
+/// Creates a vector which values correspond to the overlap addition of
+/// `frames`, where frame i starts at `positions[i]`.
+fn overlap_add_at(frames: &[Vec<f32>], positions: &[usize]) -> Vec<f32> {
+    let flen = frames[0].len();
+    let signal_length = positions[frames.len() - 1] + flen;
+    let mut signal: Vec<f32> = vec![0.0; signal_length];
+
+    for (frame, &position) in frames.iter().zip(positions) {
+        for (j, amplitude) in frame.iter().enumerate() {
+            signal[position + j] += amplitude;
+        }
+    }
+    signal
+}
+
 /// Computes the bin frequency at `bin_index`. The bin frequency is given by
 /// the following formula:
 ///     `w_bin[k] = k * sampling_rate / frame_length`
@@ -412,12 +1353,20 @@ fn frequency_deviation(
 }
 
 /// Computes the wrapped frequency deviation at `bin_index` for two consecutive
-/// frames. The wrapped frequency deviation is computed as follows:
-///     wrap_freq_dev = ((freq_dev + PI) mod 2PI) - PI
-fn wrapped_frequency_deviation(freq_dev: f32) -> f32 {
+/// frames. The phase deviation over the hop is wrapped to [-PI, PI[ before
+/// being converted back to a frequency deviation:
+///     wrap_freq_dev =
+///         (((freq_dev * hopa_as_time + PI) mod 2PI) - PI) / hopa_as_time
+fn wrapped_frequency_deviation(
+    freq_dev: f32,
+    hop_a: u32,
+    sampling_rate: f32
+) -> f32 {
     let pi = std::f32::consts::PI;
     let two_pi = 2.0 * pi;
-    ((freq_dev + pi) % (two_pi)) - pi
+    let hopa_as_time = hop_a as f32 / sampling_rate;
+    let phase_dev = freq_dev * hopa_as_time;
+    ((phase_dev + pi).rem_euclid(two_pi) - pi) / hopa_as_time
 }
 
 /// Computes the true frequency at `bin_index` for the frame i. The true
@@ -462,88 +1411,34 @@ fn normalize(frame: &[f32]) -> Vec<f32> {
     res
 }
 
-/// Given a vector of frames that are ready to be analyzed,
-/// returns a vector containing the anlyzed frames.
+/// Given frames that are ready to be analyzed, returns the analyzed frames,
+/// computed by the workers of `pool`
 fn parallelized_analysis(
-    frames: Vec<Vec<f32>>,
+    pool: &pool::WorkerPool,
+    frames: &[&[f32]],
     hopa: usize
-) -> (Vec<Vec<f32>>, Vec<Vec<Complex<f32>>>) {
-    let nb_of_frames = frames.len();
-    let mut res: Vec<Vec<Complex<f32>>> = Vec::with_capacity(nb_of_frames);
-    let mut handles = Vec::with_capacity(4);
-
-    let closure = move |frames: &[Vec<f32>]| {
-        let mut res: Vec<Vec<Complex<f32>>> = vec![];
-        let mut planner: FftPlanner<f32> = FftPlanner::new();
-        for frame in frames {
-            let analyzed = analyze_frame(&mut planner, &frame, hopa);
-            res.push(analyzed);
-        }
-        res
-    };
-
-    let frames_arc = Arc::new(frames);
-
-    for i in 0..4 {
-        let f = frames_arc.clone();
-        let handle =
-            thread::spawn(move || closure(
-                &*&f[i * nb_of_frames / 4..(i + 1) * nb_of_frames / 4]
-            ));
-        handles.push(handle);
-    }
-
-    for handle in handles {
-        let mut frame = handle.join().unwrap();
-        res.append(&mut frame);
-    }
-    ((&(frames_arc.clone())).to_vec(), res)
+) -> Vec<Vec<Complex<f32>>> {
+    pool.map(frames, FftPlanner::new, |planner, _, frame| {
+        analyze_frame(planner, frame, hopa)
+    })
 }
 
-/// Given a vector of frames that are ready for synthesis stage,
-/// returns a vector containing the synthsized frames.
+/// Given frames that are ready for synthesis stage, and the shift between
+/// each frame and the previous one, returns the synthesized frames, computed
+/// by the workers of `pool`
 fn parallelized_synthesis(
-    frames: Vec<Vec<Complex<f32>>>,
-    hops: u32
+    pool: &pool::WorkerPool,
+    frames: &[Vec<Complex<f32>>],
+    hops: &[u32]
 ) -> Vec<Vec<f32>> {
-    let nb_of_frames = frames.len();
-    let mut res: Vec<Vec<f32>> = Vec::with_capacity(nb_of_frames);
-    let mut handles = Vec::with_capacity(4);
-
-    let closure = move |frames: &[Vec<Complex<f32>>]| {
-        let mut res: Vec<Vec<f32>> = vec![];
-        let mut planner: FftPlanner<f32> = FftPlanner::new();
-        for frame in frames {
-            let xs_comp = apply_ifft(&mut planner, &frame[..]);
-            let xs = reals_of(&xs_comp[..]);
-            let normalized = normalize(&xs[..]);
-            let hanned = von_hann_window(&normalized[..]);
-            let mut windowed = Vec::new();
-            let norm = (hanned.len() as f32 / hops as f32 / 2.0).sqrt();
-            for i in 0..hanned.len() {
-                windowed.push(hanned[i] / norm);
-            }
-            res.push(windowed);
-        }
-        res
-    };
-
-    let frames_arc = Arc::new(frames);
-
-    for i in 0..4 {
-        let f = frames_arc.clone();
-        let handle =
-            thread::spawn(move || closure(
-                &*&f[i * nb_of_frames / 4..(i + 1) * nb_of_frames / 4]
-            ));
-        handles.push(handle);
-    }
-
-    for handle in handles {
-        let mut frame = handle.join().unwrap();
-        res.append(&mut frame);
-    }
-    res
+    pool.map(frames, FftPlanner::new, |planner, i, frame| {
+        let xs_comp = apply_ifft(planner, &frame[..]);
+        let xs = reals_of(&xs_comp[..]);
+        let normalized = normalize(&xs[..]);
+        let hanned = von_hann_window(&normalized[..]);
+        let norm = (hanned.len() as f32 / hops[i] as f32 / 2.0).sqrt();
+        hanned.into_iter().map(|elt| elt / norm).collect()
+    })
 }
 
 /// Returns a `Vec<f32>` containing the real part of each `Complex<f32>` in
@@ -593,23 +1488,34 @@ fn compute_and_compare_distance(
     (dist <= epsilon, dist)
 }
 
-/// Based on the index of the maximum amplitude of the complex numbers of v
-/// (not counting complex number at index 0 of v), returns the corresponding bin
-/// frequency.
+/// Based on the index of the maximum magnitude of the complex numbers of v
+/// (only counting positive frequencies, without the one at index 0 of v),
+/// returns the corresponding frequency in Hz. The index is refined by fitting
+/// a parabola through the log magnitudes around the maximum.
 fn get_main_frequency(v: &[Complex<f32>], samp_rate: f32) -> f32 {
-    let mut imax = -1;
+    let mut imax = 0;
     let mut max = f32::NEG_INFINITY;
-    for i in 1..v.len() {
-        if v[i].re > max {
-            imax = i as i32;
-            max = v[i].re;
+    for (i, elt) in v.iter().enumerate().take(v.len() / 2 + 1).skip(1) {
+        if elt.norm() > max {
+            imax = i;
+            max = elt.norm();
         }
     }
-    if imax == -1 {
-        0.0
-    } else {
-        bin_frequency(imax as u32, samp_rate, v.len() as u32)
+    if imax == 0 {
+        return 0.0;
+    }
+    let mut peak = imax as f32;
+    if imax + 1 < v.len() {
+        let log_norm = |c: Complex<f32>| c.norm().max(f32::MIN_POSITIVE).ln();
+        let a = log_norm(v[imax - 1]);
+        let b = log_norm(v[imax]);
+        let c = log_norm(v[imax + 1]);
+        let curvature = a - 2.0 * b + c;
+        if curvature < 0.0 {
+            peak += 0.5 * (a - c) / curvature;
+        }
     }
+    peak * samp_rate / v.len() as f32
 }
 
 /// Returns the index of the nearest value to f in v. Returns -1 if v is of
@@ -642,27 +1548,56 @@ fn find_index_of_nearest_to(f: f32, l: usize, r: usize, v: &[f32]) -> i32 {
     }
 }
 
+/// Writes the frequencies of `KNOWN_FREQUENCIES` that are notes of the
+/// musical scale `scale` in `key` at the start of `notes`, as many as it
+/// holds, and returns how many are written
+fn scale_notes(key: Key, scale: Scale, notes: &mut [f32]) -> usize {
+    let degrees = scale.degrees();
+    let in_scale = KNOWN_FREQUENCIES.iter().enumerate().filter(|(i, _)| {
+        let degree = (*i as u8 % 12 + 12 - key.semitones()) % 12;
+        degrees.contains(&degree)
+    });
+    let mut count = 0;
+    for ((_, &freq), note) in in_scale.zip(notes.iter_mut()) {
+        *note = freq;
+        count += 1;
+    }
+    count
+}
+
 fn known_frequencies() -> Vec<f32> {
-    vec![
-        16.35, 17.32, 18.35, 19.45, 20.60, 21.83, 23.12, 24.50, 25.96, 27.50,
-        29.14, 30.87, 32.70, 34.65, 36.71, 38.89, 41.20, 43.65, 46.25, 49.00,
-        51.91, 55.00, 58.27, 61.74, 65.41, 69.30, 73.42, 77.78, 82.41, 87.31,
-        92.50, 98.00, 103.83, 110.00, 116.54, 123.47, 130.81, 138.59, 146.83,
-        155.56, 164.81, 174.61, 185.00, 196.00, 207.65, 220.00, 233.08, 246.94,
-        261.63, 277.18, 293.66, 311.13, 329.63, 349.23, 369.99, 392.00, 415.30,
-        440.00, 466.16, 493.88, 523.25, 554.37, 587.33, 622.25, 659.26, 698.46,
-        739.99, 783.99, 830.61, 880.00, 932.33, 987.77, 1046.50, 1108.73,
-        1174.66, 1244.51, 1318.51, 1396.91, 1479.98, 1567.98, 1661.22, 1760.00,
-        1864.66, 1975.53, 2093.00, 2217.46, 2349.32, 2489.02, 2637.02, 2793.83,
-        2959.96, 3135.96, 3322.44, 3520.00, 3729.31, 3951.07, 4186.01, 4434.92,
-        4698.64, 4978.03, 5274.04, 5587.65, 5919.91, 6271.93, 6644.88, 7040.00,
-        7458.62, 7902.13
-    ]
+    KNOWN_FREQUENCIES.to_vec()
 }
 
+/// Frequencies in Hz of the notes from C0 to B8
+const KNOWN_FREQUENCIES: [f32; 108] = [
+    16.35, 17.32, 18.35, 19.45, 20.60, 21.83, 23.12, 24.50, 25.96, 27.50,
+    29.14, 30.87, 32.70, 34.65, 36.71, 38.89, 41.20, 43.65, 46.25, 49.00,
+    51.91, 55.00, 58.27, 61.74, 65.41, 69.30, 73.42, 77.78, 82.41, 87.31,
+    92.50, 98.00, 103.83, 110.00, 116.54, 123.47, 130.81, 138.59, 146.83,
+    155.56, 164.81, 174.61, 185.00, 196.00, 207.65, 220.00, 233.08, 246.94,
+    261.63, 277.18, 293.66, 311.13, 329.63, 349.23, 369.99, 392.00, 415.30,
+    440.00, 466.16, 493.88, 523.25, 554.37, 587.33, 622.25, 659.26, 698.46,
+    739.99, 783.99, 830.61, 880.00, 932.33, 987.77, 1046.50, 1108.73,
+    1174.66, 1244.51, 1318.51, 1396.91, 1479.98, 1567.98, 1661.22, 1760.00,
+    1864.66, 1975.53, 2093.00, 2217.46, 2349.32, 2489.02, 2637.02, 2793.83,
+    2959.96, 3135.96, 3322.44, 3520.00, 3729.31, 3951.07, 4186.01, 4434.92,
+    4698.64, 4978.03, 5274.04, 5587.65, 5919.91, 6271.93, 6644.88, 7040.00,
+    7458.62, 7902.13
+];
+
 #[cfg(test)]
 mod lib_tests {
     use super::*;
+    use crate::generate::Signal;
+
+    /// Returns the frequencies of `known_frequencies()` that are notes of the
+    /// musical scale `scale` in `key`
+    fn scale_frequencies(key: Key, scale: Scale) -> Vec<f32> {
+        let mut notes = [0.0; KNOWN_FREQUENCIES.len()];
+        let count = scale_notes(key, scale, &mut notes);
+        notes[..count].to_vec()
+    }
 
     /// Rounds `f` to `r` decimal digits
     fn round_digits(f: f32, r: i32) -> f32 {
@@ -702,6 +1637,83 @@ mod lib_tests {
         assert!(t4.is_empty());
     }
 
+    #[test]
+    fn test_sample_audio_past_the_end() {
+        // interpolating between the last sample and itself divided 0 by 0
+        let signal_sa = vec![1.5, 1.0, 0.5, 1.75, 2.0, 3.0, 2.5, 1.5, 0.25];
+        let t5 = sample_audio(&signal_sa, 0.7);
+        assert_eq!(13, t5.len());
+        assert!(t5.iter().all(|s| s.is_finite()));
+        assert_eq!(t5[12], 0.25);
+    }
+
+    #[test]
+    fn test_parallelized_analysis_and_synthesis() {
+        let signal: Vec<f32> = (0..8000).map(|i| (i as f32 * 0.07).sin()).collect();
+        let frames = frame_slices(&signal, 512, 128);
+        let single = pool::WorkerPool::single_threaded();
+        let workers = pool::WorkerPool::new(3).unwrap();
+        let analyzed = parallelized_analysis(&single, &frames, 128);
+        assert_eq!(frames.len(), analyzed.len());
+        assert_eq!(analyzed, parallelized_analysis(&workers, &frames, 128));
+
+        let hops: Vec<u32> = (0..analyzed.len() as u32).map(|i| 100 + i).collect();
+        let synthesized = parallelized_synthesis(&single, &analyzed, &hops);
+        assert_eq!(synthesized, parallelized_synthesis(&workers, &analyzed, &hops));
+        // the frame windowed twice, scaled by the norms of both stages
+        let norms = (512.0 / 128.0 / 2.0f32).sqrt() * (512.0 / 100.0 / 2.0f32).sqrt();
+        let expected = von_hann_window(&von_hann_window(&frames[0]));
+        for (s, e) in synthesized[0].iter().zip(expected) {
+            assert!((s - e / norms).abs() < 1e-4);
+        }
+    }
+
+    #[test]
+    fn synthesis_hop_of_each_frame() {
+        let (samp_rate, fsize, hopa) = (8000.0, 512, 128);
+        let signal = Signal::Sine { frequency: 440.0 }
+            .synthesize(samp_rate, 2.0, 1.0)
+            .unwrap();
+        let single = pool::WorkerPool::single_threaded();
+        let frames = frame_slices(&signal, fsize, hopa);
+        let analyzed = parallelized_analysis(&single, &frames, hopa);
+        let n = analyzed.len();
+        let rms = |s: &[f32]| {
+            (s.iter().map(|x| x * x).sum::<f32>() / s.len() as f32).sqrt()
+        };
+        // level of the middle of the frames synthesized with `hops`, from
+        // frame `from` to frame `to`
+        let level = |synthesis_hops: &[u32], hops: &[u32], from: usize, to: usize| {
+            let processed = process_frames(&analyzed, hopa, samp_rate, hops);
+            let synthesized = parallelized_synthesis(&single, &processed, synthesis_hops);
+            let positions: Vec<usize> = (0..n)
+                .map(|i| hops[1..=i].iter().map(|h| *h as usize).sum())
+                .collect();
+            let stretched = overlap_add_at(&synthesized, &positions);
+            rms(&stretched[positions[from] + fsize..positions[to]])
+        };
+        // the frames are placed 128 samples apart, then 256 samples apart
+        let hops: Vec<u32> = (0..n).map(|i| if i < n / 2 { 128 } else { 256 }).collect();
+        let expected = level(&vec![256; n], &vec![256; n], 0, n / 2)
+            / level(&vec![128; n], &vec![128; n], 0, n / 2);
+
+        // each frame is scaled by its own hop, as the frames of a signal
+        // transposed with that hop throughout
+        let first = level(&hops, &hops, 0, n / 2 - 4);
+        let ratio = level(&hops, &hops, n / 2, n - 4) / first;
+        assert!((ratio - expected).abs() < 0.05, "{} {}", ratio, expected);
+    }
+
+    #[test]
+    fn frame_slices_are_the_frames() {
+        let signal = vec![1.2, 4.7, 2.9, 3.2, 5.9, 6.1, 0.4, 2.2, 19.4];
+        for (fsize, hopa) in [(3, 2), (3, 3), (9, 1), (1, 4)] {
+            let frames = frame(&signal, fsize, hopa);
+            assert_eq!(frames, frame_slices(&signal, fsize, hopa));
+        }
+        assert!(frame_slices(&signal, 10, 2).is_empty());
+    }
+
     #[test]
     fn test_frame() {
         let signal = vec![1.2, 4.7, 2.9, 3.2, 5.9, 6.1, 0.4, 2.2];
@@ -772,6 +1784,43 @@ mod lib_tests {
         assert_eq!(round_digits(dev, 4), -65.3271);
     }
 
+    #[test]
+    fn test_wrapped_frequency_deviation() {
+        // a phase deviation of 2PI + 0.5 over the hop is a deviation of 0.5
+        let pi = std::f32::consts::PI;
+        let hopa_as_time = 256.0 / 48000.0;
+        let freq_dev = (2.0 * pi + 0.5) / hopa_as_time;
+        let dev = wrapped_frequency_deviation(freq_dev, 256, 48000.0);
+        assert_eq!(round_digits(dev * hopa_as_time, 4), 0.5);
+        let dev = wrapped_frequency_deviation(-freq_dev, 256, 48000.0);
+        assert_eq!(round_digits(dev * hopa_as_time, 4), -0.5);
+    }
+
+    #[test]
+    fn true_frequency_between_bins() {
+        // 445 Hz lies between the bins 10 and 11 of 1024 samples frames
+        let (samp_rate, fsize, hopa) = (44100.0, 1024, 256);
+        let two_pi = 2.0 * std::f32::consts::PI;
+        let duration = (fsize + hopa) as f32 / samp_rate;
+        let signal = Signal::Sine { frequency: 445.0 }
+            .synthesize(samp_rate, duration, 1.0)
+            .unwrap();
+        let mut planner = FftPlanner::new();
+        let prev = analyze_frame(&mut planner, &signal[..fsize], hopa);
+        let curr = analyze_frame(&mut planner, &signal[hopa..], hopa);
+        let k = 10;
+        let freq_dev = frequency_deviation(
+            prev[k].arg(), curr[k].arg(), hopa as u32, samp_rate, k as u32, fsize as u32
+        );
+        let bin_freq = bin_frequency(k as u32, samp_rate, fsize as u32);
+
+        // the phase deviation over the hop is wrapped, not the frequency
+        // deviation, so the true frequency is not pulled to the bin, 430.7 Hz
+        let wrapped = wrapped_frequency_deviation(freq_dev, hopa as u32, samp_rate);
+        let true_freq = true_frequency(wrapped, bin_freq);
+        assert!((true_freq / two_pi - 445.0).abs() < 1.0, "{}", true_freq / two_pi);
+    }
+
     #[test]
     fn test_normalize() {
         let input = vec![2.0, 2.0];
@@ -843,6 +1892,24 @@ mod lib_tests {
         assert_eq!(expected, res);
     }
 
+    #[test]
+    fn shift_amplitudes_between_bins() {
+        let mut amplitudes = vec![Complex { re: 0.0, im: 0.0 }; 10];
+        amplitudes[4] = Complex { re: 1.0, im: 0.0 };
+        let scale = 2.0f32.powf(1.0 / 12.0);
+        for (shift, expected) in [
+            (1, [(4, 1.0 - (4.0 * scale).fract()), (5, (4.0 * scale).fract())]),
+            (-1, [(3, (3.0 * scale).fract()), (4, 1.0 - (4.0 * scale).fract())])
+        ] {
+            let res = shift_amplitudes(&amplitudes[..], shift, 8000.0);
+            for (i, amplitude) in res.iter().enumerate() {
+                let weight = expected.iter().find(|(j, _)| *j == i).map_or(0.0, |e| e.1);
+                assert!((amplitude.re - weight).abs() < 1e-6, "{} {:?}", i, res);
+                assert_eq!(0.0, amplitude.im);
+            }
+        }
+    }
+
     #[test]
     fn shift_amplitudes_by_0() {
         let amplitudes = vec![
@@ -973,9 +2040,455 @@ mod lib_tests {
         assert_eq!(57, find_index_of_nearest_to(439.05, 0, notes.len(), &notes[..]));
     }
 
+    /// Returns `len` samples of a sine of frequency `freq`
+    #[test]
+    fn main_frequency_of_sine_is_in_hz() {
+        let signal = Signal::Sine { frequency: 440.0 }
+            .synthesize(44100.0, 4096.0 / 44100.0, 1.0)
+            .unwrap();
+        let mut planner = FftPlanner::new();
+        let spectrum = apply_fft(&mut planner, &signal);
+        let freq = get_main_frequency(&spectrum, 44100.0);
+        assert!((freq - 440.0).abs() < 2.0, "{}", freq);
+    }
+
+    #[test]
+    fn main_frequency_between_bins() {
+        // 445 Hz lies between the bins 41 and 42 of a 4096 samples spectrum
+        let samp_rate = 44100.0;
+        let signal = Signal::Sine { frequency: 445.0 }
+            .synthesize(samp_rate, 4096.0 / 44100.0, 1.0)
+            .unwrap();
+        let mut planner = FftPlanner::new();
+        let spectrum = apply_fft(&mut planner, &signal);
+
+        // the largest magnitude of the positive frequencies, in Hz, closer
+        // than the nearest bin (441.4 Hz) thanks to the parabolic fit
+        let main = get_main_frequency(&spectrum, samp_rate);
+        assert!((main - 445.0).abs() < 2.0, "{}", main);
+    }
+
+    #[test]
+    fn closest_scale_factor_tunes_to_nearest_note() {
+        let signal = Signal::Sine { frequency: 452.0 }
+            .synthesize(44100.0, 1.0, 1.0)
+            .unwrap();
+        let scale = get_closest_scale_factor(&signal, 44100.0).unwrap();
+        assert!((scale - 440.0 / 452.0).abs() < 0.001, "{}", scale);
+        let scale = get_closest_scale_factor_in(&signal, 44100.0, Key::C, Scale::Major)
+            .unwrap();
+        assert!((scale - 493.88 / 452.0).abs() < 0.001, "{}", scale);
+    }
+
+    #[test]
+    fn closest_scale_factor_moves_onto_the_note() {
+        let samp_rate = 44100.0;
+        let signal = Signal::Sine { frequency: 452.0 }
+            .synthesize(samp_rate, 1.0, 1.0)
+            .unwrap();
+        let pitch_of = |scale_factor: f32| {
+            let transposed =
+                phase_vocoder_transposer(&signal, 1024, 256, samp_rate, scale_factor);
+            let mut planner = FftPlanner::new();
+            let spectrum = apply_fft(&mut planner, &transposed[4096..36864]);
+            get_main_frequency(&spectrum, samp_rate)
+        };
+        let scale_factor = get_closest_scale_factor(&signal, samp_rate).unwrap();
+        assert!((pitch_of(scale_factor) - 440.0).abs() < 2.0);
+    }
+
+    #[test]
+    fn test_overlap_add_at() {
+        let input = [vec![1.0, 2.0], vec![3.0, 4.0], vec![5.0, 6.0]];
+        assert_eq!(overlap_add_at(&input, &[0, 1, 4]), [1.0, 5.0, 4.0, 0.0, 5.0, 6.0]);
+    }
+
+    #[test]
+    fn synthesis_without_frames() {
+        assert!(process_frames(&[], 256, 8000.0, &[]).is_empty());
+        let synthesized = phase_vocoder_synthesis(&[], 256, 8000.0, &[], 100, |_, _| ());
+        assert_eq!(vec![0.0; 100], synthesized);
+    }
+
+    #[test]
+    fn test_resample_along() {
+        let signal = [0.0, 1.0, 2.0, 3.0, 4.0, 5.0, 6.0, 7.0, 8.0];
+        // frames twice as far apart: read every other sample, then extrapolate
+        let res = resample_along(&signal, &[0, 4, 8], 2, 6);
+        assert_eq!(res, [0.0, 2.0, 4.0, 6.0, 8.0, 0.0]);
+        // frames 1.5 times closer
+        let res = resample_along(&signal, &[0, 1, 3], 2, 4);
+        assert_eq!(res, [0.0, 0.5, 1.0, 2.0]);
+    }
+
+    #[test]
+    fn test_resample_along_from() {
+        let signal = [0.0, 1.0, 2.0, 3.0, 4.0, 5.0, 6.0, 7.0, 8.0];
+        // without offset, as resample_along()
+        let res = resample_along_from(&signal, &[0, 1, 3], 2, 0, 4);
+        assert_eq!(res, resample_along(&signal, &[0, 1, 3], 2, 4));
+        // aligned on the middle of frames of 4 samples
+        let res = resample_along_from(&signal, &[0, 1, 3], 2, 2, 6);
+        assert_eq!(res, [1.0, 1.5, 2.0, 2.5, 3.0, 4.0]);
+    }
+
+    #[test]
+    fn phase_vocoder_corrector_transposes_by_scale_factors() {
+        let samp_rate = 44100.0;
+        let signal = Signal::Sine { frequency: 440.0 }
+            .synthesize(samp_rate, 1.0, 1.0)
+            .unwrap();
+        let scale = 2.0f32.powf(2.0 / 12.0);
+        let transposed = phase_vocoder_corrector(&signal, 1024, 256, samp_rate, |track| {
+            assert!(track.iter().all(|f| (f.frequency - 440.0).abs() < 5.0));
+            vec![scale; track.len()]
+        });
+        assert_eq!(signal.len(), transposed.len());
+        let mut planner = FftPlanner::new();
+        let spectrum = apply_fft(&mut planner, &transposed[4096..36864]);
+        let freq = get_main_frequency(&spectrum, samp_rate);
+        assert!((freq - 440.0 * scale).abs() < 2.0, "{}", freq);
+    }
+
+    #[test]
+    fn pitch_detector_sees_the_analyzed_signal() {
+        let samp_rate = 8000.0;
+        let signal = Signal::Sine { frequency: 440.0 }
+            .synthesize(samp_rate, 1.0, 1.0)
+            .unwrap();
+        let analyzed = Signal::Sine { frequency: 660.0 }
+            .synthesize(samp_rate, 1.0, 1.0)
+            .unwrap();
+        let seen = Some(&analyzed[..]);
+        let transposed =
+            phase_vocoder_corrector_with(&signal, seen, 1024, 256, samp_rate, |t| {
+                assert!(t.iter().all(|f| (f.frequency - 660.0).abs() < 5.0));
+                vec![1.0; t.len()]
+            });
+        let (measured, _) = study::measure_pitch(&transposed, samp_rate, 1024, 256);
+        assert!((measured.unwrap() - 440.0).abs() < 2.0, "{:?}", measured);
+    }
+
+    #[test]
+    fn melody_scale_factors_follow_melody_then_nearest_note() {
+        let melody = midi::Melody::new(vec![midi::smf::Note {
+            start: 0.0, end: 1.0, channel: 0, key: 69, velocity: 100
+        }]);
+        let track: Vec<analysis::PitchFrame> = [400.0, 400.0, 0.0]
+            .iter()
+            .enumerate()
+            .map(|(i, &frequency)| analysis::PitchFrame {
+                time: i as f64 + 0.01, frequency, rms: 1.0, periodicity: 1.0,
+                zero_crossing_rate: 0.0
+            })
+            .collect();
+        let factors = melody_scale_factors(&track, &melody);
+        assert_eq!(factors[0], 440.0 / 400.0);
+        assert_eq!(factors[1], 392.0 / 400.0);
+        assert_eq!(factors[2], 1.0);
+    }
+
+    #[test]
+    fn sine_block_is_voiced_but_not_silence() {
+        let signal = Signal::Sine { frequency: 440.0 }
+            .synthesize(8000.0, 1.0, 1.0)
+            .unwrap();
+        let mut vocoder = vocoder::Vocoder::new(1024, 256, 8000.0).unwrap();
+        assert!(vocoder.is_voiced(&signal, 0.5));
+        assert!(!vocoder.is_voiced(&vec![0.0; signal.len()], 0.5));
+        let quiet: Vec<f32> = signal.iter().map(|s| s * 1e-4).collect();
+        assert!(!vocoder.is_voiced(&quiet, 0.5));
+        assert!(vocoder.is_voiced(&quiet, 0.0));
+    }
+
+    #[test]
+    fn nearest_note_correction_leaves_unvoiced_frames() {
+        // the static phase vocoder used to shift the whole signal by the scale
+        // factor of the nearest note, with phase_vocoder_transposer(); frames
+        // under the voicing threshold now keep their pitch
+        let samp_rate = 8000.0;
+        let tone = Signal::Sine { frequency: 452.0 }
+            .synthesize(samp_rate, 2.0, 1.0)
+            .unwrap();
+        let noise = Signal::WhiteNoise { seed: 1 }
+            .synthesize(samp_rate, 2.0, 0.1)
+            .unwrap();
+        let signal = [tone, noise].concat();
+        let correction =
+            Correction::new(&signal, samp_rate, None, None, None, None, 0.0).unwrap();
+        let frame_rate = samp_rate / 256.0;
+        let tuned = 440.0 / 452.0;
+        for (threshold, unvoiced_factor) in [(0.5, 1.0), (0.0, tuned)] {
+            let voicing = analysis::Voicing { threshold, ..analysis::Voicing::new(1.0) };
+            let transposed = phase_vocoder_corrector(&signal, 1024, 256, samp_rate, |t| {
+                let factors =
+                    correction.voiced_scale_factors(t, &voicing, None, frame_rate);
+                // the frames of the tone, then the frames of the noise
+                assert!(factors[..50].iter().all(|f| (f - tuned).abs() < 0.01));
+                assert!(factors[70..].iter().all(|f| (f - unvoiced_factor).abs() < 0.01));
+                factors
+            });
+            let tone = &transposed[..14000];
+            let (measured, _) = study::measure_pitch(tone, samp_rate, 1024, 256);
+            assert!((measured.unwrap() - 440.0).abs() < 2.0, "{:?}", measured);
+        }
+    }
+
+    #[test]
+    fn vibrato_is_kept_or_removed_by_correction() {
+        let samp_rate = 8000.0;
+        let mut phase = 0.0;
+        // 30 cents above A4, with a vibrato of 50 cents at 5.5 Hz
+        let signal: Vec<f32> = (0..24000)
+            .map(|n| {
+                let t = n as f32 / samp_rate;
+                let cents = 30.0 + 50.0 * (2.0 * std::f32::consts::PI * 5.5 * t).sin();
+                phase += 2.0 * std::f32::consts::PI
+                    * 440.0 * 2.0f32.powf(cents / 1200.0) / samp_rate;
+                phase.sin()
+            })
+            .collect();
+        let correction = Correction::Melody(midi::Melody::new(vec![midi::smf::Note {
+            start: 0.0, end: 10.0, channel: 0, key: 69, velocity: 100
+        }]));
+        let voicing = analysis::Voicing::new(1.0);
+        for depth in [0.0, 1.0] {
+            let corrected = phase_vocoder_corrector(&signal, 512, 80, samp_rate, |track| {
+                correction.voiced_scale_factors(track, &voicing, Some(depth), 100.0)
+            });
+            let track = analysis::pitch_track(&corrected, 512, 80, samp_rate);
+            let cents: Vec<f32> = track[25..track.len() - 25]
+                .iter()
+                .map(|f| 100.0 * analysis::midi_note_number(f.frequency) - 6900.0)
+                .collect();
+            let mean = cents.iter().sum::<f32>() / cents.len() as f32;
+            let depth_found =
+                cents.iter().fold(0.0f32, |max, c| max.max((c - mean).abs()));
+            assert!(mean.abs() < 5.0);
+            // frames of 64 ms smear the vibrato, a part of it is left
+            assert!((depth_found - 50.0 * depth).abs() < 20.0, "{}", depth_found);
+        }
+    }
+
+    #[test]
+    fn add_vibrato_modulates_flat_tone() {
+        let samp_rate = 8000.0;
+        let signal = Signal::Sine { frequency: 440.0 }
+            .synthesize(samp_rate, 3.0, 1.0)
+            .unwrap();
+        let vibrato =
+            modulation::Vibrato { rate: 5.0, depth: 50.0, delay: 1.0, fade_in: 0.0 };
+        let modulated = add_vibrato(&signal, 512, 80, samp_rate, &vibrato);
+        assert_eq!(signal.len(), modulated.len());
+        let track = analysis::pitch_track(&modulated, 512, 80, samp_rate);
+        let cents: Vec<(f64, f32)> = track
+            .iter()
+            .map(|f| (f.time, 100.0 * analysis::midi_note_number(f.frequency) - 6900.0))
+            .collect();
+        // flat during the delay, after the fade-in of the first frames
+        for (_, c) in cents.iter().filter(|(t, _)| (0.1..0.9).contains(t)) {
+            assert!(c.abs() < 5.0, "{}", c);
+        }
+        // frames of 64 ms smear the vibrato, a part of it is lost
+        let modulation = cents[..cents.len() - 10]
+            .iter()
+            .filter(|(t, _)| *t > 1.2)
+            .fold(0.0f32, |max, (_, c)| max.max(c.abs()));
+        assert!((modulation - 50.0).abs() < 20.0, "{}", modulation);
+    }
+
+    #[test]
+    fn curve_scale_factors_follow_curve() {
+        let curve =
+            curve::PitchCurve::new(vec![(0.0, Some(6900.0)), (1.0, Some(8100.0))]);
+        let points = [(0.0, 400.0), (1.0, 440.0), (0.5, 0.0), (2.0, 440.0)];
+        let track: Vec<analysis::PitchFrame> = points
+            .iter()
+            .map(|&(time, frequency)| analysis::PitchFrame {
+                time, frequency, rms: 1.0, periodicity: 1.0, zero_crossing_rate: 0.0
+            })
+            .collect();
+        assert_eq!(vec![1.1, 2.0, 1.0, 1.0], curve_scale_factors(&track, &curve));
+    }
+
+    #[test]
+    fn phase_vocoder_corrector_follows_curve() {
+        let samp_rate = 8000.0;
+        let signal: Vec<f32> = (0..16000)
+            .map(|i| (2.0 * std::f32::consts::PI * 440.0 * i as f32 / samp_rate).sin())
+            .collect();
+        let curve =
+            curve::PitchCurve::new(vec![(0.0, Some(6900.0)), (2.0, Some(7100.0))]);
+        let corrected = phase_vocoder_corrector(&signal, 1024, 256, samp_rate, |track| {
+            curve_scale_factors(track, &curve)
+        });
+        for start in [2000, 7000, 12000] {
+            let window = &corrected[start..start + 2048];
+            let track = analysis::pitch_track(window, 2048, 2048, samp_rate);
+            let middle = (start + 1024) as f64 / samp_rate as f64;
+            let expected = curve.target_at(middle).unwrap();
+            assert!((track[0].frequency - expected).abs() / expected < 0.01);
+        }
+    }
+
+    #[test]
+    fn chromatic_scale_frequencies_are_known_frequencies() {
+        assert_eq!(known_frequencies(), scale_frequencies(Key::G, Scale::Chromatic));
+    }
+
+    #[test]
+    fn scale_frequencies_of_a_minor() {
+        let notes = scale_frequencies(Key::A, Scale::Minor);
+        assert_eq!(notes[..7], [16.35, 18.35, 20.60, 21.83, 24.50, 27.50, 30.87]);
+        assert_eq!(known_frequencies().len() / 12 * 7, notes.len());
+    }
+
+    #[test]
+    fn scale_frequencies_of_d_major() {
+        let notes = scale_frequencies(Key::D, Scale::Major);
+        assert_eq!(notes[..7], [17.32, 18.35, 20.60, 23.12, 24.50, 27.50, 30.87]);
+    }
+
     #[test]
     fn find_34_539944() {
         let notes = known_frequencies();
         assert_eq!(13, find_index_of_nearest_to(34.539944, 0, notes.len(), &notes[..]));
     }
+
+    #[test]
+    fn analyze_without_output_fails() {
+        use clap::Parser;
+        let path = "resources/mono_16PCM_440hz_8000sps.wav";
+        let config = AnalyzeConfiguration::parse_from(["analyze", "-i", path]);
+        assert!(analyze(config).is_err());
+    }
+
+    #[test]
+    fn pitch_track_is_the_one_of_the_transposer() {
+        use clap::Parser;
+        use config::ptconfig::{PitchCli, PitchSubcommand};
+        let path = "resources/mono_16PCM_493hz_44000sps.wav";
+        let command = PitchCli::parse_from([
+            "pitch_transposer", "static", "phase-vocoder", "-i", path,
+            "-o", "lib_tests_traced.wav", "--fsize", "1024", "--hopa", "256",
+            "--pitch-track", "lib_tests_transposer.csv"
+        ]);
+        let PitchSubcommand::Static { output, static_subcommand } = command.subcommand
+        else {
+            panic!("not a static transposition");
+        };
+        transpose_pitch(static_subcommand, &output.stage().unwrap()).unwrap();
+        let config = AnalyzeConfiguration::parse_from([
+            "analyze", "-i", path, "--fsize", "1024", "--hopa", "256",
+            "--pitch-track", "lib_tests_analyzer.csv"
+        ]);
+        analyze(config).unwrap();
+
+        let read = |name| std::fs::read_to_string(output_path(name)).unwrap();
+        let transposer = read("lib_tests_transposer.csv");
+        assert_eq!(transposer, read("lib_tests_analyzer.csv"));
+        assert!(transposer.lines().count() > 100);
+        for name in ["traced.wav", "transposer.csv", "analyzer.csv"] {
+            std::fs::remove_file(output_path(&format!("lib_tests_{}", name))).unwrap();
+        }
+    }
+
+    /// Returns the result of the static transposition of the command line
+    /// `args`, given after the subcommand
+    fn static_transposition(args: &[&str]) -> Result<(), &'static str> {
+        use clap::Parser;
+        use config::ptconfig::{PitchCli, PitchSubcommand};
+        let base = ["pitch_transposer", "static"];
+        let command = PitchCli::parse_from(base.iter().chain(args));
+        let PitchSubcommand::Static { output, static_subcommand } = command.subcommand
+        else {
+            panic!("not a static transposition");
+        };
+        transpose_pitch(static_subcommand, &output.stage().unwrap())
+    }
+
+    #[test]
+    fn vibrato_of_illegal_frames_fails() {
+        let path = "resources/mono_16PCM_440hz_8000sps.wav";
+        for (fsize, hopa) in [("1000000", "256"), ("0", "256"), ("1024", "0")] {
+            let args = [
+                "vibrato", "-i", path, "-o", "lib_tests_vibrato.wav",
+                "--fsize", fsize, "--hopa", hopa
+            ];
+            let res = static_transposition(&args);
+            assert_eq!(Err("Illegal frame size or shift between frames"), res);
+        }
+    }
+
+    #[test]
+    fn harmony_of_illegal_frames_fails() {
+        let path = "resources/mono_16PCM_440hz_8000sps.wav";
+        for (fsize, hopa) in [("1000000", "256"), ("0", "256"), ("1024", "0")] {
+            let args = [
+                "harmonize", "-i", path, "-o", "lib_tests_harmony.wav",
+                "--fsize", fsize, "--hopa", hopa, "--voice", "2d"
+            ];
+            let res = static_transposition(&args);
+            assert_eq!(Err("Illegal frame size or shift between frames"), res);
+        }
+    }
+
+    #[test]
+    fn dry_signal_is_duplicated_on_added_channels() {
+        let header = wav::Header::new(wav::header::WAV_FORMAT_PCM, 2, 8000, 16);
+        let transposition = [1.0, 2.0, 3.0, 4.0, 5.0, 6.0];
+        let dry = mix::OutputStage { mix: 0.0, ..mix::OutputStage::wet() };
+        let path = output_path("lib_tests_dry.wav");
+        for (in_channels, signal, expected) in [
+            (
+                1,
+                vec![100.0, 200.0, 300.0],
+                vec![100.0, 100.0, 200.0, 200.0, 300.0, 300.0]
+            ),
+            (2, vec![100.0, 200.0, 300.0, 400.0], vec![100.0, 200.0, 300.0, 400.0])
+        ] {
+            write_transposition(header, in_channels, &signal, &transposition, &dry, &path)
+                .unwrap();
+            let (written, samples) = convert::read_wav(&path).unwrap();
+            assert_eq!(2, written.channel_count);
+            assert_eq!(expected, samples);
+        }
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn batch_phase_vocoder_is_the_static_one() {
+        let samp_rate = 8000.0;
+        let signal = Signal::Sine { frequency: 440.0 }
+            .synthesize(samp_rate, 1.0, 0.5)
+            .unwrap();
+        let shift = batch::Shift::Semitones(7.0);
+        let algorithm = batch::Algorithm::PhaseVocoder;
+        let batch = transpose_signal(&signal, samp_rate, algorithm, shift, 1024, 256)
+            .unwrap();
+        let single = phase_vocoder_corrector(&signal, 1024, 256, samp_rate, |track| {
+            vec![shift.ratio(); track.len()]
+        });
+        assert_eq!(signal.len(), batch.len());
+        assert_eq!(single, batch);
+    }
+
+    #[test]
+    fn transposition_has_the_length_of_the_signal() {
+        let header = wav::Header::new(wav::header::WAV_FORMAT_PCM, 1, 8000, 16);
+        let signal = [100.0, 200.0, 300.0, 400.0];
+        let half = mix::OutputStage { mix: 0.5, ..mix::OutputStage::wet() };
+        let path = output_path("lib_tests_length.wav");
+        for (transposition, expected) in [
+            (vec![2.0, 4.0], vec![51.0, 102.0, 150.0, 200.0]),
+            (vec![2.0, 4.0, 6.0, 8.0, 10.0, 12.0], vec![51.0, 102.0, 153.0, 204.0])
+        ] {
+            write_transposition(header, 1, &signal, &transposition, &half, &path)
+                .unwrap();
+            let (_, samples) = convert::read_wav(&path).unwrap();
+            assert_eq!(expected, samples);
+        }
+        std::fs::remove_file(path).unwrap();
+    }
+
 }
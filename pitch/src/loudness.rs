@@ -0,0 +1,274 @@
+use std::f64::consts::PI;
+use serde::Serialize;
+use crate::filter::Biquad;
+
+/// Duration, in seconds, of the gating blocks of the integrated loudness
+const BLOCK_DURATION: f32 = 0.4;
+
+/// Number of gating blocks overlapping each sample
+const BLOCK_OVERLAP: usize = 4;
+
+/// Loudness, in LUFS, under which gating blocks are silence
+const ABSOLUTE_GATE: f64 = -70.0;
+
+/// Difference, in LU, with the loudness of the blocks above the absolute gate
+/// under which blocks are ignored
+const RELATIVE_GATE: f64 = -10.0;
+
+/// Oversampling factor of the true peak measurement
+const OVERSAMPLING: usize = 4;
+
+/// Samples on each side of an interpolated sample used to compute it
+const INTERPOLATION_TAPS: usize = 16;
+
+/// Loudness of a signal, as defined by ITU-R BS.1770
+#[derive(Clone, Copy, Debug, PartialEq, Serialize)]
+pub struct Loudness {
+    pub integrated: f32, // integrated loudness in LUFS, -inf if all is silence
+    pub true_peak: f32,  // level of the true peak in dBTP
+}
+
+/// Level that a signal is normalized to
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Target {
+    /// an integrated loudness, in LUFS
+    Lufs(f32),
+    /// a true peak level, in dBTP
+    Peak(f32),
+}
+
+/// Returns the first stage of the K-weighting filter at `samp_rate` Hz: a
+/// high shelf of about +4 dB modeling the acoustic effect of the head
+pub fn k_shelf(samp_rate: f32) -> Biquad {
+    let (f0, gain, q) = (1681.974450955533, 3.999843853973347, 0.7071752369554196);
+    let k = (PI * f0 / samp_rate as f64).tan();
+    let vh = 10.0f64.powf(gain / 20.0);
+    let vb = vh.powf(0.4996667741545416);
+    let a0 = 1.0 + k / q + k * k;
+    Biquad {
+        b0: (vh + vb * k / q + k * k) / a0,
+        b1: 2.0 * (k * k - vh) / a0,
+        b2: (vh - vb * k / q + k * k) / a0,
+        a1: 2.0 * (k * k - 1.0) / a0,
+        a2: (1.0 - k / q + k * k) / a0,
+    }
+}
+
+/// Returns the second stage of the K-weighting filter at `samp_rate` Hz: the
+/// RLB high-pass filter
+pub fn k_high_pass(samp_rate: f32) -> Biquad {
+    let (f0, q) = (38.13547087602444, 0.5003270373238773);
+    let k = (PI * f0 / samp_rate as f64).tan();
+    let a0 = 1.0 + k / q + k * k;
+    Biquad {
+        b0: 1.0,
+        b1: -2.0,
+        b2: 1.0,
+        a1: 2.0 * (k * k - 1.0) / a0,
+        a2: (1.0 - k / q + k * k) / a0,
+    }
+}
+
+/// Returns the loudness, in LUFS, of the sum of mean squares `power`
+fn to_lufs(power: f64) -> f64 {
+    -0.691 + 10.0 * power.log10()
+}
+
+/// Returns the integrated loudness, in LUFS, of `channels` sampled at
+/// `samp_rate` Hz, of amplitudes between -1 and 1: the mean power of the
+/// K-weighted channels over the overlapping 400 ms blocks above the absolute
+/// gate and the relative gate
+pub fn integrated_loudness(channels: &[Vec<f32>], samp_rate: f32) -> f32 {
+    let weighted: Vec<Vec<f32>> = channels
+        .iter()
+        .map(|c| k_high_pass(samp_rate).filter(&k_shelf(samp_rate).filter(c)))
+        .collect();
+    let len = weighted.iter().map(Vec::len).min().unwrap_or(0);
+    let block = (BLOCK_DURATION * samp_rate).round() as usize;
+    let step = (block / BLOCK_OVERLAP).max(1);
+    if block == 0 || len < block {
+        return f32::NEG_INFINITY;
+    }
+
+    // power of each block, summed over channels
+    let powers: Vec<f64> = (0..=(len - block) / step)
+        .map(|i| {
+            weighted
+                .iter()
+                .map(|c| {
+                    let samples = &c[i * step..i * step + block];
+                    let energy: f64 = samples.iter().map(|&s| (s as f64).powi(2)).sum();
+                    energy / block as f64
+                })
+                .sum()
+        })
+        .collect();
+
+    let gated = |threshold: f64| -> Vec<f64> {
+        powers.iter().copied().filter(|&p| to_lufs(p) > threshold).collect()
+    };
+    let mean = |powers: &[f64]| powers.iter().sum::<f64>() / powers.len() as f64;
+    let above_absolute = gated(ABSOLUTE_GATE);
+    if above_absolute.is_empty() {
+        return f32::NEG_INFINITY;
+    }
+    let relative = to_lufs(mean(&above_absolute)) + RELATIVE_GATE;
+    let above_relative = gated(relative.max(ABSOLUTE_GATE));
+    to_lufs(mean(&above_relative)) as f32
+}
+
+/// Returns the value of a Hann windowed sinc at `x` samples, for the
+/// interpolation of the true peak
+fn windowed_sinc(x: f64) -> f64 {
+    if x == 0.0 {
+        return 1.0;
+    }
+    let window = 0.5 * (1.0 + (PI * x / (INTERPOLATION_TAPS + 1) as f64).cos());
+    window * (PI * x).sin() / (PI * x)
+}
+
+/// Returns the true peak of `signal`, the largest magnitude of the signal
+/// oversampled 4 times by windowed sinc interpolation
+pub fn true_peak(signal: &[f32]) -> f32 {
+    // coefficients of each phase between two samples, for the samples from
+    // INTERPOLATION_TAPS - 1 before to INTERPOLATION_TAPS after
+    let taps = INTERPOLATION_TAPS as isize;
+    let phases: Vec<Vec<f64>> = (1..OVERSAMPLING)
+        .map(|phase| {
+            (-(taps - 1)..=taps)
+                .map(|k| windowed_sinc(phase as f64 / OVERSAMPLING as f64 - k as f64))
+                .collect()
+        })
+        .collect();
+
+    let mut peak = signal.iter().fold(0.0f32, |max, s| max.max(s.abs()));
+    for n in 0..signal.len() {
+        let start = n.saturating_sub(INTERPOLATION_TAPS - 1);
+        let end = (n + INTERPOLATION_TAPS + 1).min(signal.len());
+        for coefficients in &phases {
+            let value: f64 = (start..end)
+                .map(|k| signal[k] as f64 * coefficients[k + INTERPOLATION_TAPS - 1 - n])
+                .sum();
+            peak = peak.max(value.abs() as f32);
+        }
+    }
+    peak
+}
+
+/// Returns the loudness of the `channels` channels interleaved in `signal`,
+/// sampled at `samp_rate` Hz, of amplitudes between `-full_scale` and
+/// `full_scale`
+pub fn measure(
+    signal: &[f32],
+    channels: usize,
+    samp_rate: f32,
+    full_scale: f32
+) -> Loudness {
+    let channels = channels.max(1);
+    let deinterleaved: Vec<Vec<f32>> = (0..channels)
+        .map(|c| {
+            signal.iter().skip(c).step_by(channels).map(|s| s / full_scale).collect()
+        })
+        .collect();
+    let peak = deinterleaved.iter().map(|c| true_peak(c)).fold(0.0f32, f32::max);
+    Loudness {
+        integrated: integrated_loudness(&deinterleaved, samp_rate),
+        true_peak: 20.0 * peak.log10(),
+    }
+}
+
+/// Returns `signal`, as given to `measure()`, scaled to reach `target`. A
+/// silent signal is returned unchanged.
+pub fn normalize(
+    signal: &[f32],
+    channels: usize,
+    samp_rate: f32,
+    full_scale: f32,
+    target: Target
+) -> Vec<f32> {
+    let loudness = measure(signal, channels, samp_rate, full_scale);
+    let gain = match target {
+        Target::Lufs(lufs) => lufs - loudness.integrated,
+        Target::Peak(peak) => peak - loudness.true_peak,
+    };
+    if !gain.is_finite() {
+        return signal.to_vec();
+    }
+    let amplitude = 10.0f32.powf(gain / 20.0);
+    signal.iter().map(|s| s * amplitude).collect()
+}
+
+#[cfg(test)]
+mod loudness_tests {
+    use super::*;
+    use crate::generate::Signal;
+
+    const KHZ: Signal = Signal::Sine { frequency: 1000.0 };
+
+    #[test]
+    fn k_weighting_coefficients_at_48khz() {
+        // coefficients given by ITU-R BS.1770
+        let shelf = k_shelf(48000.0);
+        assert!((shelf.b0 - 1.53512485958697).abs() < 1e-8);
+        assert!((shelf.b1 + 2.69169618940638).abs() < 1e-8);
+        assert!((shelf.b2 - 1.19839281085285).abs() < 1e-8);
+        assert!((shelf.a1 + 1.69065929318241).abs() < 1e-8);
+        assert!((shelf.a2 - 0.73248077421585).abs() < 1e-8);
+        let high_pass = k_high_pass(48000.0);
+        assert!((high_pass.a1 + 1.99004745483398).abs() < 1e-8);
+        assert!((high_pass.a2 - 0.99007225036621).abs() < 1e-8);
+    }
+
+    #[test]
+    fn loudness_of_sine_at_minus_20_dbfs() {
+        // a 1 kHz sine of peak level -20 dBFS is about -23 LUFS
+        let signal = KHZ.synthesize(48000.0, 2.0, 0.1).unwrap();
+        let lufs = integrated_loudness(&[signal], 48000.0);
+        assert!((lufs + 23.0).abs() < 0.1, "{}", lufs);
+        // at any sampling rate
+        let signal = KHZ.synthesize(44100.0, 2.0, 0.1).unwrap();
+        assert!((integrated_loudness(&[signal], 44100.0) + 23.0).abs() < 0.1);
+    }
+
+    #[test]
+    fn gating_ignores_silence_and_quiet_parts() {
+        let mut signal = KHZ.synthesize(8000.0, 3.0, 0.1).unwrap();
+        signal.extend(vec![0.0; 24000]);
+        // -38 LUFS, under the relative gate
+        signal.extend(KHZ.synthesize(8000.0, 3.0, 0.0178).unwrap());
+        let lufs = integrated_loudness(&[signal], 8000.0);
+        // blocks over the end of the loud part are a bit quieter
+        assert!((lufs + 23.0).abs() < 0.3, "{}", lufs);
+        assert_eq!(f32::NEG_INFINITY, integrated_loudness(&[vec![0.0; 8000]], 8000.0));
+        assert_eq!(f32::NEG_INFINITY, integrated_loudness(&[vec![0.5; 100]], 8000.0));
+    }
+
+    #[test]
+    fn true_peak_between_samples() {
+        // samples at 45 degrees of a quarter of the sampling rate sine
+        let signal: Vec<f32> = (0..400)
+            .map(|n| (std::f32::consts::PI * (n as f32 / 2.0 + 0.25)).sin())
+            .collect();
+        let sample_peak = signal.iter().fold(0.0f32, |max, s| max.max(s.abs()));
+        assert!((sample_peak - std::f32::consts::FRAC_1_SQRT_2).abs() < 1e-3);
+        assert!((true_peak(&signal) - 1.0).abs() < 0.02);
+    }
+
+    #[test]
+    fn normalize_to_lufs_and_peak() {
+        let signal: Vec<f32> = KHZ
+            .synthesize(8000.0, 2.0, 0.1)
+            .unwrap()
+            .iter()
+            .map(|s| s * 32768.0)
+            .collect();
+        let stereo: Vec<f32> = signal.iter().flat_map(|s| [*s, *s]).collect();
+        let loud = normalize(&stereo, 2, 8000.0, 32768.0, Target::Lufs(-16.0));
+        let lufs = measure(&loud, 2, 8000.0, 32768.0).integrated;
+        assert!((lufs + 16.0).abs() < 0.05, "{}", lufs);
+        let peak = normalize(&signal, 1, 8000.0, 32768.0, Target::Peak(-1.0));
+        assert!((measure(&peak, 1, 8000.0, 32768.0).true_peak + 1.0).abs() < 0.05);
+        let silence = vec![0.0; 8000];
+        assert_eq!(silence, normalize(&silence, 1, 8000.0, 1.0, Target::Lufs(-16.0)));
+    }
+}
@@ -0,0 +1,521 @@
+use clap::ValueEnum;
+use rustfft::FftPlanner;
+
+/// Size of the frames of the spectrograms compared by the metrics
+pub const FRAME_SIZE: usize = 2048;
+
+/// Shift between the frames of the spectrograms compared by the metrics
+pub const HOP_SIZE: usize = 512;
+
+/// Number of bands of the mel filter bank of the mel-cepstral distortion
+const MEL_BANDS: usize = 40;
+
+/// Number of mel-cepstral coefficients compared, the energy (0th) one apart
+const CEPSTRAL_COEFFICIENTS: usize = 13;
+
+/// Smallest power whose logarithm is taken, so that silence has a finite
+/// level (-100 dB)
+const POWER_FLOOR: f32 = 1e-10;
+
+/// Range, in dB under the loudest bin of the oracle, of the levels compared
+/// by the log-spectral metrics: quieter bins, like the leakage of the window,
+/// are raised to it so that they do not outweigh the audible ones
+const DYNAMIC_RANGE: f32 = 80.0;
+
+/// Measure of the difference between a signal and an oracle signal. The
+/// spectral metrics compare magnitude spectrograms (Hann windowed frames of
+/// `FRAME_SIZE` samples every `HOP_SIZE` samples), so they ignore the phase,
+/// which pitch transposition does not preserve.
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq)]
+pub enum Metric {
+    /// Euclidean distance of the real parts of the FFTs of the whole signals,
+    /// in [0, +inf), 0 for identical signals. Phase sensitive, kept for
+    /// compatibility.
+    Euclidean,
+    /// Log-spectral distance in dB, in [0, +inf), 0 for identical spectrums:
+    /// mean over frames of the root mean square difference of the levels of
+    /// the bins
+    Lsd,
+    /// Spectral convergence, in [0, +inf), 0 for identical spectrums and 1
+    /// against silence: Frobenius norm of the difference of the magnitude
+    /// spectrograms relative to the norm of the oracle one
+    SpectralConvergence,
+    /// Mean absolute difference of the magnitude spectrograms, in [0, +inf),
+    /// 0 for identical spectrums
+    SpectrogramL1,
+    /// Root mean square difference of the magnitude spectrograms, in
+    /// [0, +inf), 0 for identical spectrums
+    SpectrogramL2,
+    /// Signal to noise ratio in dB, the noise being the difference with the
+    /// oracle, in (-inf, +inf], +inf for identical signals. Higher is better.
+    Snr,
+    /// Scale-invariant signal to distortion ratio in dB, the SNR of the
+    /// signal against the oracle scaled to fit it best, in (-inf, +inf],
+    /// +inf for proportional signals. Higher is better.
+    SiSdr,
+    /// Mel-cepstral distortion in dB, in [0, +inf), 0 for identical spectral
+    /// envelopes: mean over frames of the distance of the mel-cepstral
+    /// coefficients 1 to 13
+    Mcd,
+}
+
+impl Metric {
+
+    /// Returns the name of the metric, as given to `--metric`
+    pub fn name(&self) -> String {
+        self.to_possible_value().map_or(String::new(), |v| v.get_name().to_string())
+    }
+
+    /// Returns the metric named `name` (see `name()`), None if there is none
+    pub fn from_name(name: &str) -> Option<Metric> {
+        <Metric as ValueEnum>::from_str(name, false).ok()
+    }
+
+    /// Returns true if greater values of the metric mean closer signals
+    pub fn higher_is_better(&self) -> bool {
+        matches!(self, Metric::Snr | Metric::SiSdr)
+    }
+
+    /// Returns true if `value` of the metric passes `threshold`: is at most
+    /// the threshold for distances, at least the threshold for ratios
+    pub fn passes(&self, value: f32, threshold: f32) -> bool {
+        if self.higher_is_better() {
+            value >= threshold
+        } else {
+            value <= threshold
+        }
+    }
+
+    /// Returns the value of the metric for `signal` against `oracle`, both
+    /// sampled at `samp_rate` Hz and of amplitudes between -1 and 1. Samples
+    /// past the shortest signal are ignored.
+    pub fn compute(&self, signal: &[f32], oracle: &[f32], samp_rate: f32) -> f32 {
+        self.compute_framed(signal, oracle, samp_rate, FRAME_SIZE, HOP_SIZE)
+    }
+
+    /// Returns the value of the metric as `compute()` does, the spectrograms
+    /// being made of frames of `fsize` samples every `hop` samples
+    pub fn compute_framed(
+        &self,
+        signal: &[f32],
+        oracle: &[f32],
+        samp_rate: f32,
+        fsize: usize,
+        hop: usize
+    ) -> f32 {
+        let len = signal.len().min(oracle.len());
+        let (signal, oracle) = (&signal[..len], &oracle[..len]);
+        match self {
+            Metric::Euclidean => euclidean(signal, oracle),
+            Metric::Snr => snr(signal, oracle),
+            Metric::SiSdr => si_sdr(signal, oracle),
+            _ => {
+                let s = magnitude_spectrogram(signal, fsize, hop);
+                let o = magnitude_spectrogram(oracle, fsize, hop);
+                match self {
+                    Metric::Lsd => log_spectral_distance(&s, &o),
+                    Metric::SpectralConvergence => spectral_convergence(&s, &o),
+                    Metric::SpectrogramL1 => spectrogram_l1(&s, &o),
+                    Metric::SpectrogramL2 => spectrogram_l2(&s, &o),
+                    _ => mel_cepstral_distortion(&s, &o, samp_rate),
+                }
+            },
+        }
+    }
+}
+
+/// Returns the Euclidean distance of the real parts of the FFTs of `signal`
+/// and `oracle`
+fn euclidean(signal: &[f32], oracle: &[f32]) -> f32 {
+    let mut planner = FftPlanner::new();
+    let sspec = crate::apply_fft(&mut planner, signal);
+    let ospec = crate::apply_fft(&mut planner, oracle);
+    crate::compute_and_compare_distance(0.0, &sspec, &ospec).1
+}
+
+/// Returns the magnitude spectrogram of `signal`: the magnitudes of the
+/// positive frequency bins of its Hann windowed frames of `fsize` samples
+/// every `hop` samples. A signal shorter than a frame is padded with silence.
+pub fn magnitude_spectrogram(signal: &[f32], fsize: usize, hop: usize) -> Vec<Vec<f32>> {
+    let mut padded = signal.to_vec();
+    padded.resize(signal.len().max(fsize), 0.0);
+    let mut planner = FftPlanner::new();
+    crate::frame(&padded, fsize, hop)
+        .iter()
+        .map(|f| {
+            let spectrum = crate::apply_fft(&mut planner, &crate::von_hann_window(f));
+            spectrum[..fsize / 2 + 1].iter().map(|c| c.norm()).collect()
+        })
+        .collect()
+}
+
+/// Returns the differences of the magnitudes of the bins of the spectrograms
+/// `s` and `o`
+fn differences(s: &[Vec<f32>], o: &[Vec<f32>]) -> Vec<f32> {
+    s.iter()
+        .zip(o)
+        .flat_map(|(s, o)| s.iter().zip(o).map(|(a, b)| a - b))
+        .collect()
+}
+
+/// Returns the smallest power compared by the log-spectral metrics for the
+/// oracle spectrogram `o` (see `DYNAMIC_RANGE`)
+fn power_floor(o: &[Vec<f32>]) -> f32 {
+    let peak = o.iter().flatten().fold(0.0f32, |max, m| max.max(*m));
+    (peak * peak * 10.0f32.powf(-DYNAMIC_RANGE / 10.0)).max(POWER_FLOOR)
+}
+
+/// Returns the level, in dB, of the magnitude `m`, raised to the power
+/// `floor`
+fn level(m: f32, floor: f32) -> f32 {
+    10.0 * (m * m).max(floor).log10()
+}
+
+/// Returns the log-spectral distance, in dB, of the spectrograms `s` and `o`
+pub fn log_spectral_distance(s: &[Vec<f32>], o: &[Vec<f32>]) -> f32 {
+    let frames = s.len().min(o.len());
+    if frames == 0 {
+        return 0.0;
+    }
+    let floor = power_floor(o);
+    let sum: f32 = s
+        .iter()
+        .zip(o)
+        .map(|(s, o)| {
+            let squares: f32 = s
+                .iter()
+                .zip(o)
+                .map(|(a, b)| (level(*a, floor) - level(*b, floor)).powi(2))
+                .sum();
+            (squares / s.len().min(o.len()).max(1) as f32).sqrt()
+        })
+        .sum();
+    sum / frames as f32
+}
+
+/// Returns the spectral convergence of the spectrogram `s` to `o`
+pub fn spectral_convergence(s: &[Vec<f32>], o: &[Vec<f32>]) -> f32 {
+    let difference: f32 = differences(s, o).iter().map(|d| d * d).sum();
+    let reference: f32 = o.iter().flatten().map(|b| b * b).sum();
+    if reference == 0.0 {
+        return if difference == 0.0 { 0.0 } else { f32::INFINITY };
+    }
+    (difference / reference).sqrt()
+}
+
+/// Returns the mean absolute difference of the spectrograms `s` and `o`
+pub fn spectrogram_l1(s: &[Vec<f32>], o: &[Vec<f32>]) -> f32 {
+    let differences = differences(s, o);
+    let sum: f32 = differences.iter().map(|d| d.abs()).sum();
+    sum / differences.len().max(1) as f32
+}
+
+/// Returns the root mean square difference of the spectrograms `s` and `o`
+pub fn spectrogram_l2(s: &[Vec<f32>], o: &[Vec<f32>]) -> f32 {
+    let differences = differences(s, o);
+    let sum: f32 = differences.iter().map(|d| d * d).sum();
+    (sum / differences.len().max(1) as f32).sqrt()
+}
+
+/// Returns the ratio, in dB, of the energies of `target` and `noise`
+fn energy_ratio(target: f64, noise: f64) -> f32 {
+    if noise == 0.0 {
+        return if target == 0.0 { 0.0 } else { f32::INFINITY };
+    }
+    (10.0 * (target / noise).log10()) as f32
+}
+
+/// Returns the signal to noise ratio, in dB, of `signal` against `oracle`
+pub fn snr(signal: &[f32], oracle: &[f32]) -> f32 {
+    let target: f64 = oracle.iter().map(|&o| (o as f64).powi(2)).sum();
+    let noise: f64 = signal
+        .iter()
+        .zip(oracle)
+        .map(|(&s, &o)| (s as f64 - o as f64).powi(2))
+        .sum();
+    energy_ratio(target, noise)
+}
+
+/// Returns the scale-invariant signal to distortion ratio, in dB, of `signal`
+/// against `oracle`
+pub fn si_sdr(signal: &[f32], oracle: &[f32]) -> f32 {
+    let dot: f64 = signal.iter().zip(oracle).map(|(&s, &o)| s as f64 * o as f64).sum();
+    let energy: f64 = oracle.iter().map(|&o| (o as f64).powi(2)).sum();
+    if energy == 0.0 {
+        return f32::NEG_INFINITY;
+    }
+    let scale = dot / energy;
+    let target = scale * scale * energy;
+    let noise: f64 = signal
+        .iter()
+        .zip(oracle)
+        .map(|(&s, &o)| (s as f64 - scale * o as f64).powi(2))
+        .sum();
+    energy_ratio(target, noise)
+}
+
+/// Returns the mel frequency of `freq` Hz
+pub(crate) fn hz_to_mel(freq: f32) -> f32 {
+    2595.0 * (1.0 + freq / 700.0).log10()
+}
+
+/// Returns the frequency, in Hz, of the mel frequency `mel`
+pub(crate) fn mel_to_hz(mel: f32) -> f32 {
+    700.0 * (10.0f32.powf(mel / 2595.0) - 1.0)
+}
+
+/// Returns the `MEL_BANDS` triangular filters, equally spaced on the mel
+/// scale between 0 Hz and the Nyquist frequency, as weights of the `nb_bins`
+/// positive frequency bins of a spectrum of a signal sampled at `samp_rate` Hz
+fn mel_filter_bank(nb_bins: usize, samp_rate: f32) -> Vec<Vec<f32>> {
+    let nyquist = samp_rate / 2.0;
+    let step = hz_to_mel(nyquist) / (MEL_BANDS + 1) as f32;
+    let edges: Vec<f32> =
+        (0..MEL_BANDS + 2).map(|i| mel_to_hz(i as f32 * step)).collect();
+    let bin_freq = |k: usize| k as f32 * nyquist / (nb_bins - 1).max(1) as f32;
+    edges
+        .windows(3)
+        .map(|e| {
+            (0..nb_bins)
+                .map(|k| {
+                    let f = bin_freq(k);
+                    if f <= e[0] || f >= e[2] {
+                        0.0
+                    } else if f <= e[1] {
+                        (f - e[0]) / (e[1] - e[0])
+                    } else {
+                        (e[2] - f) / (e[2] - e[1])
+                    }
+                })
+                .collect()
+        })
+        .collect()
+}
+
+/// Returns the mel-cepstral coefficients 1 to `CEPSTRAL_COEFFICIENTS` of the
+/// magnitude spectrum `spectrum`: the DCT-II of the natural logarithms of
+/// its energies in the bands of `bank`, raised to the power `floor`
+fn mel_cepstrum(spectrum: &[f32], bank: &[Vec<f32>], floor: f32) -> Vec<f32> {
+    let log_energies: Vec<f32> = bank
+        .iter()
+        .map(|band| {
+            let energy: f32 = band.iter().zip(spectrum).map(|(w, m)| w * m * m).sum();
+            energy.max(floor).ln()
+        })
+        .collect();
+    let n = log_energies.len() as f32;
+    (1..=CEPSTRAL_COEFFICIENTS)
+        .map(|k| {
+            log_energies
+                .iter()
+                .enumerate()
+                .map(|(i, e)| {
+                    let angle = std::f32::consts::PI * k as f32 * (i as f32 + 0.5) / n;
+                    e * angle.cos()
+                })
+                .sum::<f32>()
+                * (2.0 / n).sqrt()
+        })
+        .collect()
+}
+
+/// Returns the mel-cepstral distortion, in dB, of the spectrograms `s` and
+/// `o` of signals sampled at `samp_rate` Hz
+pub fn mel_cepstral_distortion(s: &[Vec<f32>], o: &[Vec<f32>], samp_rate: f32) -> f32 {
+    let frames = s.len().min(o.len());
+    if frames == 0 {
+        return 0.0;
+    }
+    let bank = mel_filter_bank(s[0].len(), samp_rate);
+    let floor = power_floor(o);
+    let factor = 10.0 / std::f32::consts::LN_10 * 2.0f32.sqrt();
+    let sum: f32 = s
+        .iter()
+        .zip(o)
+        .map(|(s, o)| {
+            let (cs, co) = (mel_cepstrum(s, &bank, floor), mel_cepstrum(o, &bank, floor));
+            factor * cs.iter().zip(&co).map(|(a, b)| (a - b).powi(2)).sum::<f32>().sqrt()
+        })
+        .sum();
+    sum / frames as f32
+}
+
+#[cfg(test)]
+mod metrics_tests {
+    use super::*;
+    use crate::generate::Signal;
+
+    const ALL: [Metric; 8] = [
+        Metric::Euclidean,
+        Metric::Lsd,
+        Metric::SpectralConvergence,
+        Metric::SpectrogramL1,
+        Metric::SpectrogramL2,
+        Metric::Snr,
+        Metric::SiSdr,
+        Metric::Mcd,
+    ];
+
+    #[test]
+    fn metric_names() {
+        assert_eq!("si-sdr", Metric::SiSdr.name());
+        assert_eq!("spectrogram-l1", Metric::SpectrogramL1.name());
+        for metric in ALL {
+            assert_eq!(Some(metric), Metric::from_name(&metric.name()));
+        }
+        assert_eq!(None, Metric::from_name("psnr"));
+    }
+
+    #[test]
+    fn identical_signals_are_best() {
+        let signal = Signal::Sine { frequency: 440.0 }
+            .synthesize(8000.0, 1.0, 0.5)
+            .unwrap();
+        for metric in ALL {
+            let value = metric.compute(&signal, &signal, 8000.0);
+            if metric.higher_is_better() {
+                assert_eq!(f32::INFINITY, value, "{:?}", metric);
+            } else {
+                assert!(value.abs() < 1e-6, "{:?}: {}", metric, value);
+            }
+            assert!(metric.passes(value, 0.0));
+        }
+    }
+
+    #[test]
+    fn spectral_metrics_ignore_phase() {
+        // a sine against a cosine: same magnitudes, different phases
+        let samp_rate = 8000.0;
+        let signal = Signal::Sine { frequency: 500.0 }
+            .synthesize(samp_rate, 1.0, 0.5)
+            .unwrap();
+        let shifted: Vec<f32> = (0..8000)
+            .map(|n| {
+                let phase = 2.0 * std::f32::consts::PI * 500.0 * n as f32 / samp_rate;
+                0.5 * phase.cos()
+            })
+            .collect();
+        let octave = Signal::Sine { frequency: 1000.0 }
+            .synthesize(samp_rate, 1.0, 0.5)
+            .unwrap();
+        for metric in [Metric::Lsd, Metric::SpectralConvergence, Metric::Mcd] {
+            // only the leakage of the window, far under the sine, differs
+            let phase = metric.compute(&signal, &shifted, samp_rate);
+            let pitch = metric.compute(&octave, &signal, samp_rate);
+            assert!(phase < pitch / 3.0, "{:?}: {} {}", metric, phase, pitch);
+        }
+        assert!(Metric::SpectralConvergence.compute(&signal, &shifted, samp_rate) < 0.01);
+        // the noise, their difference, is twice as loud as the oracle
+        assert!((Metric::Snr.compute(&signal, &shifted, samp_rate) + 3.01).abs() < 0.01);
+    }
+
+    #[test]
+    fn spectral_metrics_grow_with_pitch_difference() {
+        let samp_rate = 8000.0;
+        let oracle = Signal::Sine { frequency: 440.0 }
+            .synthesize(samp_rate, 1.0, 0.5)
+            .unwrap();
+        let near = Signal::Sine { frequency: 450.0 }
+            .synthesize(samp_rate, 1.0, 0.5)
+            .unwrap();
+        let far = Signal::Sine { frequency: 880.0 }
+            .synthesize(samp_rate, 1.0, 0.5)
+            .unwrap();
+        for metric in [Metric::Lsd, Metric::SpectralConvergence, Metric::Mcd] {
+            let (n, f) = (
+                metric.compute(&near, &oracle, samp_rate),
+                metric.compute(&far, &oracle, samp_rate)
+            );
+            assert!(0.0 < n && n < f, "{:?}: {} {}", metric, n, f);
+        }
+    }
+
+    #[test]
+    fn spectral_convergence_against_silence_is_one() {
+        let signal = Signal::Sine { frequency: 440.0 }
+            .synthesize(8000.0, 0.512, 0.5)
+            .unwrap();
+        let silence = vec![0.0; 4096];
+        let sc = Metric::SpectralConvergence.compute(&silence, &signal, 8000.0);
+        assert!((sc - 1.0).abs() < 1e-6);
+        let sc = Metric::SpectralConvergence.compute(&signal, &silence, 8000.0);
+        assert_eq!(f32::INFINITY, sc);
+    }
+
+    #[test]
+    fn frame_size_of_spectrograms() {
+        let signal = Signal::Sine { frequency: 440.0 }
+            .synthesize(8000.0, 1.0, 0.5)
+            .unwrap();
+        let other = Signal::Sine { frequency: 450.0 }
+            .synthesize(8000.0, 1.0, 0.5)
+            .unwrap();
+        let framed = |fsize, hop| {
+            let sc = Metric::SpectralConvergence;
+            sc.compute_framed(&other, &signal, 8000.0, fsize, hop)
+        };
+        let default = Metric::SpectralConvergence.compute(&other, &signal, 8000.0);
+        assert_eq!(default, framed(2048, 512));
+        // the finer the frequency resolution, the further 10 Hz apart
+        assert!(framed(256, 64) < framed(4096, 1024));
+        assert_eq!(2, magnitude_spectrogram(&signal[..1000], 512, 256).len());
+        assert_eq!(1, magnitude_spectrogram(&signal[..100], 512, 256).len());
+    }
+
+    #[test]
+    fn spectrogram_distances() {
+        let s = vec![vec![1.0, 2.0], vec![3.0, 4.0]];
+        let o = vec![vec![1.0, 0.0], vec![3.0, 0.0]];
+        assert_eq!(1.5, spectrogram_l1(&s, &o));
+        assert_eq!(5.0f32.sqrt(), spectrogram_l2(&s, &o));
+        // 20 dB between the levels of each bin
+        let s = vec![vec![1.0, 10.0]];
+        let o = vec![vec![10.0, 1.0]];
+        assert!((log_spectral_distance(&s, &o) - 20.0).abs() < 1e-3);
+    }
+
+    #[test]
+    fn snr_and_si_sdr_of_noisy_signal() {
+        let oracle = Signal::Sine { frequency: 440.0 }
+            .synthesize(8000.0, 1.0, 1.0)
+            .unwrap();
+        // noise 10 times quieter: 20 dB
+        let noise = Signal::Sine { frequency: 1234.0 }
+            .synthesize(8000.0, 1.0, 0.1)
+            .unwrap();
+        let noisy: Vec<f32> = oracle.iter().zip(&noise).map(|(o, n)| o + n).collect();
+        assert!((snr(&noisy, &oracle) - 20.0).abs() < 0.05);
+        assert!((si_sdr(&noisy, &oracle) - 20.0).abs() < 0.05);
+        // a quieter copy has a poor SNR but a perfect SI-SDR
+        let quiet: Vec<f32> = oracle.iter().map(|o| 0.5 * o).collect();
+        assert!((snr(&quiet, &oracle) - 6.02).abs() < 0.01);
+        assert_eq!(f32::INFINITY, si_sdr(&quiet, &oracle));
+        assert!(Metric::SiSdr.passes(20.0, 15.0) && !Metric::SiSdr.passes(10.0, 15.0));
+    }
+
+    #[test]
+    fn mel_filter_bank_covers_spectrum() {
+        let bank = mel_filter_bank(1025, 16000.0);
+        assert_eq!(MEL_BANDS, bank.len());
+        // triangles peaking at 1 and overlapping by halves
+        for band in &bank {
+            let max = band.iter().fold(0.0f32, |m, w| m.max(*w));
+            assert!(max > 0.5 && max <= 1.0);
+        }
+        // up to the centre of the last band
+        for k in 10..900 {
+            let sum: f32 = bank.iter().map(|b| b[k]).sum();
+            assert!((sum - 1.0).abs() < 1e-4, "{}: {}", k, sum);
+        }
+    }
+
+    #[test]
+    fn mcd_ignores_level() {
+        // white noise, of energy in every band
+        let noise = Signal::WhiteNoise { seed: 1 }.synthesize(8000.0, 1.0, 0.5).unwrap();
+        // a gain only changes the 0th coefficient, which is not compared
+        let louder: Vec<f32> = noise.iter().map(|s| 2.0 * s).collect();
+        assert!(Metric::Mcd.compute(&louder, &noise, 8000.0) < 0.01);
+        assert!((Metric::Lsd.compute(&louder, &noise, 8000.0) - 6.02).abs() < 0.01);
+    }
+}
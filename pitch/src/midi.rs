@@ -0,0 +1,127 @@
+/// Standard MIDI File reading and writing module
+pub mod smf;
+
+use crate::midi::smf::{Division, EventKind, Note, Smf, TempoMap, TrackEvent};
+
+/// Number of ticks per quarter note of written MIDI files
+const TICKS_PER_QUARTER: u16 = 480;
+
+/// Tempo of written MIDI files, in microseconds per quarter note (120 bpm)
+const TEMPO: u32 = 500_000;
+
+/// Returns the frequency of the MIDI note number `key`, A4 (69) being 440 Hz
+pub fn note_frequency(key: u8) -> f32 {
+    440.0 * 2.0f32.powf((key as f32 - 69.0) / 12.0)
+}
+
+/// Returns a single track MIDI file holding the given notes, at 120 beats per
+/// minute
+pub fn notes_to_smf(notes: &[Note]) -> Smf {
+    let division = Division::TicksPerQuarter(TICKS_PER_QUARTER);
+    let tempo_map = TempoMap::new(division, &[(0, TEMPO)]);
+    let mut events = vec![TrackEvent { tick: 0, kind: EventKind::Tempo(TEMPO) }];
+    for note in notes {
+        let (channel, key) = (note.channel, note.key);
+        events.push(TrackEvent {
+            tick: tempo_map.tick(note.start),
+            kind: EventKind::NoteOn { channel, key, velocity: note.velocity },
+        });
+        events.push(TrackEvent {
+            tick: tempo_map.tick(note.end),
+            kind: EventKind::NoteOff { channel, key, velocity: 0 },
+        });
+    }
+    // note offs first, so that a note ending when the next one starts is
+    // not cut by it
+    events.sort_by_key(|e| (e.tick, !matches!(e.kind, EventKind::NoteOff { .. })));
+    Smf { format: 0, division, tracks: vec![events] }
+}
+
+/// A melody read from a MIDI file, i.e the notes that are expected to be sung
+/// over time
+pub struct Melody {
+    notes: Vec<Note>, // sorted by start time
+}
+
+impl Melody {
+
+    /// Returns the melody made of the given notes
+    pub fn new(mut notes: Vec<Note>) -> Melody {
+        notes.sort_by(|a, b| a.start.total_cmp(&b.start));
+        Melody { notes }
+    }
+
+    /// Loads the melody of the MIDI file at `path`. Only notes of `track`
+    /// (from 0) and `channel` (from 1 to 16) are kept if they are given. The
+    /// melody starts `offset` seconds after the beginning of the audio.
+    pub fn load(
+        path: &str,
+        track: Option<usize>,
+        channel: Option<u8>,
+        offset: f64
+    ) -> Result<Melody, &'static str> {
+        let channel = match channel {
+            Some(c) if (1..=16).contains(&c) => Some(c - 1),
+            Some(_) => return Err("MIDI channel must be between 1 and 16"),
+            None => None,
+        };
+        let smf = Smf::load(path)?;
+        let mut notes = smf.notes(track, channel)?;
+        for note in notes.iter_mut() {
+            note.start += offset;
+            note.end += offset;
+        }
+        Ok(Melody::new(notes))
+    }
+
+    /// Returns the note held at `time` seconds, if any. When several notes are
+    /// held, the last one to start is returned.
+    pub fn note_at(&self, time: f64) -> Option<u8> {
+        let started = self.notes.partition_point(|n| n.start <= time);
+        self.notes[..started]
+            .iter()
+            .rev()
+            .find(|n| time < n.end)
+            .map(|n| n.key)
+    }
+}
+
+#[cfg(test)]
+mod midi_tests {
+    use super::*;
+
+    fn note(start: f64, end: f64, key: u8) -> Note {
+        Note { start, end, channel: 0, key, velocity: 100 }
+    }
+
+    #[test]
+    fn note_frequency_of_a4_and_a5() {
+        assert_eq!(440.0, note_frequency(69));
+        assert_eq!(880.0, note_frequency(81));
+        assert!((note_frequency(60) - 261.63).abs() < 0.01);
+    }
+
+    #[test]
+    fn notes_to_smf_then_notes_gives_same_notes() {
+        let notes = vec![note(0.0, 0.5, 60), note(0.5, 1.25, 60), note(2.0, 2.1, 72)];
+        let smf = Smf::parse(&notes_to_smf(&notes).to_bytes()).unwrap();
+        assert_eq!(notes, smf.notes(None, None).unwrap());
+    }
+
+    #[test]
+    fn note_at_returns_held_note() {
+        let melody = Melody::new(vec![note(1.0, 2.0, 62), note(0.0, 1.0, 60)]);
+        assert_eq!(Some(60), melody.note_at(0.0));
+        assert_eq!(Some(60), melody.note_at(0.99));
+        assert_eq!(Some(62), melody.note_at(1.0));
+        assert_eq!(None, melody.note_at(2.0));
+        assert_eq!(None, melody.note_at(-1.0));
+    }
+
+    #[test]
+    fn note_at_prefers_last_started_note() {
+        let melody = Melody::new(vec![note(0.0, 3.0, 60), note(1.0, 2.0, 64)]);
+        assert_eq!(Some(64), melody.note_at(1.5));
+        assert_eq!(Some(60), melody.note_at(2.5));
+    }
+}
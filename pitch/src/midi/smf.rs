@@ -0,0 +1,505 @@
+use std::fs;
+
+/// Time division of a Standard MIDI File, i.e the meaning of its ticks
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Division {
+    /// number of ticks per quarter note, tempo changes apply
+    TicksPerQuarter(u16),
+    /// number of SMPTE frames per second and of ticks per frame
+    Smpte { fps: u8, ticks_per_frame: u8 },
+}
+
+/// The events of a track that are of interest to the crate. Channels are
+/// numbered from 0 to 15.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum EventKind {
+    NoteOn { channel: u8, key: u8, velocity: u8 },
+    NoteOff { channel: u8, key: u8, velocity: u8 },
+    /// tempo change, in microseconds per quarter note
+    Tempo(u32),
+    /// any other event, which content is dropped
+    Other,
+}
+
+/// An event of a track, at an absolute time in ticks
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct TrackEvent {
+    pub tick: u64,
+    pub kind: EventKind,
+}
+
+/// A note held between `start` and `end`, in seconds
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Note {
+    pub start: f64,
+    pub end: f64,
+    pub channel: u8,
+    pub key: u8,
+    pub velocity: u8,
+}
+
+/// A parsed Standard MIDI File
+#[derive(Clone, Debug, PartialEq)]
+pub struct Smf {
+    pub format: u16,
+    pub division: Division,
+    pub tracks: Vec<Vec<TrackEvent>>,
+}
+
+/// Conversion of ticks to seconds, following the tempo changes of a file
+pub struct TempoMap {
+    division: Division,
+    changes: Vec<(u64, f64, u32)>, // tick, seconds at tick, tempo from tick
+}
+
+/// Default tempo of MIDI files: 120 beats per minute
+const DEFAULT_TEMPO: u32 = 500_000;
+
+/// Reads the `n` bytes starting at `*pos` in `bytes` and moves `*pos` after
+/// them
+fn read<'a>(bytes: &'a [u8], pos: &mut usize, n: usize) -> Result<&'a [u8], &'static str> {
+    match bytes.get(*pos..*pos + n) {
+        Some(read) => {
+            *pos += n;
+            Ok(read)
+        },
+        None => Err("truncated MIDI file"),
+    }
+}
+
+/// Reads the big endian number of `n` bytes starting at `*pos` in `bytes`
+fn read_number(bytes: &[u8], pos: &mut usize, n: usize) -> Result<u32, &'static str> {
+    Ok(read(bytes, pos, n)?.iter().fold(0, |acc, &b| (acc << 8) | b as u32))
+}
+
+/// Reads the variable length quantity starting at `*pos` in `bytes`. Each
+/// byte holds 7 bits of the number, the last one has its top bit cleared.
+fn read_vlq(bytes: &[u8], pos: &mut usize) -> Result<u32, &'static str> {
+    let mut value = 0u32;
+    for _ in 0..4 {
+        let b = read(bytes, pos, 1)?[0];
+        value = (value << 7) | (b & 0x7f) as u32;
+        if b & 0x80 == 0 {
+            return Ok(value);
+        }
+    }
+    Err("variable length quantity too long")
+}
+
+/// Appends the variable length quantity of `value` to `bytes`
+pub fn write_vlq(bytes: &mut Vec<u8>, value: u32) {
+    let mut groups = vec![(value & 0x7f) as u8];
+    let mut rest = value >> 7;
+    while rest > 0 {
+        groups.push((rest & 0x7f) as u8 | 0x80);
+        rest >>= 7;
+    }
+    groups.reverse();
+    bytes.append(&mut groups);
+}
+
+/// Parses the content of a track chunk
+fn parse_track(bytes: &[u8]) -> Result<Vec<TrackEvent>, &'static str> {
+    let mut events = Vec::new();
+    let mut pos = 0;
+    let mut tick = 0u64;
+    let mut running_status: Option<u8> = None;
+    while pos < bytes.len() {
+        tick += read_vlq(bytes, &mut pos)? as u64;
+        let mut status = match bytes.get(pos) {
+            Some(&status) => status,
+            None => return Err("truncated MIDI file"),
+        };
+        if status & 0x80 != 0 {
+            pos += 1;
+        } else {
+            status = match running_status {
+                Some(status) => status,
+                None => return Err("MIDI data byte without status"),
+            };
+        }
+        let kind = match status {
+            0xff => {
+                running_status = None;
+                let meta_type = read(bytes, &mut pos, 1)?[0];
+                let len = read_vlq(bytes, &mut pos)? as usize;
+                let data = read(bytes, &mut pos, len)?;
+                match meta_type {
+                    0x51 if len == 3 => {
+                        let mut data_pos = 0;
+                        EventKind::Tempo(read_number(data, &mut data_pos, 3)?)
+                    },
+                    0x2f => {
+                        events.push(TrackEvent { tick, kind: EventKind::Other });
+                        break;
+                    },
+                    _ => EventKind::Other,
+                }
+            },
+            0xf0 | 0xf7 => {
+                running_status = None;
+                let len = read_vlq(bytes, &mut pos)? as usize;
+                read(bytes, &mut pos, len)?;
+                EventKind::Other
+            },
+            0xf1..=0xfe => return Err("unexpected MIDI system message"),
+            _ => {
+                running_status = Some(status);
+                let channel = status & 0x0f;
+                let nb_data = if status & 0xe0 == 0xc0 { 1 } else { 2 };
+                let data = read(bytes, &mut pos, nb_data)?;
+                if data.iter().any(|byte| byte & 0x80 != 0) {
+                    return Err("MIDI data byte out of range");
+                }
+                match status & 0xf0 {
+                    0x90 if data[1] > 0 => EventKind::NoteOn {
+                        channel, key: data[0], velocity: data[1]
+                    },
+                    0x80 | 0x90 => EventKind::NoteOff {
+                        channel, key: data[0], velocity: data[1]
+                    },
+                    _ => EventKind::Other,
+                }
+            },
+        };
+        events.push(TrackEvent { tick, kind });
+    }
+    Ok(events)
+}
+
+impl Smf {
+
+    /// Parses the bytes of a Standard MIDI File
+    pub fn parse(bytes: &[u8]) -> Result<Smf, &'static str> {
+        let mut pos = 0;
+        if read(bytes, &mut pos, 4)? != b"MThd" {
+            return Err("not a MIDI file");
+        }
+        let header_len = read_number(bytes, &mut pos, 4)? as usize;
+        if header_len < 6 {
+            return Err("invalid MIDI header");
+        }
+        let header_end = pos + header_len;
+        let format = read_number(bytes, &mut pos, 2)? as u16;
+        let nb_tracks = read_number(bytes, &mut pos, 2)? as usize;
+        let division = read_number(bytes, &mut pos, 2)? as u16;
+        let division = if division & 0x8000 == 0 {
+            Division::TicksPerQuarter(division)
+        } else {
+            Division::Smpte {
+                fps: (-((division >> 8) as u8 as i8)) as u8,
+                ticks_per_frame: (division & 0xff) as u8,
+            }
+        };
+        let invalid_division = match division {
+            Division::TicksPerQuarter(tpq) => tpq == 0,
+            Division::Smpte { fps, ticks_per_frame } => fps == 0 || ticks_per_frame == 0,
+        };
+        if invalid_division {
+            return Err("invalid MIDI time division");
+        }
+        pos = header_end;
+
+        let mut tracks = Vec::with_capacity(nb_tracks);
+        while tracks.len() < nb_tracks {
+            let chunk_type = read(bytes, &mut pos, 4)?;
+            let len = read_number(bytes, &mut pos, 4)? as usize;
+            let data = read(bytes, &mut pos, len)?;
+            if chunk_type == b"MTrk" { // other chunks are ignored
+                tracks.push(parse_track(data)?);
+            }
+        }
+        Ok(Smf { format, division, tracks })
+    }
+
+    /// Loads the Standard MIDI File at `path`
+    pub fn load(path: &str) -> Result<Smf, &'static str> {
+        match fs::read(path) {
+            Ok(bytes) => Smf::parse(&bytes),
+            Err(_) => Err("could not read MIDI file"),
+        }
+    }
+
+    /// Returns the tempo map of the file, built from the tempo changes of
+    /// every track
+    pub fn tempo_map(&self) -> TempoMap {
+        let mut tempos: Vec<(u64, u32)> = self
+            .tracks
+            .iter()
+            .flatten()
+            .filter_map(|e| match e.kind {
+                EventKind::Tempo(tempo) => Some((e.tick, tempo)),
+                _ => None,
+            })
+            .collect();
+        tempos.sort_by_key(|&(tick, _)| tick);
+        TempoMap::new(self.division, &tempos)
+    }
+
+    /// Returns the notes of the file, sorted by start time. Only notes of
+    /// `track` and `channel` (from 0 to 15) are returned if they are given.
+    pub fn notes(
+        &self,
+        track: Option<usize>,
+        channel: Option<u8>
+    ) -> Result<Vec<Note>, &'static str> {
+        let tracks: Vec<&Vec<TrackEvent>> = match track {
+            Some(t) => match self.tracks.get(t) {
+                Some(events) => vec![events],
+                None => return Err("MIDI track index out of range"),
+            },
+            None => self.tracks.iter().collect(),
+        };
+        let tempo_map = self.tempo_map();
+        let mut notes = Vec::new();
+        for events in tracks {
+            // start tick and velocity of held notes, by channel and key
+            let mut held: Vec<Vec<(u64, u8)>> = vec![Vec::new(); 16 * 128];
+            for event in events {
+                match event.kind {
+                    EventKind::NoteOn { channel: c, key, velocity } => {
+                        held[c as usize * 128 + key as usize]
+                            .push((event.tick, velocity));
+                    },
+                    EventKind::NoteOff { channel: c, key, .. } => {
+                        let starts = &mut held[c as usize * 128 + key as usize];
+                        if starts.is_empty() {
+                            continue;
+                        }
+                        let (start, velocity) = starts.remove(0);
+                        if channel.is_none_or(|ch| ch == c) {
+                            notes.push(Note {
+                                start: tempo_map.seconds(start),
+                                end: tempo_map.seconds(event.tick),
+                                channel: c,
+                                key,
+                                velocity,
+                            });
+                        }
+                    },
+                    _ => {},
+                }
+            }
+        }
+        notes.sort_by(|a, b| a.start.total_cmp(&b.start));
+        Ok(notes)
+    }
+
+    /// Returns the bytes of the file. Events of kind `Other` are not written
+    /// and an end of track event is added to each track.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = b"MThd".to_vec();
+        bytes.extend_from_slice(&6u32.to_be_bytes());
+        bytes.extend_from_slice(&self.format.to_be_bytes());
+        bytes.extend_from_slice(&(self.tracks.len() as u16).to_be_bytes());
+        let division = match self.division {
+            Division::TicksPerQuarter(ticks) => ticks,
+            Division::Smpte { fps, ticks_per_frame } =>
+                (((-(fps as i8)) as u8 as u16) << 8) | ticks_per_frame as u16,
+        };
+        bytes.extend_from_slice(&division.to_be_bytes());
+
+        for events in &self.tracks {
+            let mut data = Vec::new();
+            let mut tick = 0;
+            for event in events {
+                let (status, content) = match event.kind {
+                    EventKind::NoteOn { channel, key, velocity } =>
+                        (0x90 | channel, vec![key, velocity]),
+                    EventKind::NoteOff { channel, key, velocity } =>
+                        (0x80 | channel, vec![key, velocity]),
+                    EventKind::Tempo(tempo) =>
+                        (0xff, vec![0x51, 3, (tempo >> 16) as u8, (tempo >> 8) as u8, tempo as u8]),
+                    EventKind::Other => continue,
+                };
+                write_vlq(&mut data, (event.tick.max(tick) - tick) as u32);
+                tick = event.tick.max(tick);
+                data.push(status);
+                data.extend_from_slice(&content);
+            }
+            data.extend_from_slice(&[0, 0xff, 0x2f, 0]); // end of track
+            bytes.extend_from_slice(b"MTrk");
+            bytes.extend_from_slice(&(data.len() as u32).to_be_bytes());
+            bytes.append(&mut data);
+        }
+        bytes
+    }
+
+    /// Saves the file at `path`
+    pub fn save(&self, path: &str) -> Result<(), &'static str> {
+        match fs::write(path, self.to_bytes()) {
+            Ok(()) => Ok(()),
+            Err(_) => Err("could not write MIDI file"),
+        }
+    }
+}
+
+impl TempoMap {
+
+    /// Returns the tempo map of the given division and tempo changes, which
+    /// must be sorted by tick
+    pub fn new(division: Division, tempos: &[(u64, u32)]) -> TempoMap {
+        let mut changes = vec![(0, 0.0, DEFAULT_TEMPO)];
+        for &(tick, tempo) in tempos {
+            let seconds = TempoMap::seconds_from(division, changes.last().unwrap(), tick);
+            if tick == changes.last().unwrap().0 {
+                changes.pop();
+            }
+            changes.push((tick, seconds, tempo));
+        }
+        TempoMap { division, changes }
+    }
+
+    /// Returns the number of seconds at `tick`, given the last tempo change
+    /// before it
+    fn seconds_from(division: Division, change: &(u64, f64, u32), tick: u64) -> f64 {
+        let (change_tick, change_seconds, tempo) = *change;
+        let ticks = tick.saturating_sub(change_tick) as f64;
+        match division {
+            Division::TicksPerQuarter(tpq) =>
+                change_seconds + ticks * tempo as f64 / 1e6 / tpq as f64,
+            Division::Smpte { fps, ticks_per_frame } =>
+                change_seconds + ticks / (fps as f64 * ticks_per_frame as f64),
+        }
+    }
+
+    /// Returns the time in seconds of `tick`
+    pub fn seconds(&self, tick: u64) -> f64 {
+        let i = self.changes.partition_point(|&(t, _, _)| t <= tick);
+        TempoMap::seconds_from(self.division, &self.changes[i.max(1) - 1], tick)
+    }
+
+    /// Returns the tick at `seconds`, rounded to the nearest one
+    pub fn tick(&self, seconds: f64) -> u64 {
+        let i = self.changes.partition_point(|&(_, s, _)| s <= seconds);
+        let (tick, change_seconds, tempo) = self.changes[i.max(1) - 1];
+        let elapsed = (seconds - change_seconds).max(0.0);
+        let ticks = match self.division {
+            Division::TicksPerQuarter(tpq) =>
+                elapsed * 1e6 * tpq as f64 / tempo as f64,
+            Division::Smpte { fps, ticks_per_frame } =>
+                elapsed * fps as f64 * ticks_per_frame as f64,
+        };
+        tick + ticks.round() as u64
+    }
+}
+
+#[cfg(test)]
+mod smf_tests {
+    use super::*;
+
+    /// Returns a file of the given format and division made of the given
+    /// track chunk contents
+    fn file(format: u16, division: u16, tracks: &[&[u8]]) -> Vec<u8> {
+        let mut bytes = b"MThd\0\0\0\x06".to_vec();
+        bytes.extend_from_slice(&format.to_be_bytes());
+        bytes.extend_from_slice(&(tracks.len() as u16).to_be_bytes());
+        bytes.extend_from_slice(&division.to_be_bytes());
+        for track in tracks {
+            bytes.extend_from_slice(b"MTrk");
+            bytes.extend_from_slice(&(track.len() as u32).to_be_bytes());
+            bytes.extend_from_slice(track);
+        }
+        bytes
+    }
+
+    #[test]
+    fn vlq_round_trip() {
+        for value in [0, 0x40, 0x7f, 0x80, 0x2000, 0x3fff, 0x4000, 0x0fff_ffff] {
+            let mut bytes = Vec::new();
+            write_vlq(&mut bytes, value);
+            let mut pos = 0;
+            assert_eq!(value, read_vlq(&bytes, &mut pos).unwrap());
+            assert_eq!(bytes.len(), pos);
+        }
+        let mut bytes = Vec::new();
+        write_vlq(&mut bytes, 0x2000);
+        assert_eq!(vec![0xc0, 0x00], bytes);
+    }
+
+    #[test]
+    fn parse_notes_with_running_status() {
+        // A4 for a quarter note then, with running status, B4 released with a
+        // zero velocity note on
+        let track: &[u8] = &[
+            0x00, 0x90, 69, 100,
+            0x60, 0x80, 69, 0,
+            0x00, 0x91, 71, 90,
+            0x60, 71, 0,
+            0x00, 0xff, 0x2f, 0x00,
+        ];
+        let smf = Smf::parse(&file(0, 96, &[track])).unwrap();
+        assert_eq!(Division::TicksPerQuarter(96), smf.division);
+        let notes = smf.notes(None, None).unwrap();
+        assert_eq!(2, notes.len());
+        assert_eq!((0.0, 0.5, 69, 100), (notes[0].start, notes[0].end, notes[0].key, notes[0].velocity));
+        assert_eq!((0.5, 1.0, 71, 1), (notes[1].start, notes[1].end, notes[1].key, notes[1].channel));
+        assert_eq!(1, smf.notes(None, Some(0)).unwrap().len());
+        assert!(smf.notes(Some(1), None).is_err());
+    }
+
+    #[test]
+    fn tempo_changes_of_other_tracks_apply() {
+        // format 1: tempo track at 60 bpm then 240 bpm after one quarter note
+        let tempo_track: &[u8] = &[
+            0x00, 0xff, 0x51, 0x03, 0x0f, 0x42, 0x40,
+            0x60, 0xff, 0x51, 0x03, 0x03, 0xd0, 0x90,
+            0x00, 0xff, 0x2f, 0x00,
+        ];
+        let note_track: &[u8] = &[
+            0x00, 0xf0, 0x02, 0x01, 0xf7, // sysex
+            0x81, 0x40, 0x90, 60, 64,     // two quarter notes later
+            0x60, 0x80, 60, 64,
+            0x00, 0xff, 0x2f, 0x00,
+        ];
+        let smf = Smf::parse(&file(1, 96, &[tempo_track, note_track])).unwrap();
+        let notes = smf.notes(Some(1), None).unwrap();
+        assert_eq!(1, notes.len());
+        assert_eq!(1.25, notes[0].start);
+        assert_eq!(1.5, notes[0].end);
+        let map = smf.tempo_map();
+        assert_eq!(192, map.tick(1.25));
+        assert_eq!(48, map.tick(0.5));
+    }
+
+    #[test]
+    fn smpte_division() {
+        let track: &[u8] = &[0x00, 0x90, 60, 64, 0x50, 0x80, 60, 64];
+        // 25 frames per second, 40 ticks per frame: 1000 ticks per second
+        let smf = Smf::parse(&file(0, 0xe728, &[track])).unwrap();
+        assert_eq!(Division::Smpte { fps: 25, ticks_per_frame: 40 }, smf.division);
+        assert_eq!(0.08, smf.notes(None, None).unwrap()[0].end);
+    }
+
+    #[test]
+    fn to_bytes_then_parse_gives_same_notes() {
+        let smf = Smf {
+            format: 0,
+            division: Division::TicksPerQuarter(480),
+            tracks: vec![vec![
+                TrackEvent { tick: 0, kind: EventKind::Tempo(600_000) },
+                TrackEvent { tick: 10, kind: EventKind::NoteOn { channel: 2, key: 64, velocity: 80 } },
+                TrackEvent { tick: 500, kind: EventKind::NoteOff { channel: 2, key: 64, velocity: 0 } },
+            ]],
+        };
+        let parsed = Smf::parse(&smf.to_bytes()).unwrap();
+        assert_eq!(smf.notes(None, None), parsed.notes(None, None));
+        assert_eq!(4, parsed.tracks[0].len()); // with end of track
+    }
+
+    #[test]
+    fn parse_invalid_files_fails() {
+        assert!(Smf::parse(b"RIFF").is_err());
+        assert!(Smf::parse(&file(0, 96, &[&[0x00, 69, 100]])).is_err());
+        let mut truncated = file(0, 96, &[&[0x00, 0x90, 69, 100]]);
+        truncated.pop();
+        assert!(Smf::parse(&truncated).is_err());
+
+        // keys and velocities are 7 bits
+        let end = [0x00, 0xff, 0x2f, 0x00];
+        for data in [[0x9f, 0xff, 100], [0x9f, 69, 0x80], [0x8f, 0x80, 0]] {
+            let track = [&[0x00][..], &data, &end].concat();
+            assert!(Smf::parse(&file(0, 96, &[&track])).is_err(), "{:?}", data);
+        }
+    }
+}
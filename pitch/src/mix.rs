@@ -0,0 +1,166 @@
+use crate::loudness::{self, Target};
+
+/// Last stage of a transposer: mixes the processed (wet) signal with the
+/// original (dry) one, applies an output gain and optionally a loudness
+/// normalization, then a soft limiter, which keeps overshoots of the
+/// processing under a ceiling.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct OutputStage {
+    pub mix: f32,                  // 0.0 for the dry signal only, 1.0 for the wet one
+    pub gain: f32,                 // output gain, in dB
+    pub limit: Option<f32>,        // ceiling of the limiter in dBFS, None if off
+    pub normalize: Option<Target>, // loudness normalization, None if off
+}
+
+/// Returns the amplitude of a level of `db` dB
+pub fn db_to_amplitude(db: f32) -> f32 {
+    10.0f32.powf(db / 20.0)
+}
+
+/// Returns `sample` limited under `ceiling`: samples under half the ceiling
+/// are unchanged, louder ones are smoothly compressed towards it
+pub fn soft_limit(sample: f32, ceiling: f32) -> f32 {
+    let knee = ceiling / 2.0;
+    let magnitude = sample.abs();
+    if magnitude <= knee {
+        return sample;
+    }
+    let range = ceiling - knee;
+    let limited = knee + range * ((magnitude - knee) / range).tanh();
+    limited.copysign(sample)
+}
+
+impl OutputStage {
+
+    /// Returns a stage letting the wet signal through unchanged
+    pub fn wet() -> OutputStage {
+        OutputStage { mix: 1.0, gain: 0.0, limit: None, normalize: None }
+    }
+
+    /// Returns the output of the stage for the `dry` and `wet` signals, of
+    /// amplitudes between `-full_scale` and `full_scale`, without loudness
+    /// normalization. The output has the length of `wet`, a missing dry
+    /// sample being silence.
+    pub fn apply(&self, dry: &[f32], wet: &[f32], full_scale: f32) -> Vec<f32> {
+        let mut output = self.mix(dry, wet);
+        self.limit(&mut output, full_scale);
+        output
+    }
+
+    /// Returns the output of the stage for the `dry` and `wet` signals of
+    /// `channels` interleaved channels sampled at `samp_rate` Hz, as `apply()`
+    /// does: the mix is normalized to the loudness target of the stage if any
+    /// before the limiter, which keeps its ceiling
+    pub fn process(
+        &self,
+        dry: &[f32],
+        wet: &[f32],
+        channels: usize,
+        samp_rate: f32,
+        full_scale: f32
+    ) -> Vec<f32> {
+        let mixed = self.mix(dry, wet);
+        let mut output = self.normalize(&mixed, channels, samp_rate, full_scale);
+        self.limit(&mut output, full_scale);
+        output
+    }
+
+    /// Returns `signal`, a mix of the stage for `channels` interleaved
+    /// channels sampled at `samp_rate` Hz, normalized to the loudness target
+    /// of the stage if any (see `loudness::normalize()`)
+    pub fn normalize(
+        &self,
+        signal: &[f32],
+        channels: usize,
+        samp_rate: f32,
+        full_scale: f32
+    ) -> Vec<f32> {
+        match self.normalize {
+            Some(target) => {
+                loudness::normalize(signal, channels, samp_rate, full_scale, target)
+            },
+            None => signal.to_vec(),
+        }
+    }
+
+    /// Returns the `dry` and `wet` signals mixed, with the output gain
+    fn mix(&self, dry: &[f32], wet: &[f32]) -> Vec<f32> {
+        let gain = db_to_amplitude(self.gain);
+        wet.iter()
+            .enumerate()
+            .map(|(n, w)| {
+                let d = dry.get(n).copied().unwrap_or(0.0);
+                gain * (self.mix * w + (1.0 - self.mix) * d)
+            })
+            .collect()
+    }
+
+    /// Passes `signal` through the limiter of the stage, if any
+    fn limit(&self, signal: &mut [f32], full_scale: f32) {
+        if let Some(limit) = self.limit {
+            let ceiling = full_scale * db_to_amplitude(limit);
+            for sample in signal.iter_mut() {
+                *sample = soft_limit(*sample, ceiling);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod mix_tests {
+    use super::*;
+
+    #[test]
+    fn mix_between_dry_and_wet() {
+        let (dry, wet) = ([1.0, 2.0, 3.0], [3.0, 2.0, 1.0, 4.0]);
+        let stage = |mix| OutputStage { mix, ..OutputStage::wet() };
+        assert_eq!(wet.to_vec(), stage(1.0).apply(&dry, &wet, 1.0));
+        assert_eq!(vec![1.0, 2.0, 3.0, 0.0], stage(0.0).apply(&dry, &wet, 1.0));
+        assert_eq!(vec![2.0, 2.0, 2.0, 2.0], stage(0.5).apply(&dry, &wet, 1.0));
+    }
+
+    #[test]
+    fn output_gain_in_db() {
+        let stage = OutputStage { gain: -20.0, ..OutputStage::wet() };
+        let output = stage.apply(&[], &[0.5, -1.0], 1.0);
+        assert!((output[0] - 0.05).abs() < 1e-6);
+        assert!((output[1] + 0.1).abs() < 1e-6);
+    }
+
+    #[test]
+    fn soft_limit_keeps_quiet_samples_and_bounds_loud_ones() {
+        assert_eq!(0.4, soft_limit(0.4, 1.0));
+        assert_eq!(-0.5, soft_limit(-0.5, 1.0));
+        for sample in [0.6, 1.0, 2.0, 100.0] {
+            let limited = soft_limit(sample, 1.0);
+            assert!(limited > 0.5 && limited <= 1.0);
+            assert_eq!(-limited, soft_limit(-sample, 1.0));
+        }
+        // continuous and increasing
+        assert!((soft_limit(0.501, 1.0) - 0.501).abs() < 1e-4);
+        assert!(soft_limit(0.9, 1.0) < soft_limit(1.2, 1.0));
+    }
+
+    #[test]
+    fn limiter_ceiling_in_dbfs() {
+        let stage = OutputStage { gain: 12.0, limit: Some(-6.0), ..OutputStage::wet() };
+        let ceiling = 32768.0 * db_to_amplitude(-6.0);
+        for sample in stage.apply(&[], &[30000.0, -20000.0, 100.0], 32768.0) {
+            assert!(sample.abs() <= ceiling);
+        }
+    }
+
+    #[test]
+    fn limiter_after_normalization() {
+        let stage = OutputStage {
+            limit: Some(-6.0),
+            normalize: Some(Target::Peak(-1.0)),
+            ..OutputStage::wet()
+        };
+        let wet: Vec<f32> = (0..8000).map(|n| 0.1 * (0.3 * n as f32).sin()).collect();
+        let output = stage.process(&[], &wet, 1, 8000.0, 1.0);
+        let peak = output.iter().fold(0.0f32, |max, s| max.max(s.abs()));
+        assert!(peak <= db_to_amplitude(-6.0), "{}", peak);
+        assert!(peak > db_to_amplitude(-7.0), "{}", peak);
+    }
+}
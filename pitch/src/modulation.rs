@@ -0,0 +1,64 @@
+use std::f64::consts::PI;
+
+/// A synthetic vibrato: a sine modulation of the pitch, starting after
+/// `delay` seconds and reaching its full depth after `fade_in` more seconds.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Vibrato {
+    pub rate: f32,    // modulations per second, in Hz
+    pub depth: f32,   // largest deviation from the original pitch, in cents
+    pub delay: f32,   // seconds before the modulation starts
+    pub fade_in: f32, // seconds for the depth to go from 0 to `depth`
+}
+
+impl Vibrato {
+
+    /// Returns the depth, in cents, of the modulation at `time` seconds
+    pub fn depth_at(&self, time: f64) -> f32 {
+        let elapsed = time - self.delay as f64;
+        if elapsed <= 0.0 {
+            0.0
+        } else if elapsed < self.fade_in as f64 {
+            self.depth * (elapsed / self.fade_in as f64) as f32
+        } else {
+            self.depth
+        }
+    }
+
+    /// Returns the pitch deviation, in cents, at `time` seconds. The
+    /// modulation starts at the end of the delay, in its rising phase, so
+    /// that the pitch does not jump.
+    pub fn cents_at(&self, time: f64) -> f32 {
+        let elapsed = time - self.delay as f64;
+        let sine = (2.0 * PI * self.rate as f64 * elapsed).sin() as f32;
+        self.depth_at(time) * sine
+    }
+
+    /// Returns the scale factor of the pitch at `time` seconds
+    pub fn ratio_at(&self, time: f64) -> f32 {
+        2.0f32.powf(self.cents_at(time) / 1200.0)
+    }
+}
+
+#[cfg(test)]
+mod modulation_tests {
+    use super::*;
+
+    #[test]
+    fn vibrato_starts_after_delay_and_fades_in() {
+        let vibrato = Vibrato { rate: 5.0, depth: 40.0, delay: 1.0, fade_in: 2.0 };
+        assert_eq!(0.0, vibrato.depth_at(0.5));
+        assert_eq!(0.0, vibrato.depth_at(1.0));
+        assert_eq!(20.0, vibrato.depth_at(2.0));
+        assert_eq!(40.0, vibrato.depth_at(3.5));
+        assert_eq!(1.0, vibrato.ratio_at(0.5));
+        // a quarter of period after the end of the fade-in
+        assert!((vibrato.cents_at(3.05) - 40.0).abs() < 1e-3);
+        assert!((vibrato.ratio_at(3.15) - 2.0f32.powf(-40.0 / 1200.0)).abs() < 1e-5);
+    }
+
+    #[test]
+    fn vibrato_without_fade_in() {
+        let vibrato = Vibrato { rate: 4.0, depth: 100.0, delay: 0.0, fade_in: 0.0 };
+        assert!((vibrato.ratio_at(1.0 / 16.0) - 2.0f32.powf(1.0 / 12.0)).abs() < 1e-5);
+    }
+}
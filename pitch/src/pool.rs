@@ -0,0 +1,159 @@
+use rayon::prelude::*;
+use std::sync::{Arc, RwLock};
+use std::thread;
+
+/// Worker threads on which the frames of the phase vocoder are analyzed and
+/// synthesized. The threads are started once and reused by every call.
+pub struct WorkerPool {
+    pool: Option<rayon::ThreadPool>, // None to work on the calling thread
+}
+
+/// Pool used by the transposers, started on first use
+static GLOBAL: RwLock<Option<Arc<WorkerPool>>> = RwLock::new(None);
+
+impl WorkerPool {
+
+    /// Returns a pool of `threads` workers, or of one worker per processor
+    /// if `threads` is 0. A pool of 1 worker works on the calling thread.
+    pub fn new(threads: usize) -> Result<WorkerPool, &'static str> {
+        let threads = match threads {
+            0 => thread::available_parallelism().map_or(1, usize::from),
+            n => n,
+        };
+        if threads == 1 {
+            return Ok(WorkerPool::single_threaded());
+        }
+        rayon::ThreadPoolBuilder::new()
+            .num_threads(threads)
+            .thread_name(|i| format!("pitch-worker-{}", i))
+            .build()
+            .map(|pool| WorkerPool { pool: Some(pool) })
+            .map_err(|_| "could not start the worker threads")
+    }
+
+    /// Returns a pool working on the calling thread, without any thread
+    pub fn single_threaded() -> WorkerPool {
+        WorkerPool { pool: None }
+    }
+
+    /// Returns the number of threads working at the same time
+    pub fn threads(&self) -> usize {
+        self.pool.as_ref().map_or(1, rayon::ThreadPool::current_num_threads)
+    }
+
+    /// Returns `f(state, i, item)` for each `item` of `items` at index `i`,
+    /// in the order of `items`. The items are borrowed by the workers, each
+    /// one processing a contiguous part of them with its own `state` built by
+    /// `init`, e.g a FFT planner.
+    pub fn map<T, R, S, I, F>(&self, items: &[T], init: I, f: F) -> Vec<R>
+    where
+        T: Sync,
+        R: Send,
+        I: Fn() -> S + Sync,
+        F: Fn(&mut S, usize, &T) -> R + Sync
+    {
+        let pool = match &self.pool {
+            Some(pool) if items.len() > 1 => pool,
+            _ => {
+                let mut state = init();
+                return items
+                    .iter()
+                    .enumerate()
+                    .map(|(i, t)| f(&mut state, i, t))
+                    .collect();
+            },
+        };
+        let chunk_size = items.len().div_ceil(pool.current_num_threads());
+        pool.install(|| {
+            items
+                .par_chunks(chunk_size)
+                .enumerate()
+                .flat_map_iter(|(c, chunk)| {
+                    let mut state = init();
+                    let f = &f;
+                    chunk
+                        .iter()
+                        .enumerate()
+                        .map(move |(i, t)| f(&mut state, c * chunk_size + i, t))
+                })
+                .collect()
+        })
+    }
+}
+
+/// Returns the pool used by the transposers, of one worker per processor
+/// unless set by `set_threads()`
+pub fn global() -> Arc<WorkerPool> {
+    if let Some(pool) = GLOBAL.read().unwrap_or_else(|e| e.into_inner()).as_ref() {
+        return pool.clone();
+    }
+    let mut global = GLOBAL.write().unwrap_or_else(|e| e.into_inner());
+    global
+        .get_or_insert_with(|| {
+            Arc::new(WorkerPool::new(0).unwrap_or_else(|_| WorkerPool::single_threaded()))
+        })
+        .clone()
+}
+
+/// Replaces the pool used by the transposers by a pool of `threads` workers
+/// (see `WorkerPool::new()`). Calls in progress finish on the previous pool.
+pub fn set_threads(threads: usize) -> Result<(), &'static str> {
+    let pool = Arc::new(WorkerPool::new(threads)?);
+    *GLOBAL.write().unwrap_or_else(|e| e.into_inner()) = Some(pool);
+    Ok(())
+}
+
+#[cfg(test)]
+mod pool_tests {
+    use super::*;
+
+    #[test]
+    fn map_keeps_order() {
+        let items: Vec<usize> = (0..103).collect();
+        for threads in [1, 2, 3, 8] {
+            let pool = WorkerPool::new(threads).unwrap();
+            assert_eq!(threads, pool.threads());
+            let mapped = pool.map(&items, || (), |_, i, item| (i, 2 * item));
+            let expected: Vec<(usize, usize)> =
+                items.iter().map(|i| (*i, 2 * i)).collect();
+            assert_eq!(expected, mapped);
+        }
+        let pool = WorkerPool::new(4).unwrap();
+        assert!(pool.map(&[] as &[f32], || (), |_, _, x| *x).is_empty());
+        assert_eq!(vec![3.0], pool.map(&[1.5], || 2.0, |s, _, x| *s * x));
+    }
+
+    #[test]
+    fn states_and_threads() {
+        let items = vec![0; 64];
+        let caller = thread::current().id();
+        let single = WorkerPool::single_threaded();
+        let ids = single.map(&items, || (), |_, _, _| thread::current().id());
+        assert!(ids.iter().all(|id| *id == caller));
+
+        // one state per worker, each built once by part of the items
+        let pool = WorkerPool::new(4).unwrap();
+        let counts = pool.map(&items, || 0, |count, _, _| { *count += 1; *count });
+        assert_eq!(64, counts.len());
+        assert_eq!(4, counts.iter().filter(|c| **c == 1).count());
+        let names =
+            pool.map(&items, || (), |_, _, _| thread::current().name().map(String::from));
+        assert!(names.iter().all(|n| n.as_deref().unwrap().starts_with("pitch-worker-")));
+    }
+
+    #[test]
+    fn pool_is_reused() {
+        assert!(Arc::ptr_eq(&global(), &global()));
+
+        // the same 3 workers serve every call, the global pool being left as is
+        let pool = WorkerPool::new(3).unwrap();
+        let items = vec![0; 30];
+        let caller = thread::current().id();
+        let mut ids = std::collections::HashSet::new();
+        for _ in 0..20 {
+            ids.extend(pool.map(&items, || (), |_, _, _| thread::current().id()));
+        }
+        assert!(!ids.contains(&caller));
+        assert!(!ids.is_empty() && ids.len() <= 3, "{:?}", ids);
+    }
+}
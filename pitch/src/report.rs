@@ -0,0 +1,395 @@
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::Path;
+use clap::ValueEnum;
+use serde::{Deserialize, Serialize};
+use crate::accuracy::PitchReference;
+use crate::align::LengthPolicy;
+use crate::analysis;
+use crate::loudness::Loudness;
+use crate::metrics::{self, Metric};
+use crate::midi;
+
+/// How a signal is compared to its oracle
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct CheckOptions {
+    pub fsize: usize,          // size of the frames of spectrograms and pitch tracks
+    pub hopa: usize,           // shift between the frames
+    pub length: LengthPolicy,  // how signals of different lengths are compared
+    pub max_lag: Option<f32>,  // largest delay searched in seconds, None if not aligned
+}
+
+impl Default for CheckOptions {
+    fn default() -> CheckOptions {
+        CheckOptions {
+            fsize: metrics::FRAME_SIZE,
+            hopa: metrics::HOP_SIZE,
+            length: LengthPolicy::Trim,
+            max_lag: None,
+        }
+    }
+}
+
+/// Pitch check of a case: the largest mean deviation, in cents, of the pitch
+/// of the signal from a note, from the oracle shifted by a number of semi
+/// tons, or from the oracle if neither is given
+#[derive(Clone, Debug, Deserialize, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct PitchCheck {
+    pub threshold: f32,
+    pub expected_note: Option<String>,
+    pub expected_shift: Option<f32>,
+}
+
+impl PitchCheck {
+
+    /// Returns the pitch the signal is expected to have
+    pub fn reference(&self) -> Result<PitchReference, &'static str> {
+        match (&self.expected_note, self.expected_shift) {
+            (Some(_), Some(_)) => Err("Both an expected note and an expected shift"),
+            (Some(name), None) => match analysis::note_number(name) {
+                Some(key) => {
+                    let frequency = midi::note_frequency(key);
+                    Ok(PitchReference::Note(frequency))
+                },
+                None => Err("Expected a note such as A4, C#3 or Bb2"),
+            },
+            (None, shift) => Ok(PitchReference::Shift(shift.unwrap_or(0.0))),
+        }
+    }
+}
+
+/// Case of a manifest: a signal checked against an oracle, with thresholds
+/// of metrics (named as by `--metric`) and of the pitch
+#[derive(Clone, Debug, Deserialize, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct Case {
+    pub name: Option<String>,
+    pub signal: String,
+    pub oracle: Option<String>,
+    #[serde(default)]
+    pub thresholds: BTreeMap<String, f32>,
+    pub pitch: Option<PitchCheck>,
+    #[serde(default)]
+    pub loudness: bool,
+    pub fsize: Option<usize>,
+    pub hopa: Option<usize>,
+    pub length: Option<String>,
+    #[serde(default)]
+    pub align: bool,
+    pub max_lag: Option<f32>,
+}
+
+/// Report of the value of a metric, with its threshold and verdict if it
+/// has one. Infinite values are written as null in JSON.
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct MetricReport {
+    pub metric: String,
+    pub value: f32,
+    pub threshold: Option<f32>,
+    pub passed: Option<bool>,
+}
+
+/// Report of a pitch check
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct PitchReport {
+    pub mean_cents: f32,
+    pub max_cents: f32,
+    pub frames: usize,
+    pub threshold: f32,
+    pub passed: bool,
+}
+
+/// Report of a case: a case passes if it could be checked and all its
+/// thresholds are passed
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct CaseReport {
+    pub name: Option<String>,
+    pub signal: String,
+    pub oracle: Option<String>,
+    pub metrics: Vec<MetricReport>,
+    pub pitch: Option<PitchReport>,
+    pub loudness: BTreeMap<String, Loudness>, // of the signal and the oracle
+    pub error: Option<String>,
+    pub passed: bool,
+}
+
+/// Report of all the cases of a manifest
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct BatchReport {
+    pub passed: bool,
+    pub failed: usize,
+    pub cases: Vec<CaseReport>,
+}
+
+impl Case {
+
+    /// Returns a case checking the signal at `signal` against the oracle at
+    /// `oracle` with `options`, without any threshold
+    pub fn new(signal: &str, oracle: Option<&str>, options: CheckOptions) -> Case {
+        Case {
+            name: None,
+            signal: signal.to_string(),
+            oracle: oracle.map(str::to_string),
+            thresholds: BTreeMap::new(),
+            pitch: None,
+            loudness: false,
+            fsize: Some(options.fsize),
+            hopa: Some(options.hopa),
+            length: Some(options.length.to_possible_value().map_or(
+                String::from("trim"),
+                |v| v.get_name().to_string()
+            )),
+            align: options.max_lag.is_some(),
+            max_lag: options.max_lag,
+        }
+    }
+
+    /// Returns the options of the case, the default ones where it has none
+    pub fn options(&self) -> Result<CheckOptions, &'static str> {
+        let default = CheckOptions::default();
+        let length = match &self.length {
+            Some(name) => match LengthPolicy::from_str(name, false) {
+                Ok(length) => length,
+                Err(_) => return Err("Unknown length policy"),
+            },
+            None => default.length,
+        };
+        Ok(CheckOptions {
+            fsize: self.fsize.unwrap_or(default.fsize),
+            hopa: self.hopa.unwrap_or(default.hopa),
+            length,
+            max_lag: if self.align { Some(self.max_lag.unwrap_or(0.5)) } else { None },
+        })
+    }
+
+    /// Returns the case with its relative paths taken from the directory
+    /// `dir`
+    fn relative_to(mut self, dir: &Path) -> Case {
+        let resolve = |path: &str| dir.join(path).to_string_lossy().into_owned();
+        self.oracle = self.oracle.as_deref().map(resolve);
+        self.signal = resolve(&self.signal);
+        self
+    }
+
+    /// Runs the checks of the case. All the metrics are measured if there is
+    /// an oracle, those with a threshold giving a verdict.
+    pub fn run(&self) -> CaseReport {
+        let mut report = CaseReport {
+            name: self.name.clone(),
+            signal: self.signal.clone(),
+            oracle: self.oracle.clone(),
+            metrics: Vec::new(),
+            pitch: None,
+            loudness: BTreeMap::new(),
+            error: None,
+            passed: false,
+        };
+        if let Err(why) = self.check(&mut report) {
+            report.error = Some(why.to_string());
+            return report;
+        }
+        report.passed = report.metrics.iter().all(|m| m.passed != Some(false))
+            && report.pitch.as_ref().is_none_or(|p| p.passed);
+        report
+    }
+
+    /// Fills `report` with the results of the checks of the case
+    fn check(&self, report: &mut CaseReport) -> Result<(), &'static str> {
+        let options = self.options()?;
+        let mut thresholds = Vec::new();
+        for (name, threshold) in &self.thresholds {
+            match Metric::from_name(name) {
+                Some(metric) => thresholds.push((metric, *threshold)),
+                None => return Err("Unknown metric in thresholds"),
+            }
+        }
+        if let Some(oracle) = &self.oracle {
+            let all = Metric::value_variants();
+            let values = crate::measure_metrics(&self.signal, oracle, all, &options)?;
+            for (metric, value) in all.iter().zip(values) {
+                let threshold = thresholds
+                    .iter()
+                    .find(|(m, _)| m == metric)
+                    .map(|(_, threshold)| *threshold);
+                report.metrics.push(MetricReport {
+                    metric: metric.name(),
+                    value,
+                    threshold,
+                    passed: threshold.map(|t| metric.passes(value, t)),
+                });
+            }
+        } else if !thresholds.is_empty() {
+            return Err("No oracle signal to compare with");
+        }
+        if let Some(pitch) = &self.pitch {
+            let reference = pitch.reference()?;
+            let accuracy = crate::measure_pitch(
+                &self.signal,
+                self.oracle.as_deref(),
+                reference,
+                &options
+            )?;
+            report.pitch = Some(PitchReport {
+                mean_cents: accuracy.mean,
+                max_cents: accuracy.max,
+                frames: accuracy.frames,
+                threshold: pitch.threshold,
+                passed: accuracy.passes(pitch.threshold),
+            });
+        }
+        if self.loudness {
+            let signal = Some(&self.signal);
+            for (key, path) in [("signal", signal), ("oracle", self.oracle.as_ref())] {
+                if let Some(path) = path {
+                    let loudness = crate::measure_loudness(path)?;
+                    report.loudness.insert(key.to_string(), loudness);
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+/// List of cases, read from a TOML file of `[[case]]` tables or from a JSON
+/// file of a `case` array
+#[derive(Clone, Debug, Deserialize, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct Manifest {
+    #[serde(rename = "case", alias = "cases", default)]
+    pub cases: Vec<Case>,
+}
+
+impl Manifest {
+
+    /// Parses a manifest in JSON if `json` is true, in TOML otherwise
+    pub fn parse(text: &str, json: bool) -> Result<Manifest, &'static str> {
+        if json {
+            serde_json::from_str(text).map_err(|_| "Invalid JSON manifest")
+        } else {
+            toml::from_str(text).map_err(|_| "Invalid TOML manifest")
+        }
+    }
+
+    /// Loads the manifest at `path`, in JSON if it ends with `.json`, in TOML
+    /// otherwise. Relative paths of signals are relative to its directory.
+    pub fn load(path: &str) -> Result<Manifest, &'static str> {
+        let text = match fs::read_to_string(path) {
+            Ok(text) => text,
+            Err(_) => return Err("Could not read the manifest"),
+        };
+        let manifest = Manifest::parse(&text, path.ends_with(".json"))?;
+        let dir = Path::new(path).parent().unwrap_or(Path::new(""));
+        Ok(Manifest {
+            cases: manifest.cases.into_iter().map(|c| c.relative_to(dir)).collect(),
+        })
+    }
+
+    /// Runs all the cases of the manifest
+    pub fn run(&self) -> BatchReport {
+        let cases: Vec<CaseReport> = self.cases.iter().map(Case::run).collect();
+        let failed = cases.iter().filter(|c| !c.passed).count();
+        BatchReport { passed: failed == 0, failed, cases }
+    }
+}
+
+#[cfg(test)]
+mod report_tests {
+    use super::*;
+    use crate::generate::{self, Signal};
+
+    /// Writes a second of sine of frequency `frequency` sampled at 8 kHz to
+    /// the temporary file `name` and returns its path
+    fn write_sine(name: &str, frequency: f32) -> String {
+        let sine = Signal::Sine { frequency }.synthesize(8000.0, 1.0, 0.25).unwrap();
+        let path = std::env::temp_dir().join(name).to_string_lossy().into_owned();
+        generate::write_wav(&path, &sine, 8000, 16).unwrap();
+        path
+    }
+
+    #[test]
+    fn parse_toml_and_json_manifests() {
+        let toml = r#"
+            [[case]]
+            name = "shift"
+            signal = "out.wav"
+            oracle = "in.wav"
+            thresholds = { lsd = 3.0, si-sdr = 10.0 }
+            pitch = { threshold = 10.0, expected_shift = 2.0 }
+            align = true
+
+            [[case]]
+            signal = "note.wav"
+            pitch = { threshold = 5.0, expected_note = "A4" }
+        "#;
+        let manifest = Manifest::parse(toml, false).unwrap();
+        assert_eq!(2, manifest.cases.len());
+        let case = &manifest.cases[0];
+        assert_eq!(Some(3.0), case.thresholds.get("lsd").copied());
+        assert_eq!(Some(0.5), case.options().unwrap().max_lag);
+        assert_eq!(
+            PitchReference::Shift(2.0),
+            case.pitch.as_ref().unwrap().reference().unwrap()
+        );
+        let json = r#"{"case": [{"signal": "note.wav",
+            "pitch": {"threshold": 5.0, "expected_note": "A4"}}]}"#;
+        assert_eq!(manifest.cases[1], Manifest::parse(json, true).unwrap().cases[0]);
+        assert!(Manifest::parse("[[case]]\nsignal = 1", false).is_err());
+        assert!(Manifest::parse("[[case]]\nsignal = \"a\"\ntypo = 1", false).is_err());
+    }
+
+    #[test]
+    fn run_cases_with_verdicts() {
+        let a4 = write_sine("pitch_report_a4.wav", 440.0);
+        let b4 = write_sine("pitch_report_b4.wav", 493.88);
+        let mut good = Case::new(&b4, Some(&a4), CheckOptions::default());
+        good.thresholds.insert(String::from("spectral-convergence"), 2.0);
+        good.pitch = Some(PitchCheck {
+            threshold: 5.0,
+            expected_note: None,
+            expected_shift: Some(2.0),
+        });
+        let report = good.run();
+        assert!(report.passed, "{:?}", report);
+        assert_eq!(Metric::value_variants().len(), report.metrics.len());
+        assert_eq!(1, report.metrics.iter().filter(|m| m.passed.is_some()).count());
+        assert!(report.pitch.unwrap().mean_cents < 5.0);
+
+        let mut bad = good.clone();
+        bad.pitch.as_mut().unwrap().expected_shift = Some(1.0);
+        let options = CheckOptions::default();
+        let mut missing = Case::new("no_such_file.wav", Some(&a4), options);
+        missing.thresholds.insert(String::from("lsd"), 1.0);
+        let batch = Manifest { cases: vec![good, bad, missing] }.run();
+        assert!(!batch.passed);
+        assert_eq!(2, batch.failed);
+        assert!(batch.cases[2].error.is_some());
+        let json = serde_json::to_string(&batch).unwrap();
+        assert!(json.contains("\"metric\":\"si-sdr\""));
+    }
+
+    #[test]
+    fn unsupported_bit_depth_fails() {
+        let a4 = write_sine("pitch_report_8bit_a4.wav", 440.0);
+        let path = std::env::temp_dir().join("pitch_report_8bit.wav");
+        let header = wav::Header::new(wav::header::WAV_FORMAT_PCM, 1, 8000, 8);
+        let mut file = fs::File::create(&path).unwrap();
+        wav::write(header, &wav::BitDepth::Eight(vec![128; 8000]), &mut file).unwrap();
+        let eight_bit = path.to_string_lossy().into_owned();
+
+        let mut case = Case::new(&eight_bit, Some(&a4), CheckOptions::default());
+        case.thresholds.insert(String::from("lsd"), 100.0);
+        let report = case.run();
+        assert!(!report.passed);
+        assert_eq!(Some(String::from("Unsupported bit depth")), report.error);
+        let note = Case {
+            pitch: Some(PitchCheck {
+                threshold: 1000.0,
+                expected_note: Some(String::from("A4")),
+                expected_shift: None,
+            }),
+            ..Case::new(&eight_bit, None, CheckOptions::default())
+        };
+        assert!(!Manifest { cases: vec![note] }.run().passed);
+    }
+}
@@ -0,0 +1,431 @@
+use clap::ValueEnum;
+use rustfft::FftPlanner;
+use std::f32::consts::PI;
+use std::fs::File;
+use std::io::BufWriter;
+use crate::analysis::{PitchFrame, Voicing};
+use crate::metrics;
+
+/// Windows applied to frames before their FFT
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq)]
+pub enum Window {
+    /// no window
+    Rectangular,
+    /// von Hann (raised cosine) window
+    Hann,
+    /// Hamming window, whose side lobes are lower than Hann's but do not
+    /// fall as fast
+    Hamming,
+    /// Blackman window, whose side lobes are the lowest for a wider main lobe
+    Blackman,
+}
+
+impl Window {
+
+    /// Returns the `len` coefficients of the window
+    pub fn coefficients(&self, len: usize) -> Vec<f32> {
+        let phase = |n: usize| 2.0 * PI * n as f32 / len.max(1) as f32;
+        (0..len)
+            .map(|n| match self {
+                Window::Rectangular => 1.0,
+                Window::Hann => 0.5 - 0.5 * phase(n).cos(),
+                Window::Hamming => 0.54 - 0.46 * phase(n).cos(),
+                Window::Blackman => {
+                    0.42 - 0.5 * phase(n).cos() + 0.08 * (2.0 * phase(n)).cos()
+                },
+            })
+            .collect()
+    }
+}
+
+/// Scales of the frequency axis
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq)]
+pub enum FrequencyScale {
+    /// frequencies equally spaced
+    Linear,
+    /// octaves equally spaced
+    Log,
+    /// mel frequencies equally spaced, close to the perceived pitch
+    Mel,
+}
+
+impl FrequencyScale {
+
+    /// Returns the frequency, in Hz, at `position` from 0 (`min` Hz) to 1
+    /// (`max` Hz) of the axis
+    pub fn frequency(&self, position: f32, min: f32, max: f32) -> f32 {
+        match self {
+            FrequencyScale::Linear => min + position * (max - min),
+            FrequencyScale::Log => min * (max / min).powf(position),
+            FrequencyScale::Mel => {
+                let (low, high) = (metrics::hz_to_mel(min), metrics::hz_to_mel(max));
+                metrics::mel_to_hz(low + position * (high - low))
+            },
+        }
+    }
+}
+
+/// Colormaps of levels, from the lowest to the highest
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq)]
+pub enum Colormap {
+    /// from black to white
+    Gray,
+    /// from dark purple to yellow through blue and green
+    Viridis,
+    /// from black to pale yellow through purple and orange
+    Magma,
+    /// from black to pale yellow through purple, red and orange
+    Inferno,
+}
+
+impl Colormap {
+
+    /// Returns the colors of the colormap, equally spaced from 0 to 1
+    fn anchors(&self) -> &'static [[u8; 3]] {
+        match self {
+            Colormap::Gray => &[[0, 0, 0], [255, 255, 255]],
+            Colormap::Viridis => &[
+                [68, 1, 84], [59, 82, 139], [33, 145, 140], [94, 201, 98], [253, 231, 37]
+            ],
+            Colormap::Magma => &[
+                [0, 0, 4], [81, 18, 124], [183, 55, 121], [252, 137, 97], [252, 253, 191]
+            ],
+            Colormap::Inferno => &[
+                [0, 0, 4], [87, 16, 110], [188, 55, 84], [249, 142, 9], [252, 255, 164]
+            ],
+        }
+    }
+
+    /// Returns the color of `value`, from 0 to 1
+    pub fn color(&self, value: f32) -> [u8; 3] {
+        let anchors = self.anchors();
+        let position = value.clamp(0.0, 1.0) * (anchors.len() - 1) as f32;
+        let i = (position.floor() as usize).min(anchors.len() - 2);
+        let t = position - i as f32;
+        let mut color = [0; 3];
+        for (c, (a, b)) in color.iter_mut().zip(anchors[i].iter().zip(&anchors[i + 1])) {
+            *c = (*a as f32 + t * (*b as f32 - *a as f32)).round() as u8;
+        }
+        color
+    }
+
+    /// Returns a color standing out of the colormap, to draw over it
+    pub fn overlay(&self) -> [u8; 3] {
+        match self {
+            Colormap::Gray => [255, 0, 0],
+            _ => [0, 255, 255],
+        }
+    }
+}
+
+/// Magnitudes of the short-time Fourier transform of a signal
+#[derive(Clone, Debug, PartialEq)]
+pub struct Spectrogram {
+    pub magnitudes: Vec<Vec<f32>>, // positive frequency bins of each frame
+    pub samp_rate: f32,
+    pub fsize: usize,              // samples per frame
+}
+
+impl Spectrogram {
+
+    /// Returns the spectrogram of `signal`, sampled at `samp_rate` Hz, cut
+    /// in frames of `fsize` samples every `hop` samples, as the phase vocoder
+    /// does, weighted by `window`. A signal shorter than a frame is padded
+    /// with silence.
+    pub fn new(
+        signal: &[f32],
+        samp_rate: f32,
+        fsize: usize,
+        hop: usize,
+        window: Window
+    ) -> Spectrogram {
+        let mut padded = signal.to_vec();
+        padded.resize(signal.len().max(fsize), 0.0);
+        let coefficients = window.coefficients(fsize);
+        let mut planner = FftPlanner::new();
+        let magnitudes = crate::frame(&padded, fsize, hop)
+            .iter()
+            .map(|f| {
+                let windowed: Vec<f32> =
+                    f.iter().zip(&coefficients).map(|(s, w)| s * w).collect();
+                let spectrum = crate::apply_fft(&mut planner, &windowed);
+                spectrum[..fsize / 2 + 1].iter().map(|c| c.norm()).collect()
+            })
+            .collect();
+        Spectrogram { magnitudes, samp_rate, fsize }
+    }
+
+    /// Returns the level, in dB relative to the loudest bin, of frame
+    /// `frame` between `low` and `high` Hz: of its loudest bin in the band,
+    /// or interpolated between the bins around it if the band is narrower
+    /// than a bin
+    fn level(&self, frame: usize, low: f32, high: f32, loudest: f32) -> f32 {
+        let bins = &self.magnitudes[frame];
+        let resolution = self.samp_rate / self.fsize as f32;
+        let position = |f: f32| (f / resolution).clamp(0.0, (bins.len() - 1) as f32);
+        let first = position(low).ceil() as usize;
+        let last = position(high).floor() as usize;
+        let magnitude = if first <= last {
+            bins[first..=last].iter().fold(0.0f32, |m, b| m.max(*b))
+        } else {
+            let centre = position((low + high) / 2.0);
+            let i = (centre.floor() as usize).min(bins.len().saturating_sub(2));
+            let t = centre - i as f32;
+            bins[i] * (1.0 - t) + bins.get(i + 1).copied().unwrap_or(bins[i]) * t
+        };
+        20.0 * (magnitude.max(f32::MIN_POSITIVE) / loudest).log10()
+    }
+}
+
+/// How a spectrogram is drawn
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Rendering {
+    pub width: Option<usize>,   // columns, one per frame if None
+    pub height: usize,          // rows
+    pub scale: FrequencyScale,
+    pub min_frequency: f32,     // of the lowest row, in Hz
+    pub max_frequency: f32,     // of the highest row, in Hz
+    pub db_range: f32,          // levels drawn under the loudest bin, in dB
+    pub colormap: Colormap,
+}
+
+/// RGB image, row by row from the top
+#[derive(Clone, Debug, PartialEq)]
+pub struct Image {
+    pub width: usize,
+    pub height: usize,
+    pub pixels: Vec<u8>,
+}
+
+impl Image {
+
+    /// Returns the color of the pixel of column `x` and row `y`
+    pub fn pixel(&self, x: usize, y: usize) -> [u8; 3] {
+        let i = 3 * (y * self.width + x);
+        [self.pixels[i], self.pixels[i + 1], self.pixels[i + 2]]
+    }
+
+    /// Sets the color of the pixel of column `x` and row `y`
+    fn set_pixel(&mut self, x: usize, y: usize, color: [u8; 3]) {
+        let i = 3 * (y * self.width + x);
+        self.pixels[i..i + 3].copy_from_slice(&color);
+    }
+
+    /// Writes the image to the PNG file at `path`
+    pub fn save_png(&self, path: &str) -> Result<(), &'static str> {
+        let file = match File::create(path) {
+            Ok(file) => file,
+            Err(_) => return Err("could not create spectrogram file"),
+        };
+        let (width, height) = (self.width as u32, self.height as u32);
+        let mut encoder = png::Encoder::new(BufWriter::new(file), width, height);
+        encoder.set_color(png::ColorType::Rgb);
+        encoder.set_depth(png::BitDepth::Eight);
+        let written = encoder
+            .write_header()
+            .and_then(|mut writer| writer.write_image_data(&self.pixels));
+        match written {
+            Ok(()) => Ok(()),
+            Err(_) => Err("could not write spectrogram file"),
+        }
+    }
+}
+
+impl Rendering {
+
+    /// Returns the frequency band, in Hz, of row `y` from the top
+    fn band(&self, y: usize) -> (f32, f32) {
+        let (min, max) = (self.min_frequency, self.max_frequency);
+        let frequency = |edge: usize| {
+            self.scale.frequency(1.0 - edge as f32 / self.height as f32, min, max)
+        };
+        (frequency(y + 1), frequency(y))
+    }
+
+    /// Returns the row, from the top, of `frequency` Hz, None if it is out
+    /// of the axis
+    fn row(&self, frequency: f32) -> Option<usize> {
+        if !(self.min_frequency..=self.max_frequency).contains(&frequency) {
+            return None;
+        }
+        (0..self.height).find(|y| self.band(*y).0 <= frequency)
+    }
+
+    /// Returns the image of `spectrogram`, with the frequencies of the frames
+    /// of `track` voiced for `voicing` drawn over it. The frames of the
+    /// track are those of the spectrogram.
+    pub fn render(
+        &self,
+        spectrogram: &Spectrogram,
+        track: Option<(&[PitchFrame], &Voicing)>
+    ) -> Result<Image, &'static str> {
+        let frames = spectrogram.magnitudes.len();
+        let nyquist = spectrogram.samp_rate / 2.0;
+        if self.height == 0 || self.width == Some(0) || frames == 0 {
+            return Err("Illegal size of the spectrogram");
+        }
+        if self.min_frequency < 0.0
+            || self.min_frequency >= self.max_frequency
+            || self.max_frequency > nyquist
+        {
+            return Err("Frequencies must increase up to the Nyquist frequency");
+        }
+        if self.scale == FrequencyScale::Log && self.min_frequency == 0.0 {
+            return Err("A logarithmic axis can not start at 0 Hz");
+        }
+        if self.db_range.is_nan() || self.db_range <= 0.0 {
+            return Err("The range of levels must be positive");
+        }
+
+        let width = self.width.unwrap_or(frames);
+        let column_frame = |x: usize| (x * frames / width).min(frames - 1);
+        let loudest = spectrogram
+            .magnitudes
+            .iter()
+            .flatten()
+            .fold(f32::MIN_POSITIVE, |m, b| m.max(*b));
+        let bands: Vec<(f32, f32)> = (0..self.height).map(|y| self.band(y)).collect();
+        let pixels = vec![0; 3 * width * self.height];
+        let mut image = Image { width, height: self.height, pixels };
+        for x in 0..width {
+            for (y, (low, high)) in bands.iter().enumerate() {
+                let level = spectrogram.level(column_frame(x), *low, *high, loudest);
+                let color = self.colormap.color(1.0 + level / self.db_range);
+                image.set_pixel(x, y, color);
+            }
+        }
+
+        if let Some((track, voicing)) = track {
+            let overlay = self.colormap.overlay();
+            for x in 0..width {
+                let frame = match track.get(column_frame(x)) {
+                    Some(frame) if voicing.is_voiced(frame) => frame,
+                    _ => continue,
+                };
+                if let Some(y) = self.row(frame.frequency) {
+                    // a line of 3 pixels
+                    for y in y.saturating_sub(1)..(y + 2).min(self.height) {
+                        image.set_pixel(x, y, overlay);
+                    }
+                }
+            }
+        }
+        Ok(image)
+    }
+}
+
+#[cfg(test)]
+mod spectrogram_tests {
+    use super::*;
+    use crate::analysis::pitch_track;
+    use crate::generate::Signal;
+
+    fn rendering(scale: FrequencyScale, min_frequency: f32) -> Rendering {
+        Rendering {
+            width: None,
+            height: 200,
+            scale,
+            min_frequency,
+            max_frequency: 4000.0,
+            db_range: 80.0,
+            colormap: Colormap::Gray,
+        }
+    }
+
+    /// Returns the row of the brightest pixel of column `x` of a gray image
+    fn brightest_row(image: &Image, x: usize) -> usize {
+        (0..image.height).max_by_key(|y| image.pixel(x, *y)[0]).unwrap()
+    }
+
+    #[test]
+    fn windows() {
+        let hann = Window::Hann.coefficients(8);
+        assert_eq!(0.0, hann[0]);
+        assert!((hann[4] - 1.0).abs() < 1e-6);
+        assert!((Window::Hamming.coefficients(8)[0] - 0.08).abs() < 1e-6);
+        assert!(Window::Blackman.coefficients(8)[0].abs() < 1e-6);
+        assert_eq!(vec![1.0; 4], Window::Rectangular.coefficients(4));
+    }
+
+    #[test]
+    fn frequency_scales() {
+        for scale in FrequencyScale::value_variants() {
+            assert!((scale.frequency(0.0, 20.0, 4000.0) - 20.0).abs() < 1e-2);
+            assert!((scale.frequency(1.0, 20.0, 4000.0) - 4000.0).abs() < 1e-1);
+        }
+        assert_eq!(2010.0, FrequencyScale::Linear.frequency(0.5, 20.0, 4000.0));
+        // as many octaves below and above the middle
+        assert!((FrequencyScale::Log.frequency(0.5, 100.0, 400.0) - 200.0).abs() < 1e-3);
+        let mel = FrequencyScale::Mel.frequency(0.5, 0.0, 4000.0);
+        assert!(mel > 1000.0 && mel < 2000.0, "{}", mel);
+    }
+
+    #[test]
+    fn colormaps() {
+        assert_eq!([0, 0, 0], Colormap::Gray.color(-1.0));
+        assert_eq!([128, 128, 128], Colormap::Gray.color(0.5));
+        assert_eq!([255, 255, 255], Colormap::Gray.color(2.0));
+        assert_eq!([68, 1, 84], Colormap::Viridis.color(0.0));
+        assert_eq!([253, 231, 37], Colormap::Viridis.color(1.0));
+        assert_eq!([183, 55, 121], Colormap::Magma.color(0.5));
+    }
+
+    #[test]
+    fn tone_is_drawn_at_its_row() {
+        let sine = Signal::Sine { frequency: 1000.0 };
+        let sine = sine.synthesize(8000.0, 1.0, 0.5).unwrap();
+        let spectrogram = Spectrogram::new(&sine, 8000.0, 1024, 256, Window::Hann);
+        assert_eq!(sine.len() / 256 - 3, spectrogram.magnitudes.len());
+        assert_eq!(513, spectrogram.magnitudes[0].len());
+        for (scale, min) in [
+            (FrequencyScale::Linear, 0.0),
+            (FrequencyScale::Log, 50.0),
+            (FrequencyScale::Mel, 0.0),
+        ] {
+            let rendering = rendering(scale, min);
+            let image = rendering.render(&spectrogram, None).unwrap();
+            assert_eq!((spectrogram.magnitudes.len(), 200), (image.width, image.height));
+            let (low, high) = rendering.band(brightest_row(&image, 10));
+            assert!(low <= 1010.0 && high >= 990.0, "{:?}: {} {}", scale, low, high);
+        }
+        let linear = rendering(FrequencyScale::Linear, 0.0);
+        let rendering = Rendering { width: Some(7), ..linear };
+        assert_eq!(7, rendering.render(&spectrogram, None).unwrap().width);
+        let silence = Spectrogram::new(&[0.0; 100], 8000.0, 1024, 256, Window::Hann);
+        assert_eq!(1, silence.magnitudes.len());
+    }
+
+    #[test]
+    fn illegal_renderings() {
+        let spectrogram = Spectrogram::new(&[0.0; 2048], 8000.0, 1024, 256, Window::Hann);
+        let log = rendering(FrequencyScale::Log, 0.0);
+        assert!(log.render(&spectrogram, None).is_err());
+        let linear = rendering(FrequencyScale::Linear, 0.0);
+        let above = Rendering { max_frequency: 5000.0, ..linear };
+        assert!(above.render(&spectrogram, None).is_err());
+        let empty = Rendering { height: 0, ..linear };
+        assert!(empty.render(&spectrogram, None).is_err());
+    }
+
+    #[test]
+    fn pitch_track_overlay() {
+        let sine = Signal::Sine { frequency: 440.0 };
+        let sine = sine.synthesize(8000.0, 1.0, 0.5).unwrap();
+        let spectrogram = Spectrogram::new(&sine, 8000.0, 2048, 512, Window::Hann);
+        let track = pitch_track(&sine, 2048, 512, 8000.0);
+        assert_eq!(track.len(), spectrogram.magnitudes.len());
+        let rendering = rendering(FrequencyScale::Linear, 0.0);
+        let voicing = Voicing::new(1.0);
+        let image = rendering.render(&spectrogram, Some((&track, &voicing))).unwrap();
+        let row = rendering.row(440.0).unwrap();
+        assert_eq!(Colormap::Gray.overlay(), image.pixel(5, row));
+
+        let path = std::env::temp_dir().join("pitch_spectrogram.png");
+        let path = path.to_str().unwrap();
+        image.save_png(path).unwrap();
+        let decoder = png::Decoder::new(File::open(path).unwrap());
+        let reader = decoder.read_info().unwrap();
+        let info = reader.info();
+        assert_eq!((image.width as u32, 200), (info.width, info.height));
+    }
+}
@@ -0,0 +1,153 @@
+use std::fmt::Write;
+use crate::accuracy;
+use crate::analysis::{self, Voicing};
+use crate::batch::{Algorithm, Shift};
+
+/// Pitch and level of a signal transposed by a shift, as in the studies of
+/// `resources/test_results/results.org`
+#[derive(Clone, Debug, PartialEq)]
+pub struct ShiftMeasure {
+    pub algorithm: Algorithm,
+    pub semitones: f32,
+    pub expected: f32,          // frequency expected, in Hz
+    pub measured: Option<f32>,  // median frequency of the voiced frames, None if none
+    pub voiced: f32,            // proportion of voiced frames
+    pub level: f32,             // level of the output, in dBFS
+    pub error: Option<String>,  // why the signal could not be transposed
+}
+
+impl ShiftMeasure {
+
+    /// Returns the deviation, in cents, of the measured pitch from the
+    /// expected one, None if no pitch was measured
+    pub fn cents(&self) -> Option<f32> {
+        self.measured.map(|f| accuracy::cents(f, self.expected))
+    }
+}
+
+/// Returns the median frequency of the frames of `signal`, of amplitudes
+/// between -1 and 1, voiced for the default voicing, and the proportion of
+/// voiced frames
+pub fn measure_pitch(
+    signal: &[f32],
+    samp_rate: f32,
+    fsize: usize,
+    hopa: usize
+) -> (Option<f32>, f32) {
+    if signal.len() < fsize {
+        return (None, 0.0);
+    }
+    let voicing = Voicing::new(1.0);
+    let track = analysis::pitch_track(signal, fsize, hopa, samp_rate);
+    let mut voiced: Vec<f32> = track
+        .iter()
+        .filter(|f| voicing.is_voiced(f))
+        .map(|f| f.frequency)
+        .collect();
+    voiced.sort_by(|a, b| a.total_cmp(b));
+    let proportion = voiced.len() as f32 / track.len().max(1) as f32;
+    (voiced.get(voiced.len() / 2).copied(), proportion)
+}
+
+/// Transposes `tone`, a tone of `frequency` Hz of amplitudes between -1 and
+/// 1, by each of `shifts` with each of `algorithms`, and measures the pitch
+/// and level of the outputs. The phase vocoder and the pitch detector use
+/// frames of `fsize` samples every `hopa` samples.
+pub fn shift_study(
+    tone: &[f32],
+    frequency: f32,
+    samp_rate: f32,
+    algorithms: &[Algorithm],
+    shifts: &[Shift],
+    fsize: usize,
+    hopa: usize
+) -> Vec<ShiftMeasure> {
+    let mut measures = Vec::new();
+    for &algorithm in algorithms {
+        for shift in shifts {
+            let mut measure = ShiftMeasure {
+                algorithm,
+                semitones: shift.semitones(),
+                expected: frequency * shift.ratio(),
+                measured: None,
+                voiced: 0.0,
+                level: f32::NEG_INFINITY,
+                error: None,
+            };
+            match crate::transpose_signal(tone, samp_rate, algorithm, *shift, fsize, hopa) {
+                Ok(output) => {
+                    let (measured, voiced) = measure_pitch(&output, samp_rate, fsize, hopa);
+                    let power = output.iter().map(|s| s * s).sum::<f32>()
+                        / output.len().max(1) as f32;
+                    measure.measured = measured;
+                    measure.voiced = voiced;
+                    measure.level = analysis::to_dbfs(power.sqrt(), 1.0);
+                },
+                Err(why) => measure.error = Some(why.to_string()),
+            }
+            measures.push(measure);
+        }
+    }
+    measures
+}
+
+/// Returns `measures` as an Org mode table, like those of `results.org`.
+/// Pitches that could not be measured are written ND.
+pub fn org_table(measures: &[ShiftMeasure]) -> String {
+    let mut table = String::from(
+        "| algorithm     | shift | Hz expected | Hz measured | cents  | voiced | dBFS   |\n\
+         |---------------+-------+-------------+-------------+--------+--------+--------|\n"
+    );
+    let nd = |value: Option<f32>, precision: usize| {
+        value.map_or(String::from("ND"), |v| format!("{:.*}", precision, v))
+    };
+    for m in measures {
+        // writing to a String can not fail
+        let _ = writeln!(
+            table,
+            "| {:<13} | {:>5} | {:>11.3} | {:>11} | {:>6} | {:>5.0}% | {:>6.1} |",
+            m.algorithm.name(),
+            m.semitones,
+            m.expected,
+            nd(m.measured, 1),
+            nd(m.cents(), 1),
+            100.0 * m.voiced,
+            m.level
+        );
+    }
+    table
+}
+
+#[cfg(test)]
+mod study_tests {
+    use super::*;
+    use crate::generate::Signal;
+
+    #[test]
+    fn pitch_of_tones_and_silence() {
+        let sine = Signal::Sine { frequency: 440.0 };
+        let tone = sine.synthesize(8000.0, 1.0, 0.5).unwrap();
+        let (measured, voiced) = measure_pitch(&tone, 8000.0, 1024, 256);
+        assert!((measured.unwrap() - 440.0).abs() < 2.0, "{:?}", measured);
+        assert_eq!(1.0, voiced);
+        assert_eq!((None, 0.0), measure_pitch(&[0.0; 4000], 8000.0, 1024, 256));
+        assert_eq!((None, 0.0), measure_pitch(&[0.0; 100], 8000.0, 1024, 256));
+    }
+
+    #[test]
+    fn study_table() {
+        let sine = Signal::Sine { frequency: 440.0 };
+        let tone = sine.synthesize(8000.0, 1.0, 0.5).unwrap();
+        let shifts = [Shift::Semitones(0.0), Shift::Semitones(0.5)];
+        let measures =
+            shift_study(&tone, 440.0, 8000.0, &[Algorithm::Basic], &shifts, 1024, 256);
+        assert_eq!(2, measures.len());
+        // the basic transposer leaves the tone as it is without a shift
+        assert!(measures[0].cents().unwrap().abs() < 10.0, "{:?}", measures[0]);
+        assert!((measures[0].level + 9.03).abs() < 0.1, "{:?}", measures[0]);
+        assert!(measures[1].error.is_some());
+        let table = org_table(&measures);
+        assert_eq!(4, table.lines().count());
+        assert!(table.lines().nth(3).unwrap().contains(" ND |"), "{}", table);
+    }
+}
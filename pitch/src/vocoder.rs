@@ -0,0 +1,338 @@
+use realfft::{ComplexToReal, RealFftPlanner, RealToComplex};
+use rustfft::{num_complex::Complex, FftPlanner};
+use std::sync::Arc;
+use crate::analysis::{self, preprocess::Preprocessing};
+use crate::config::ptconfig::{Key, Scale};
+
+/// Processing context of the phase vocoder for frames of `fsize` samples
+/// every `hopa` samples. The forward and inverse real FFTs are planned and
+/// the buffers allocated by `new()`. The buffers depending on the signal grow
+/// up to the longest signal and largest scale factor seen, so that steady
+/// processing does not allocate. So do the buffers of the pitch detection of
+/// `autotune()`, whose FFTs are planned once per length of signal.
+pub struct Vocoder {
+    fsize: usize,
+    hopa: usize,
+    samp_rate: f32,
+    forward: Arc<dyn RealToComplex<f32>>,
+    inverse: Arc<dyn ComplexToReal<f32>>,
+    window: Vec<f32>,             // von Hann window
+    analysis_norm: f32,           // of the window for hopa
+    frame: Vec<f32>,              // windowed frame, then synthesized frame
+    spectrum: Vec<Complex<f32>>,  // the fsize / 2 + 1 bins of the frame
+    scratch: Vec<Complex<f32>>,   // of the FFTs
+    analysis_phases: Vec<f32>,    // of the previous analyzed frame
+    synthesis_phases: Vec<f32>,   // of the previous processed frame
+    stretched: Vec<f32>,          // overlap-add of the synthesized frames
+    planner: FftPlanner<f32>,     // of the FFTs of the pitch detection
+    detected: Vec<f32>,           // signal seen by the pitch detector
+    detection: Vec<Complex<f32>>, // spectrum of a frame or of the signal
+    detection_scratch: Vec<Complex<f32>>, // of the FFTs of the pitch detection
+}
+
+impl Vocoder {
+
+    /// Returns the context of frames of `fsize` samples every `hopa` samples
+    /// of signals sampled at `samp_rate` Hz
+    pub fn new(
+        fsize: usize,
+        hopa: usize,
+        samp_rate: f32
+    ) -> Result<Vocoder, &'static str> {
+        if fsize < 2 || hopa == 0 {
+            return Err("Illegal frame size or shift between frames");
+        }
+        let mut planner = RealFftPlanner::new();
+        let forward = planner.plan_fft_forward(fsize);
+        let inverse = planner.plan_fft_inverse(fsize);
+        let scratch_len = forward.get_scratch_len().max(inverse.get_scratch_len());
+        let bins = fsize / 2 + 1;
+        Ok(Vocoder {
+            fsize,
+            hopa,
+            samp_rate,
+            forward,
+            inverse,
+            window: (0..fsize).map(|i| crate::von_hann(i, fsize)).collect(),
+            analysis_norm: (fsize as f32 / hopa as f32 / 2.0).sqrt(),
+            frame: vec![0.0; fsize],
+            spectrum: vec![Complex::new(0.0, 0.0); bins],
+            scratch: vec![Complex::new(0.0, 0.0); scratch_len],
+            analysis_phases: vec![0.0; bins],
+            synthesis_phases: vec![0.0; bins],
+            stretched: Vec::new(),
+            planner: FftPlanner::new(),
+            detected: Vec::new(),
+            detection: Vec::new(),
+            detection_scratch: Vec::new(),
+        })
+    }
+
+    /// Returns the context in `slot` if it has the given parameters, or
+    /// replaces it by a new one (see `new()`)
+    pub fn reuse(
+        slot: &mut Option<Vocoder>,
+        fsize: usize,
+        hopa: usize,
+        samp_rate: f32
+    ) -> Result<&mut Vocoder, &'static str> {
+        let matches = slot
+            .as_ref()
+            .is_some_and(|v| (v.fsize, v.hopa, v.samp_rate) == (fsize, hopa, samp_rate));
+        if !matches {
+            *slot = Some(Vocoder::new(fsize, hopa, samp_rate)?);
+        }
+        Ok(slot.as_mut().unwrap())
+    }
+
+    /// Forgets the phases of the previous frame, before a new signal
+    pub fn reset(&mut self) {
+        self.analysis_phases.fill(0.0);
+        self.synthesis_phases.fill(0.0);
+    }
+
+    /// Analyzes `frame`, of `fsize` samples, and returns it synthesized with
+    /// its phases adjusted for being placed `hops` samples after the previous
+    /// frame, ready to be overlap-added. The first frame after `reset()`
+    /// keeps its phases.
+    pub fn process_frame(
+        &mut self,
+        frame: &[f32],
+        hops: u32,
+        first: bool
+    ) -> Result<&[f32], &'static str> {
+        if frame.len() != self.fsize || hops == 0 {
+            return Err("Illegal frame size or scale factor");
+        }
+        for ((windowed, sample), coefficient) in
+            self.frame.iter_mut().zip(frame).zip(&self.window)
+        {
+            *windowed = sample * coefficient / self.analysis_norm;
+        }
+        // buffers have the lengths planned, the FFTs can not fail
+        let _ = self.forward.process_with_scratch(
+            &mut self.frame,
+            &mut self.spectrum,
+            &mut self.scratch
+        );
+
+        let hopa = self.hopa as u32;
+        for (k, bin) in self.spectrum.iter_mut().enumerate() {
+            let phase = bin.arg();
+            let freq_dev = crate::frequency_deviation(
+                self.analysis_phases[k],
+                phase,
+                hopa,
+                self.samp_rate,
+                k as u32,
+                self.fsize as u32
+            );
+            let wrap_freq_dev =
+                crate::wrapped_frequency_deviation(freq_dev, hopa, self.samp_rate);
+            let bin_freq =
+                crate::bin_frequency(k as u32, self.samp_rate, self.fsize as u32);
+            let true_freq = crate::true_frequency(wrap_freq_dev, bin_freq);
+            let phi = if first {
+                phase
+            } else {
+                let previous = self.synthesis_phases[k];
+                crate::phase_adjustment(previous, hops, self.samp_rate, true_freq)
+            };
+            self.analysis_phases[k] = phase;
+            self.synthesis_phases[k] = phi.rem_euclid(2.0 * std::f32::consts::PI);
+            *bin = Complex::from_polar(bin.norm(), phi);
+        }
+        // the bins at 0 Hz and at the Nyquist frequency of a real signal
+        self.spectrum[0].im = 0.0;
+        if self.fsize.is_multiple_of(2) {
+            self.spectrum[self.fsize / 2].im = 0.0;
+        }
+        let _ = self.inverse.process_with_scratch(
+            &mut self.spectrum,
+            &mut self.frame,
+            &mut self.scratch
+        );
+
+        // normalization of the inverse FFT and of the window for hops
+        let scale = self.fsize as f32 * (self.fsize as f32 / hops as f32 / 2.0).sqrt();
+        for (sample, coefficient) in self.frame.iter_mut().zip(&self.window) {
+            *sample *= coefficient / scale;
+        }
+        Ok(&self.frame)
+    }
+
+    /// Writes to `output` the transposition of `signal` by `scale_factor`,
+    /// as `phase_vocoder_transposer()` does. `output` is cleared first; its
+    /// capacity and the buffers of the context are reused.
+    pub fn transpose(
+        &mut self,
+        signal: &[f32],
+        scale_factor: f32,
+        output: &mut Vec<f32>
+    ) -> Result<(), &'static str> {
+        let hops = (scale_factor * self.hopa as f32).round();
+        if self.fsize > signal.len() || !hops.is_finite() || hops < 1.0 {
+            return Err("Illegal frame size or scale factor");
+        }
+        let hops = hops as usize;
+        let nb_frames = (signal.len() - self.fsize) / self.hopa + 1;
+        let mut stretched = std::mem::take(&mut self.stretched);
+        stretched.clear();
+        stretched.resize(self.fsize + (nb_frames - 1) * hops, 0.0);
+
+        self.reset();
+        for i in 0..nb_frames {
+            let start = i * self.hopa;
+            let frame = self.process_frame(
+                &signal[start..start + self.fsize],
+                hops as u32,
+                i == 0
+            )?;
+            for (out, sample) in stretched[i * hops..].iter_mut().zip(frame) {
+                *out += sample;
+            }
+        }
+
+        output.clear();
+        crate::sample_audio_into(&stretched, scale_factor, output);
+        self.stretched = stretched;
+        Ok(())
+    }
+
+    /// Returns the scale factor tuning `signal`, as seen by the pitch
+    /// detector through `preprocessing`, to the nearest note of the musical
+    /// scale `scale` in `key`, or 1.0 if it is not voiced for the `voicing`
+    /// threshold (see `is_voiced()` and `closest_scale_factor()`)
+    pub fn autotune(
+        &mut self,
+        signal: &[f32],
+        preprocessing: &Preprocessing,
+        key: Key,
+        scale: Scale,
+        voicing: f32
+    ) -> Result<f32, &'static str> {
+        let mut detected = std::mem::take(&mut self.detected);
+        preprocessing.apply_into(signal, self.samp_rate, &mut detected);
+        let scale_factor = if self.is_voiced(&detected, voicing) {
+            self.closest_scale_factor(&detected, key, scale)
+                .ok_or("could not find nearest note")
+        } else {
+            Ok(1.0)
+        };
+        self.detected = detected;
+        scale_factor
+    }
+
+    /// Returns true if the mean voicing confidence of the frames of `signal`,
+    /// of amplitudes between -1 and 1, reaches `threshold`
+    pub fn is_voiced(&mut self, signal: &[f32], threshold: f32) -> bool {
+        let voicing = analysis::Voicing { threshold, ..analysis::Voicing::new(1.0) };
+        let fft = self.planner.plan_fft_forward(self.fsize);
+        let scratch_len = fft.get_inplace_scratch_len();
+        if self.detection_scratch.len() < scratch_len {
+            self.detection_scratch.resize(scratch_len, Complex::new(0.0, 0.0));
+        }
+        let (mut confidence, mut frames) = (0.0, 0);
+        for (i, frame) in signal.windows(self.fsize).step_by(self.hopa).enumerate() {
+            // as `analyze_frame()` does
+            self.detection.clear();
+            self.detection.extend(frame.iter().zip(&self.window).map(|(s, c)| {
+                Complex::new(s * c / self.analysis_norm, 0.0)
+            }));
+            fft.process_with_scratch(
+                &mut self.detection,
+                &mut self.detection_scratch[..scratch_len]
+            );
+            let (hopa, samp_rate) = (self.hopa, self.samp_rate);
+            let pitch_frame =
+                analysis::pitch_frame(i, frame, &self.detection, hopa, samp_rate);
+            confidence += voicing.confidence(&pitch_frame);
+            frames += 1;
+        }
+        confidence >= threshold * frames as f32
+    }
+
+    /// Returns the scale factor between the main frequency of `signal` and
+    /// the nearest note of the musical scale `scale` in `key`, as
+    /// `get_closest_scale_factor_in()` does
+    pub fn closest_scale_factor(
+        &mut self,
+        signal: &[f32],
+        key: Key,
+        scale: Scale
+    ) -> Option<f32> {
+        let fft = self.planner.plan_fft_forward(signal.len());
+        let scratch_len = fft.get_inplace_scratch_len();
+        if self.detection_scratch.len() < scratch_len {
+            self.detection_scratch.resize(scratch_len, Complex::new(0.0, 0.0));
+        }
+        self.detection.clear();
+        self.detection.extend(signal.iter().map(|&s| Complex::new(s, 0.0)));
+        fft.process_with_scratch(
+            &mut self.detection,
+            &mut self.detection_scratch[..scratch_len]
+        );
+        let freq = crate::get_main_frequency(&self.detection, self.samp_rate);
+        crate::scale_factor_to_nearest(freq, key, scale)
+    }
+}
+
+#[cfg(test)]
+mod vocoder_tests {
+    use super::*;
+    use crate::generate::Signal;
+
+    #[test]
+    fn transposition() {
+        let sine = Signal::Sine { frequency: 440.0 };
+        let tone = sine.synthesize(8000.0, 1.0, 0.5).unwrap();
+        let mut vocoder = Vocoder::new(1024, 256, 8000.0).unwrap();
+        let mut output = Vec::new();
+        for (scale_factor, expected) in [(1.0, 440.0), (1.5, 660.0), (0.5, 220.0)] {
+            vocoder.transpose(&tone, scale_factor, &mut output).unwrap();
+            let reference =
+                crate::phase_vocoder_transposer(&tone, 1024, 256, 8000.0, scale_factor);
+            assert_eq!(reference.len(), output.len());
+            let (measured, voiced) =
+                crate::study::measure_pitch(&output, 8000.0, 1024, 256);
+            assert!((measured.unwrap() - expected).abs() < 2.0, "{:?}", measured);
+            assert_eq!(1.0, voiced);
+
+            // the level of the middle of the signal, away from the edges
+            let rms = |s: &[f32]| {
+                (s.iter().map(|x| x * x).sum::<f32>() / s.len() as f32).sqrt()
+            };
+            let middle = output.len() / 4..3 * output.len() / 4;
+            let level = rms(&output[middle.clone()]);
+            assert!((level - rms(&reference[middle])).abs() < 0.02, "{}", level);
+        }
+    }
+
+    #[test]
+    fn illegal_parameters() {
+        assert!(Vocoder::new(0, 256, 8000.0).is_err());
+        assert!(Vocoder::new(1024, 0, 8000.0).is_err());
+        let mut vocoder = Vocoder::new(1024, 256, 8000.0).unwrap();
+        let mut output = vec![1.0];
+        assert!(vocoder.transpose(&[0.0; 1000], 1.0, &mut output).is_err());
+        assert!(vocoder.transpose(&[0.0; 2000], 0.001, &mut output).is_err());
+        assert!(vocoder.transpose(&[0.0; 2000], f32::NAN, &mut output).is_err());
+        assert!(vocoder.transpose(&[0.0; 2000], f32::INFINITY, &mut output).is_err());
+        assert!(vocoder.process_frame(&[0.0; 1023], 256, true).is_err());
+        assert!(vocoder.process_frame(&[0.0; 1025], 256, true).is_err());
+        assert!(vocoder.process_frame(&[0.0; 1024], 0, true).is_err());
+        vocoder.transpose(&[0.0; 2000], 1.0, &mut output).unwrap();
+        assert!(output.iter().all(|s| *s == 0.0));
+    }
+
+    #[test]
+    fn reuse_matching_contexts() {
+        let mut slot = None;
+        Vocoder::reuse(&mut slot, 1024, 256, 8000.0).unwrap().stretched = vec![1.0];
+        let vocoder = Vocoder::reuse(&mut slot, 1024, 256, 8000.0).unwrap();
+        assert_eq!(vec![1.0], vocoder.stretched);
+        let vocoder = Vocoder::reuse(&mut slot, 512, 256, 8000.0).unwrap();
+        assert!(vocoder.stretched.is_empty());
+        assert!(Vocoder::reuse(&mut slot, 512, 0, 8000.0).is_err());
+    }
+}
@@ -0,0 +1,151 @@
+//! Checks that the processing context of the phase vocoder, and the real-time
+//! transposition running in it, do not allocate once they are set up, by
+//! counting the allocations of the testing thread.
+use pitch::config::ptconfig::{Key, PreprocessingOptions, RealTimeSubCommand, Scale};
+use pitch::generate::Signal;
+use pitch::vocoder::Vocoder;
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::cell::Cell;
+
+struct CountingAllocator;
+
+thread_local! {
+    // allocations of the current thread, counted while it is Some
+    static ALLOCATIONS: Cell<Option<usize>> = const { Cell::new(None) };
+}
+
+fn count() {
+    // the thread local may be gone while the thread exits
+    let _ = ALLOCATIONS.try_with(|c| c.set(c.get().map(|n| n + 1)));
+}
+
+unsafe impl GlobalAlloc for CountingAllocator {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        count();
+        System.alloc(layout)
+    }
+
+    unsafe fn alloc_zeroed(&self, layout: Layout) -> *mut u8 {
+        count();
+        System.alloc_zeroed(layout)
+    }
+
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        count();
+        System.realloc(ptr, layout, new_size)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        System.dealloc(ptr, layout)
+    }
+}
+
+#[global_allocator]
+static ALLOCATOR: CountingAllocator = CountingAllocator;
+
+/// Returns the number of allocations made by `f` on the current thread
+fn allocations<F: FnOnce()>(f: F) -> usize {
+    ALLOCATIONS.with(|c| c.set(Some(0)));
+    f();
+    ALLOCATIONS.with(|c| c.replace(None)).unwrap()
+}
+
+#[test]
+fn steady_state_does_not_allocate() {
+    let tone = Signal::Sine { frequency: 440.0 }.synthesize(8000.0, 1.0, 0.5).unwrap();
+    let mut vocoder = Vocoder::new(1024, 256, 8000.0).unwrap();
+    let mut output = Vec::new();
+
+    // the buffers depending on the signal and the scale factor grow up to
+    // the largest ones, then are reused
+    let scale_factors = [1.5, 1.0, 2f32.powf(5.0 / 12.0), 0.5, 2.0];
+    assert!(allocations(|| vocoder.transpose(&tone, 1.5, &mut output).unwrap()) > 0);
+    for scale_factor in scale_factors {
+        vocoder.transpose(&tone, scale_factor, &mut output).unwrap();
+    }
+    for scale_factor in scale_factors.into_iter().rev() {
+        let n =
+            allocations(|| vocoder.transpose(&tone, scale_factor, &mut output).unwrap());
+        assert_eq!(0, n, "{} allocations at scale factor {}", n, scale_factor);
+    }
+
+    // frame by frame, as a stream
+    let frame = &tone[..1024];
+    vocoder.reset();
+    let n = allocations(|| {
+        for i in 0..100 {
+            vocoder.process_frame(frame, 256, i == 0).unwrap();
+        }
+    });
+    assert_eq!(0, n);
+    assert!(allocations(|| drop(vec![0.0f32; 16])) > 0);
+}
+
+#[test]
+fn real_time_clips_do_not_allocate() {
+    let tone = Signal::Sine { frequency: 440.0 }.synthesize(8000.0, 1.0, 0.5).unwrap();
+    let config = RealTimeSubCommand::PhaseVocoder {
+        fsize: 1024,
+        hopa: 256,
+        shift: Some(1.5),
+        key: Key::C,
+        scale: Scale::Chromatic,
+        retune: 1.0,
+        voicing: 0.0,
+        preprocessing: PreprocessingOptions::default(),
+    };
+    let mut vocoder = None;
+    let mut output = Vec::new();
+    let mut transpose = |clip: &[f32]| {
+        let (vocoder, output) = (&mut vocoder, &mut output);
+        pitch::transpose_pitch_real_time_with(vocoder, &config, 8000.0, clip, output)
+            .unwrap()
+    };
+
+    // the context and the output buffer are set up by the longest clip
+    assert!(allocations(|| transpose(&tone)) > 0);
+    for clip in [&tone[..], &tone[..4000], &tone[2000..]] {
+        let n = allocations(|| transpose(clip));
+        assert_eq!(0, n, "{} allocations for a clip of {} samples", n, clip.len());
+    }
+}
+
+#[test]
+fn autotuned_clips_do_not_allocate() {
+    let tone = Signal::Sine { frequency: 452.0 }.synthesize(8000.0, 1.0, 0.5).unwrap();
+    let noise = Signal::WhiteNoise { seed: 1 }.synthesize(8000.0, 1.0, 0.01).unwrap();
+    let config = RealTimeSubCommand::PhaseVocoder {
+        fsize: 1024,
+        hopa: 256,
+        shift: None,
+        key: Key::C,
+        scale: Scale::Major,
+        retune: 1.0,
+        voicing: 0.5,
+        preprocessing: PreprocessingOptions {
+            high_pass: Some(80.0),
+            gate: Some(-60.0),
+            pre_emphasis: Some(0.97),
+            ..PreprocessingOptions::default()
+        },
+    };
+    let mut vocoder = None;
+    let mut output = Vec::new();
+    let mut transpose = |clip: &[f32]| {
+        let (vocoder, output) = (&mut vocoder, &mut output);
+        pitch::transpose_pitch_real_time_with(vocoder, &config, 8000.0, clip, output)
+            .unwrap()
+    };
+
+    // the context and the output buffer are set up by the longest clip, and
+    // the FFT of the pitch detection is planned once per clip length
+    let clips = [&tone[..], &noise[..], &tone[..4000], &tone[2000..]];
+    assert!(allocations(|| transpose(&tone)) > 0);
+    for clip in clips {
+        transpose(clip);
+    }
+    for clip in clips {
+        let n = allocations(|| transpose(clip));
+        assert_eq!(0, n, "{} allocations for a clip of {} samples", n, clip.len());
+    }
+}
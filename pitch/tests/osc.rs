@@ -0,0 +1,110 @@
+use pitch::config::ptconfig::{Key, PreprocessingOptions, RealTimeSubCommand, Scale};
+use pitch::control::osc::{encode, OscArg, OscMessage, OscServer};
+use pitch::control::params::LiveParameters;
+use std::net::UdpSocket;
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// Starts an OSC server on a free local port and returns the parameters it
+/// updates along with a UDP client connected to it
+fn start_server(config: &RealTimeSubCommand) -> (Arc<LiveParameters>, UdpSocket) {
+    let params = Arc::new(LiveParameters::new(config));
+    let server = OscServer::bind("127.0.0.1:0").unwrap();
+    let addr = server.local_addr().unwrap();
+    server.spawn(params.clone());
+    let client = UdpSocket::bind("127.0.0.1:0").unwrap();
+    client.connect(addr).unwrap();
+    (params, client)
+}
+
+/// Sends a message to the server
+fn send(client: &UdpSocket, address: &str, args: Vec<OscArg>) {
+    let message = OscMessage { address: String::from(address), args };
+    client.send(&encode(&message)).unwrap();
+}
+
+/// Waits until `cond` holds on the parameters, fails after one second
+fn wait_until(params: &LiveParameters, cond: impl Fn(&LiveParameters) -> bool) {
+    let start = Instant::now();
+    while !cond(params) {
+        assert!(start.elapsed() < Duration::from_secs(1), "parameters not updated");
+        thread::sleep(Duration::from_millis(5));
+    }
+}
+
+fn phase_vocoder() -> RealTimeSubCommand {
+    RealTimeSubCommand::PhaseVocoder {
+        fsize: 1024,
+        hopa: 256,
+        shift: Some(1.5),
+        key: Key::C,
+        scale: Scale::Chromatic,
+        retune: 1.0,
+        voicing: 0.5,
+        preprocessing: PreprocessingOptions {
+            high_pass: Some(80.0),
+            ..Default::default()
+        },
+    }
+}
+
+#[test]
+fn osc_messages_update_phase_vocoder_parameters() {
+    let (params, client) = start_server(&phase_vocoder());
+    send(&client, "/pitch/fsize", vec![OscArg::Int(2048)]);
+    send(&client, "/pitch/hopa", vec![OscArg::Float(512.0)]);
+    send(&client, "/pitch/key", vec![OscArg::Str(String::from("F#"))]);
+    send(&client, "/pitch/scale", vec![OscArg::Str(String::from("minor"))]);
+    send(&client, "/pitch/retune", vec![OscArg::Float(0.5)]);
+    send(&client, "/pitch/voicing", vec![OscArg::Float(0.25)]);
+    send(&client, "/pitch/autotune", vec![]);
+    wait_until(&params, |p| match p.snapshot() {
+        RealTimeSubCommand::PhaseVocoder { shift, .. } => shift.is_none(),
+        _ => false,
+    });
+    match params.snapshot() {
+        RealTimeSubCommand::PhaseVocoder {
+            fsize, hopa, shift, key, scale, retune, voicing, preprocessing
+        } => {
+            assert_eq!(2048, fsize);
+            assert_eq!(512, hopa);
+            assert_eq!(None, shift);
+            assert_eq!(Key::FSharp, key);
+            assert_eq!(Scale::Minor, scale);
+            assert_eq!(0.5, retune);
+            assert_eq!(0.25, voicing);
+            assert_eq!(Some(80.0), preprocessing.high_pass);
+        },
+        _ => panic!("algorithm changed"),
+    }
+    send(&client, "/pitch/shift", vec![OscArg::Float(2.0)]);
+    wait_until(&params, |p| match p.snapshot() {
+        RealTimeSubCommand::PhaseVocoder { shift, .. } => shift == Some(2.0),
+        _ => false,
+    });
+}
+
+#[test]
+fn osc_bypass_toggles() {
+    let (params, client) = start_server(&phase_vocoder());
+    assert!(!params.bypass());
+    send(&client, "/pitch/bypass", vec![OscArg::Bool(true)]);
+    wait_until(&params, |p| p.bypass());
+    send(&client, "/pitch/bypass", vec![OscArg::Int(0)]);
+    wait_until(&params, |p| !p.bypass());
+}
+
+#[test]
+fn invalid_osc_messages_are_ignored() {
+    let (params, client) = start_server(&RealTimeSubCommand::Basic { shift: 3 });
+    send(&client, "/pitch/autotune", vec![]);
+    send(&client, "/pitch/unknown", vec![OscArg::Int(1)]);
+    send(&client, "/pitch/retune", vec![OscArg::Float(4.0)]);
+    client.send(b"garbage").unwrap();
+    send(&client, "/pitch/shift", vec![OscArg::Int(-5)]);
+    wait_until(&params, |p| match p.snapshot() {
+        RealTimeSubCommand::Basic { shift } => shift == -5,
+        _ => false,
+    });
+}
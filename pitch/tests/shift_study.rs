@@ -0,0 +1,55 @@
+//! Automated version of the studies of `resources/test_results/results.org`:
+//! a 440 Hz tone is shifted by every semitone from -12 to +12 with each
+//! algorithm, and the pitch and level of the outputs are measured. The tables
+//! are printed with `cargo test --test shift_study -- --nocapture`.
+use pitch::batch::{Algorithm, Shift};
+use pitch::generate::Signal;
+use pitch::study::{self, ShiftMeasure};
+
+const FREQUENCY: f32 = 440.0;
+const FSIZE: usize = 1024;
+const HOPA: usize = 256;
+const TOLERANCE: f32 = 10.0; // maximum deviation of the pitch, in cents
+
+/// Runs the study at `samp_rate` with `algorithm` and prints its table
+fn study(samp_rate: f32, algorithm: Algorithm) -> Vec<ShiftMeasure> {
+    let tone = Signal::Sine { frequency: FREQUENCY }
+        .synthesize(samp_rate, 1.0, 0.5)
+        .unwrap();
+    let shifts: Vec<Shift> = (-12..=12).map(|s| Shift::Semitones(s as f32)).collect();
+    let measures =
+        study::shift_study(&tone, FREQUENCY, samp_rate, &[algorithm], &shifts, FSIZE, HOPA);
+    println!("{} sps\n{}", samp_rate, study::org_table(&measures));
+    measures
+}
+
+/// Checks that the pitch of `measure` is within the tolerance, on most frames,
+/// and that the output has a finite level
+fn assert_accurate(measure: &ShiftMeasure) {
+    let cents = measure.cents();
+    assert!(
+        cents.is_some_and(|c| c.abs() < TOLERANCE),
+        "{:?} off by {:?} cents",
+        measure,
+        cents
+    );
+    assert!(measure.voiced > 0.9, "{:?}", measure);
+    assert!(measure.level.is_finite() && measure.level > -20.0, "{:?}", measure);
+}
+
+#[test]
+fn phase_vocoder_shifts() {
+    for samp_rate in [8000.0, 44100.0] {
+        let measures = study(samp_rate, Algorithm::PhaseVocoder);
+        assert_eq!(25, measures.len());
+        measures.iter().for_each(assert_accurate);
+    }
+}
+
+#[test]
+fn basic_transposer_shifts() {
+    let measures = study(44100.0, Algorithm::Basic);
+    assert_eq!(25, measures.len());
+    assert!(measures.iter().all(|m| m.error.is_none()));
+    measures.iter().for_each(assert_accurate);
+}